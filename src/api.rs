@@ -0,0 +1,464 @@
+use crate::config::{SharedConfig, StatusPageConfig, SystemConfig};
+use crate::config_files;
+use crate::events::{EventFilter, MonitorEvent};
+use crate::export::{self, ExportFormat, ExportKind, TimestampZone};
+use crate::heartbeat::Heartbeat;
+use crate::history;
+use crate::metrics;
+use crate::monitor::{self, MonitorManager};
+use crate::status_page;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::convert::Infallible;
+use subtle::ConstantTimeEq;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::RwLock;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+const OPENAPI_SPEC: &str = include_str!("openapi.json");
+
+#[derive(Clone)]
+pub struct ApiState {
+    manager: Arc<RwLock<MonitorManager>>,
+    // Shared with the GUI, which saves the same file
+    config: SharedConfig,
+    config_path: String,
+    bearer_token: Option<String>,
+    status_page: StatusPageConfig,
+}
+
+impl ApiState {
+    pub async fn new(manager: Arc<RwLock<MonitorManager>>, config: SharedConfig, config_path: &str) -> Self {
+        let (bearer_token, status_page) = {
+            let config = config.lock().await;
+            (config.api.bearer_token.clone(), config.status_page.clone())
+        };
+        Self { manager, config, config_path: config_path.to_string(), bearer_token, status_page }
+    }
+
+    async fn persist(&self) -> Result<(), ApiError> {
+        monitor::save_config(&self.config, &self.manager, &self.config_path).await.map_err(ApiError::internal)
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn not_found(id: Uuid) -> Self {
+        Self { status: StatusCode::NOT_FOUND, message: format!("System {} not found", id) }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::BAD_REQUEST, message: message.into() }
+    }
+
+    fn conflict(error: anyhow::Error) -> Self {
+        Self { status: StatusCode::CONFLICT, message: error.to_string() }
+    }
+
+    fn forbidden(message: impl Into<String>) -> Self {
+        Self { status: StatusCode::FORBIDDEN, message: message.into() }
+    }
+
+    fn internal(error: anyhow::Error) -> Self {
+        Self { status: StatusCode::INTERNAL_SERVER_ERROR, message: error.to_string() }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "error": self.message }));
+        (self.status, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessToken {
+    access_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    kind: Option<String>,
+    format: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    timezone: Option<String>,
+}
+
+pub fn router(state: ApiState) -> Router {
+    let protected = Router::new()
+        .route("/systems", get(list_systems).post(create_system))
+        .route("/systems/:id", get(get_system).put(update_system).delete(delete_system))
+        .route("/systems/:id/enable", post(enable_system))
+        .route("/systems/:id/disable", post(disable_system))
+        .route("/systems/:id/history", get(get_history))
+        .route("/systems/:id/incidents", get(get_incidents))
+        .route("/systems/:id/latency", get(get_latency))
+        .route("/systems/:id/export", get(export_system))
+        .route("/systems/:id/trace", get(get_trace).post(start_trace))
+        .route("/scheduler", get(get_scheduler))
+        .route("/events", get(event_stream))
+        .route("/events/ws", get(event_socket))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+
+    // Cron jobs report in without the API token; the heartbeat token in the URL identifies them
+    let api = Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/heartbeat/:token", get(record_heartbeat).post(record_heartbeat))
+        .merge(protected);
+
+    // Prometheus expects metrics at the root; scrapers can send the bearer token
+    let metrics = Router::new()
+        .route("/metrics", get(prometheus_metrics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+
+    let mut router = Router::new().nest("/api/v1", api).merge(metrics);
+    // The status page is meant for people without API access, so it sits outside the token check
+    if state.status_page.enabled {
+        router = router.route("/status", get(status_page));
+    }
+    router.with_state(state)
+}
+
+pub async fn serve(bind_address: String, state: ApiState) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(&bind_address).await?;
+    log::info!("API listening on http://{}", bind_address);
+    axum::serve(listener, router(state)).await?;
+    Ok(())
+}
+
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    let Some(expected) = &state.bearer_token else {
+        return next.run(request).await;
+    };
+
+    let header_token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Browsers can't set headers on EventSource or WebSocket connections, so also accept ?access_token=
+    let query_token = Query::<AccessToken>::try_from_uri(request.uri()).ok().and_then(|query| query.0.access_token);
+    // Compared in constant time so the response time doesn't give the token away byte by byte
+    let authorized = header_token.or(query_token.as_deref())
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(expected.as_bytes())));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        let error = ApiError { status: StatusCode::UNAUTHORIZED, message: "Missing or invalid bearer token".to_string() };
+        let mut response = error.into_response();
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+        response
+    }
+}
+
+async fn openapi() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI_SPEC)
+}
+
+async fn status_page(State(state): State<ApiState>) -> Html<String> {
+    let snapshot = status_page::snapshot(&state.status_page, &*state.manager.read().await);
+    Html(status_page::render(&state.status_page, &snapshot))
+}
+
+async fn list_systems(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.manager.read().await.get_systems())
+}
+
+async fn get_system(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    let system = state.manager.read().await.get_system(id).ok_or_else(|| ApiError::not_found(id))?;
+    Ok(Json(system))
+}
+
+// Parameters can come from the query string, a JSON body, or both
+async fn record_heartbeat(
+    State(state): State<ApiState>,
+    Path(token): Path<String>,
+    Query(query): Query<Heartbeat>,
+    body: Option<Json<Heartbeat>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let heartbeat = match body {
+        Some(Json(body)) => query.merge(body),
+        None => query,
+    };
+    let id = state.manager.read().await.record_heartbeat(&token, heartbeat).await
+        .map_err(|e| ApiError { status: StatusCode::NOT_FOUND, message: e.to_string() })?;
+    Ok(Json(serde_json::json!({ "system_id": id })))
+}
+
+fn validate(config: &SystemConfig) -> Result<(), ApiError> {
+    if config.name.trim().is_empty() {
+        return Err(ApiError::bad_request("name must not be empty"));
+    }
+    if config.host.trim().is_empty() {
+        return Err(ApiError::bad_request("host must not be empty"));
+    }
+    // Anyone reaching the API could otherwise run commands as the monitor's user
+    if config.runs_commands() {
+        return Err(ApiError::forbidden("Systems that run commands can only be added in the configuration file"));
+    }
+    Ok(())
+}
+
+// Settings from the template the system `extends`, which the config file would add on its next
+// load; validating without them would let a template slip in e.g. a restart command
+async fn resolve_template(state: &ApiState, config: &SystemConfig) -> Result<SystemConfig, ApiError> {
+    let templates = state.config.lock().await.templates.clone();
+    config_files::resolve_template(config, &templates).map_err(|e| ApiError::bad_request(e.to_string()))
+}
+
+async fn create_system(
+    State(state): State<ApiState>,
+    Json(config): Json<SystemConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = resolve_template(&state, &config).await?;
+    validate(&config)?;
+    let system = {
+        let mut manager = state.manager.write().await;
+        manager.validate_probe(&config, None).map_err(|e| ApiError::bad_request(e.to_string()))?;
+        let id = manager.add_system(config).await.map_err(ApiError::internal)?;
+        manager.get_system(id).ok_or_else(|| ApiError::not_found(id))?
+    };
+    state.persist().await?;
+    Ok((StatusCode::CREATED, Json(system)))
+}
+
+async fn update_system(
+    State(state): State<ApiState>,
+    Path(id): Path<Uuid>,
+    Json(config): Json<SystemConfig>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = resolve_template(&state, &config).await?;
+    validate(&config)?;
+    let system = {
+        let mut manager = state.manager.write().await;
+        if manager.get_system(id).is_none() {
+            return Err(ApiError::not_found(id));
+        }
+        manager.ensure_editable(id).map_err(ApiError::conflict)?;
+        manager.validate_probe(&config, Some(id)).map_err(|e| ApiError::bad_request(e.to_string()))?;
+        manager.update_system(id, config).await.map_err(ApiError::internal)?;
+        manager.get_system(id).ok_or_else(|| ApiError::not_found(id))?
+    };
+    state.persist().await?;
+    Ok(Json(system))
+}
+
+async fn delete_system(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    {
+        let mut manager = state.manager.write().await;
+        if manager.get_system(id).is_none() {
+            return Err(ApiError::not_found(id));
+        }
+        manager.ensure_editable(id).map_err(ApiError::conflict)?;
+        manager.remove_system(id);
+    }
+    state.persist().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_enabled(state: ApiState, id: Uuid, enabled: bool) -> Result<Json<crate::monitor::SystemStatus>, ApiError> {
+    let system = {
+        let mut manager = state.manager.write().await;
+        if manager.get_system(id).is_none() {
+            return Err(ApiError::not_found(id));
+        }
+        manager.ensure_editable(id).map_err(ApiError::conflict)?;
+        manager.set_enabled(id, enabled).map_err(|_| ApiError::not_found(id))?;
+        manager.get_system(id).ok_or_else(|| ApiError::not_found(id))?
+    };
+    state.persist().await?;
+    Ok(Json(system))
+}
+
+async fn enable_system(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    set_enabled(state, id, true).await
+}
+
+async fn disable_system(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    set_enabled(state, id, false).await
+}
+
+async fn get_history(
+    State(state): State<ApiState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let history = state.manager.read().await.get_history(id, query.limit).ok_or_else(|| ApiError::not_found(id))?;
+    Ok(Json(history))
+}
+
+async fn get_incidents(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    let incidents = state.manager.read().await.get_incidents(id).ok_or_else(|| ApiError::not_found(id))?;
+    Ok(Json(incidents))
+}
+
+async fn get_scheduler(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.manager.read().await.scheduler_stats())
+}
+
+async fn get_latency(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    let latency = state.manager.read().await.get_latency(id).ok_or_else(|| ApiError::not_found(id))?;
+    Ok(Json(latency))
+}
+
+async fn prometheus_metrics(State(state): State<ApiState>) -> impl IntoResponse {
+    let body = metrics::render(&*state.manager.read().await);
+    ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], body)
+}
+
+async fn export_system(
+    State(state): State<ApiState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    fn parse<T: std::str::FromStr<Err = anyhow::Error>>(value: Option<&str>, default: T) -> Result<T, ApiError> {
+        value.map_or(Ok(default), |v| v.parse().map_err(|e: anyhow::Error| ApiError::bad_request(e.to_string())))
+    }
+    let kind = parse(query.kind.as_deref(), ExportKind::Checks)?;
+    let format = parse(query.format.as_deref(), ExportFormat::Csv)?;
+    let zone = parse(query.timezone.as_deref(), TimestampZone::Utc)?;
+    let from = query.from.as_deref().map(|v| zone.parse(v)).transpose().map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let to = query.to.as_deref().map(|v| zone.parse_end(v)).transpose().map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let checks = state.manager.read().await
+        .load_checks(id, from, to).await
+        .map_err(ApiError::internal)?
+        .ok_or_else(|| ApiError::not_found(id))?;
+    let body = match kind {
+        ExportKind::Checks => export::export_checks(&checks, format, zone),
+        ExportKind::Incidents => export::export_incidents(&history::incidents_from_checks(&checks), format, zone),
+        ExportKind::Latency => export::export_latency(&checks, format, zone),
+    }.map_err(ApiError::internal)?;
+
+    Ok(([(header::CONTENT_TYPE, format.content_type())], body))
+}
+
+async fn get_trace(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    let manager = state.manager.read().await;
+    if manager.get_system(id).is_none() {
+        return Err(ApiError::not_found(id));
+    }
+    Ok(Json(manager.get_trace(id).unwrap_or_default()))
+}
+
+async fn start_trace(State(state): State<ApiState>, Path(id): Path<Uuid>) -> Result<impl IntoResponse, ApiError> {
+    let started = state.manager.read().await.start_trace(id).map_err(|_| ApiError::not_found(id))?;
+    let status = if started { StatusCode::ACCEPTED } else { StatusCode::CONFLICT };
+    Ok((status, Json(serde_json::json!({ "started": started }))))
+}
+
+async fn event_stream(
+    State(state): State<ApiState>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let receiver = state.manager.read().await.subscribe();
+    let stream = BroadcastStream::new(receiver).filter_map(move |event| {
+        let event = match event {
+            Ok(event) if filter.matches(&event) => event,
+            Ok(_) => return None,
+            // The client fell behind; tell it so it can re-fetch the full state
+            Err(_) => return Some(Ok(Event::default().event("lagged").data("{}"))),
+        };
+        let data = serde_json::to_string(&event).ok()?;
+        Some(Ok(Event::default().event(event.event_type()).data(data)))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn event_socket(
+    State(state): State<ApiState>,
+    Query(filter): Query<EventFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let receiver = state.manager.read().await.subscribe();
+    upgrade.on_upgrade(move |socket| forward_events(socket, receiver, filter))
+}
+
+async fn forward_events(
+    mut socket: WebSocket,
+    mut receiver: tokio::sync::broadcast::Receiver<MonitorEvent>,
+    filter: EventFilter,
+) {
+    loop {
+        let message = tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) if filter.matches(&event) => match serde_json::to_string(&event) {
+                    Ok(text) => Message::Text(text),
+                    Err(_) => continue,
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => Message::Text(r#"{"type":"lagged"}"#.to_string()),
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Protocol};
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_reject_systems_running_commands() {
+        let manager = Arc::new(RwLock::new(MonitorManager::new()));
+        let mut file = Config::default();
+        file.templates.insert("restartable".to_string(), toml::from_str("probe = { restart_command = [\"sh\", \"-c\", \"id\"] }").unwrap());
+        let config: SharedConfig = Arc::new(Mutex::new(file));
+        let path = std::env::temp_dir().join(format!("monitor-api-{}.toml", Uuid::new_v4()));
+        let state = ApiState::new(Arc::clone(&manager), config, &path.to_string_lossy()).await;
+
+        let mut exec = SystemConfig::new("backup".to_string(), "localhost".to_string(), None, Protocol::Exec);
+        exec.probe = Some(toml::from_str("command = \"rm -rf /\"").unwrap());
+        let error = create_system(State(state.clone()), Json(exec)).await.err().unwrap();
+        assert_eq!(error.status, StatusCode::FORBIDDEN);
+
+        let mut process = SystemConfig::new("db".to_string(), "localhost".to_string(), None, Protocol::Process);
+        process.probe = Some(toml::from_str("name = \"postgres\"\nrestart_command = [\"sh\", \"-c\", \"id\"]").unwrap());
+        let error = create_system(State(state.clone()), Json(process)).await.err().unwrap();
+        assert_eq!(error.status, StatusCode::FORBIDDEN);
+        // Nor through a template, which the next load would apply
+        let mut templated = SystemConfig::new("cache".to_string(), "localhost".to_string(), None, Protocol::Process);
+        templated.probe = Some(toml::from_str("name = \"redis\"").unwrap());
+        templated.extends = Some("restartable".to_string());
+        let error = create_system(State(state.clone()), Json(templated)).await.err().unwrap();
+        assert_eq!(error.status, StatusCode::FORBIDDEN);
+
+        let id = manager.write().await
+            .add_system(SystemConfig::new("web".to_string(), "192.0.2.1".to_string(), None, Protocol::Ping)).await.unwrap();
+        let exec = SystemConfig::new("web".to_string(), "localhost".to_string(), None, Protocol::Exec);
+        let error = update_system(State(state), Path(id), Json(exec)).await.err().unwrap();
+        assert_eq!(error.status, StatusCode::FORBIDDEN);
+        assert_eq!(manager.read().await.get_system(id).unwrap().config.protocol, Protocol::Ping);
+        assert!(!path.exists());
+    }
+}
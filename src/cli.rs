@@ -0,0 +1,320 @@
+use crate::config::{Config, Protocol};
+use crate::discovery::{self, SweepOptions};
+use crate::export::{self, ExportFormat, ExportKind, TimestampZone};
+use crate::history::{self, HistoryStore};
+use crate::inventory::{self, FieldMapping, ImportFormat, ImportOptions, RowStatus};
+use crate::monitor::MonitorManager;
+use crate::sla::{self, ReportFormat};
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "system-monitor", about = "Monitor a list of systems for connectivity issues")]
+pub struct Cli {
+    /// Configuration file to use
+    #[arg(long, default_value = "monitor_config.toml")]
+    pub config: String,
+
+    /// Runs the GUI when no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Export check history or incidents for a system
+    Export(ExportArgs),
+    /// Report achieved availability against the configured SLAs
+    SlaReport(SlaReportArgs),
+    /// Sweep a network for hosts and optionally add them as systems
+    Discover(DiscoverArgs),
+    /// Add systems from a CSV or JSON file, a hosts file or nmap XML output
+    Import(ImportArgs),
+    /// Write the configured systems to CSV or JSON
+    ExportSystems(ExportSystemsArgs),
+    /// Write the JSON Schema of the configuration file, for editors to validate it with
+    ConfigSchema(ConfigSchemaArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Name of the system as it appears in the configuration
+    #[arg(long)]
+    pub system: String,
+    /// What to export: checks, incidents or latency (hourly percentiles)
+    #[arg(long, default_value = "checks")]
+    pub kind: ExportKind,
+    /// Output format: csv, json or jsonl
+    #[arg(long, default_value = "csv")]
+    pub format: ExportFormat,
+    /// Start of the range (RFC 3339 or YYYY-MM-DD)
+    #[arg(long)]
+    pub from: Option<String>,
+    /// End of the range (RFC 3339 or YYYY-MM-DD, a date includes that whole day)
+    #[arg(long)]
+    pub to: Option<String>,
+    /// Write timestamps in utc or local time; also used for dates given without an offset
+    #[arg(long, default_value = "utc")]
+    pub timezone: TimestampZone,
+    /// Output file, standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct SlaReportArgs {
+    /// Name of the SLA, every SLA when omitted
+    #[arg(long)]
+    pub sla: Option<String>,
+    /// Period to report on: YYYY-MM, YYYY-Qn or YYYY; the last complete period when omitted
+    #[arg(long)]
+    pub period: Option<String>,
+    /// Output format: markdown, html or json
+    #[arg(long, default_value = "markdown")]
+    pub format: ReportFormat,
+    /// Output file, standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiscoverArgs {
+    /// Network to sweep, e.g. 192.168.1.0/24
+    #[arg(long)]
+    pub network: String,
+    /// TCP ports to try, comma separated; a host counts as found when one accepts a connection
+    #[arg(long, value_delimiter = ',', default_value = "22,80,443")]
+    pub ports: Vec<u16>,
+    /// Don't ping the addresses, only try the ports
+    #[arg(long)]
+    pub no_ping: bool,
+    /// Don't look up the names of the hosts found
+    #[arg(long)]
+    pub no_reverse_dns: bool,
+    /// Addresses probed at the same time
+    #[arg(long, default_value_t = 64)]
+    pub concurrency: usize,
+    /// Time to wait for each ping or connection
+    #[arg(long, default_value_t = 1000)]
+    pub timeout_ms: u64,
+    /// Add the hosts that aren't monitored yet to the configuration
+    #[arg(long)]
+    pub add: bool,
+    /// Group of the added systems
+    #[arg(long)]
+    pub group: Option<String>,
+    /// Protocol the added systems are checked with; TCP uses the first open port
+    #[arg(long, default_value = "ping")]
+    pub protocol: Protocol,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// File to import
+    #[arg(long)]
+    pub file: PathBuf,
+    /// csv, json, hosts or nmap; guessed from the file name when omitted
+    #[arg(long)]
+    pub format: Option<ImportFormat>,
+    /// Column or key a field is read from, e.g. --map host=ip_address; may be repeated
+    #[arg(long)]
+    pub map: Vec<String>,
+    /// Protocol of rows that don't give one
+    #[arg(long, default_value = "ping")]
+    pub protocol: Protocol,
+    /// Group of rows that don't give one
+    #[arg(long)]
+    pub group: Option<String>,
+    /// Only show what would be imported
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportSystemsArgs {
+    /// Output format: csv, json or jsonl
+    #[arg(long, default_value = "csv")]
+    pub format: ExportFormat,
+    /// Output file, standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigSchemaArgs {
+    /// Output file, standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+pub async fn run(command: Command, config: &Config, config_path: &str) -> Result<()> {
+    match command {
+        Command::Export(args) => run_export(args, config).await,
+        Command::SlaReport(args) => run_sla_report(args, config).await,
+        Command::Discover(args) => run_discover(args, config, config_path).await,
+        Command::Import(args) => run_import(args, config, config_path).await,
+        Command::ExportSystems(args) => run_export_systems(args, config).await,
+        Command::ConfigSchema(args) => run_config_schema(args).await,
+    }
+}
+
+async fn run_export(args: ExportArgs, config: &Config) -> Result<()> {
+    let system = config.find_system(&args.system)
+        .ok_or_else(|| anyhow::anyhow!("No system named '{}' in the configuration", args.system))?;
+    if !config.history.enabled {
+        anyhow::bail!("History is disabled in the configuration, there is nothing to export");
+    }
+
+    let from = args.from.as_deref().map(|t| args.timezone.parse(t)).transpose()?;
+    let to = args.to.as_deref().map(|t| args.timezone.parse_end(t)).transpose()?;
+    let store = HistoryStore::new(&config.history.directory, config.history.retention_days);
+    let checks = store.load(&system.storage_key(), from, to).await?;
+
+    let output = match args.kind {
+        ExportKind::Checks => export::export_checks(&checks, args.format, args.timezone)?,
+        ExportKind::Incidents => {
+            let incidents = history::incidents_from_checks(&checks);
+            export::export_incidents(&incidents, args.format, args.timezone)?
+        }
+        ExportKind::Latency => export::export_latency(&checks, args.format, args.timezone)?,
+    };
+
+    match args.output {
+        Some(path) => {
+            tokio::fs::write(&path, output).await?;
+            eprintln!("Exported {} checks for {} to {}", checks.len(), system.name, path.display());
+        }
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+async fn run_sla_report(args: SlaReportArgs, config: &Config) -> Result<()> {
+    let reports = sla::generate(config, args.sla.as_deref(), args.period.as_deref()).await?;
+    let output = sla::render(&reports, args.format)?;
+
+    match args.output {
+        Some(path) => {
+            tokio::fs::write(&path, output).await?;
+            eprintln!("Wrote {} SLA report(s) to {}", reports.len(), path.display());
+        }
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+async fn run_discover(args: DiscoverArgs, config: &Config, config_path: &str) -> Result<()> {
+    let options = SweepOptions {
+        icmp: !args.no_ping,
+        ports: args.ports,
+        concurrency: args.concurrency,
+        timeout: std::time::Duration::from_millis(args.timeout_ms),
+        reverse_dns: !args.no_reverse_dns,
+    };
+    eprintln!("Sweeping {} addresses of {}", discovery::parse_cidr(&args.network)?.len(), args.network);
+    let hosts = discovery::sweep(&args.network, &options, None).await?;
+
+    println!("{:<40} {:<40} {:<5} {:<16} {:>9}", "ADDRESS", "HOSTNAME", "PING", "PORTS", "RTT");
+    for host in &hosts {
+        let ports: Vec<String> = host.open_ports.iter().map(|p| p.to_string()).collect();
+        let monitored = if host.is_monitored(&config.systems) { " (monitored)" } else { "" };
+        println!(
+            "{:<40} {:<40} {:<5} {:<16} {:>9}{}",
+            host.address,
+            host.hostname.as_deref().unwrap_or("-"),
+            if host.answers_ping { "yes" } else { "no" },
+            if ports.is_empty() { "-".to_string() } else { ports.join(",") },
+            host.response_time_ms.map_or("-".to_string(), |ms| format!("{:.1}ms", ms)),
+            monitored,
+        );
+    }
+    eprintln!("Found {} host(s)", hosts.len());
+
+    if args.add {
+        let mut updated = config.clone();
+        let new_hosts: Vec<_> = hosts.iter().filter(|host| !host.is_monitored(&config.systems)).collect();
+        for host in &new_hosts {
+            updated.add_system(host.to_system(args.protocol.clone(), args.group.clone()));
+        }
+        updated.save_to_file(config_path).await?;
+        eprintln!("Added {} system(s) to {}", new_hosts.len(), config_path);
+    }
+    Ok(())
+}
+
+async fn run_import(args: ImportArgs, config: &Config, config_path: &str) -> Result<()> {
+    let mut mapping = FieldMapping::default();
+    for spec in &args.map {
+        mapping.set(spec)?;
+    }
+    let options = ImportOptions {
+        format: args.format.unwrap_or_else(|| ImportFormat::detect(&args.file)),
+        mapping,
+        protocol: args.protocol,
+        group: args.group,
+    };
+    let content = tokio::fs::read_to_string(&args.file).await?;
+    // Rows the monitor couldn't check are shown as invalid rather than written to the config
+    let manager = MonitorManager::new();
+    let preview = inventory::preview(&content, &options, &config.systems, &|system| manager.validate_probe(system, None))?;
+
+    println!("{:>5}  {:<10} {:<30} {:<30} {:<10} NOTE", "ROW", "STATUS", "NAME", "HOST", "PROTOCOL");
+    for row in &preview.rows {
+        let (status, note) = match &row.status {
+            RowStatus::New => ("new", String::new()),
+            RowStatus::Duplicate { of } => ("duplicate", format!("same as {}", of)),
+            RowStatus::Invalid { reason } => ("invalid", reason.clone()),
+        };
+        let system = row.system.as_ref();
+        let host = system.map_or("-".to_string(), |s| match s.port {
+            Some(port) => format!("{}:{}", s.host, port),
+            None => s.host.clone(),
+        });
+        println!(
+            "{:>5}  {:<10} {:<30} {:<30} {:<10} {}",
+            row.row,
+            status,
+            system.map_or("-", |s| s.name.as_str()),
+            host,
+            system.map_or("-".to_string(), |s| s.protocol.to_string()),
+            note,
+        );
+    }
+    let (new, duplicates, invalid) = preview.counts();
+    eprintln!("{} new, {} duplicate, {} invalid", new, duplicates, invalid);
+
+    if !args.dry_run && new > 0 {
+        let mut updated = config.clone();
+        for system in preview.new_systems() {
+            updated.add_system(system);
+        }
+        updated.save_to_file(config_path).await?;
+        eprintln!("Added {} system(s) to {}", new, config_path);
+    }
+    Ok(())
+}
+
+async fn run_export_systems(args: ExportSystemsArgs, config: &Config) -> Result<()> {
+    let output = inventory::export_systems(&config.systems, args.format)?;
+    match args.output {
+        Some(path) => {
+            tokio::fs::write(&path, output).await?;
+            eprintln!("Exported {} systems to {}", config.systems.len(), path.display());
+        }
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+pub async fn run_config_schema(args: ConfigSchemaArgs) -> Result<()> {
+    let schema = Config::json_schema()?;
+    match args.output {
+        Some(path) => {
+            tokio::fs::write(&path, schema).await?;
+            eprintln!("Wrote the configuration schema to {}", path.display());
+        }
+        None => print!("{}", schema),
+    }
+    Ok(())
+}
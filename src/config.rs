@@ -1,37 +1,443 @@
+use chrono::{DateTime, NaiveTime, Utc, Weekday};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use crate::config_files::{self, ConfigSource, SystemOrigin};
+use crate::health::SystemState;
+use crate::migrations::CONFIG_VERSION;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
+use tokio::sync::Mutex;
 use anyhow::Result;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// The one configuration the GUI and the API both change and save, see `monitor::save_config`
+pub type SharedConfig = Arc<Mutex<Config>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
+    // Layout of the file, see migrations.rs; files without one are from before versioning
+    #[serde(default)]
+    pub version: u32,
     pub systems: Vec<SystemConfig>,
     pub check_interval_seconds: u64,
     pub timeout_seconds: u64,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub status_page: StatusPageConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub state: StateConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub heartbeat: HeartbeatConfig,
+    #[serde(default)]
+    pub local_network: LocalNetworkConfig,
+    // Thresholds for systems without a `thresholds` section of their own; unset, such systems
+    // are never degraded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_thresholds: Option<ThresholdSettings>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slas: Vec<SlaConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceWindow>,
+    // Further files with systems and templates, relative to this one; "conf.d/*.toml" works
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    // Shared settings systems pick up with `extends = "name"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(with = "BTreeMap<String, BTreeMap<String, serde_json::Value>>")]
+    pub templates: BTreeMap<String, toml::Table>,
+    // What the files looked like, so saving keeps includes, templates and `${VAR}` references
+    #[serde(skip)]
+    pub source: Option<ConfigSource>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SystemConfig {
     pub name: String,
     pub host: String,
     pub port: Option<u16>,
     pub protocol: Protocol,
     pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceSettings>,
+    #[serde(default, skip_serializing_if = "AddressFamily::is_auto")]
+    pub address_family: AddressFamily,
+    // DNS server to resolve `host` with instead of the system resolver, e.g. "10.0.0.53:53"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_server: Option<String>,
+    // Request path for HTTP(S) checks, "/" when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thresholds: Option<ThresholdSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<ScheduleSettings>,
+    // Settings for the protocol's probe, e.g. `timeout_ms`; see probe.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<BTreeMap<String, serde_json::Value>>")]
+    pub probe: Option<toml::Table>,
+    // Name of the system this one is reached through, e.g. its gateway. While the parent is
+    // down, failures of this system are put down to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    // Template this system's settings default to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    // The file entry the system was loaded from; None for systems added since
+    #[serde(skip)]
+    pub origin: Option<SystemOrigin>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum TraceMethod {
+    Icmp,
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct TraceSettings {
+    // Defaults to the method matching the system's protocol
+    pub method: Option<TraceMethod>,
+    pub max_hops: u8,
+    pub probes_per_hop: u8,
+    pub probe_timeout_ms: u64,
+    // Trace automatically when the system goes down
+    pub on_outage: bool,
+    // Trace on a schedule to detect route changes; disabled when unset
+    pub interval_seconds: Option<u64>,
+    // Treat a hop as unchanged when any of its responders was seen there before, so
+    // load-balanced (ECMP) hops don't look like route changes
+    pub ecmp_tolerant: bool,
+    // Number of differing hops tolerated before a route change is reported
+    pub max_changed_hops: u8,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum Protocol {
     Ping,
     Tcp,
     Udp,
+    Http,
+    Https,
+    // Runs a command and reads its Nagios plugin style exit code and output
+    Exec,
+    // Passive: the client reports in, see heartbeat.rs
+    Heartbeat,
+    // CPU, memory, disk or load of the machine the monitor runs on
+    Local,
+    // A process running on the machine the monitor runs on
+    Process,
+    // A network interface of the machine the monitor runs on
+    Interface,
+    // Any other name, checked by the probe registered under it
+    #[serde(untagged)]
+    Custom(String),
+}
+
+// Limits that put an online system into the degraded state, evaluated over the last
+// `window` checks. Unset limits are not checked.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ThresholdSettings {
+    pub window: usize,
+    // Latency is compared as this percentile of the window, or the average when unset
+    pub percentile: Option<f64>,
+    pub warning_latency_ms: Option<f64>,
+    pub critical_latency_ms: Option<f64>,
+    pub warning_loss_percent: Option<f64>,
+    pub critical_loss_percent: Option<f64>,
+    pub warning_jitter_ms: Option<f64>,
+    pub critical_jitter_ms: Option<f64>,
+}
+
+impl Default for ThresholdSettings {
+    fn default() -> Self {
+        Self {
+            window: 5,
+            percentile: None,
+            warning_latency_ms: None,
+            critical_latency_ms: None,
+            warning_loss_percent: None,
+            critical_loss_percent: None,
+            warning_jitter_ms: None,
+            critical_jitter_ms: None,
+        }
+    }
+}
+
+// How often a system is checked depending on its state. Unset intervals fall back to
+// `interval_seconds`, which falls back to the global `check_interval_seconds`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ScheduleSettings {
+    pub interval_seconds: Option<u64>,
+    pub interval_when_down: Option<u64>,
+    pub interval_when_degraded: Option<u64>,
+    // Every further failed check multiplies the down interval by this, so long outages
+    // are polled less often
+    pub backoff_multiplier: Option<f64>,
+    // Upper limit for the backed-off interval, the normal interval when unset
+    pub max_interval_seconds: Option<u64>,
+}
+
+// Which of the host's resolved addresses are checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum AddressFamily {
+    // Whatever the resolver returns first
+    #[default]
+    Auto,
+    V4,
+    V6,
+    // Every resolved address of both families, each checked separately
+    Both,
+    PreferV6,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    // When set, every API request must carry `Authorization: Bearer <token>`
+    pub bearer_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct StatusPageConfig {
+    // Serve the page at /status on the API server
+    pub enabled: bool,
+    pub title: String,
+    // When set, index.html is rewritten here every `refresh_seconds`
+    pub output_directory: Option<String>,
+    pub refresh_seconds: u64,
+    // Systems shown on the page; when empty every system is shown under its own name
+    pub systems: Vec<StatusPageSystem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StatusPageSystem {
+    // Internal name, matching `SystemConfig::name`
+    pub name: String,
+    pub display_name: Option<String>,
+    // Public group the system is listed under; internal groups are never shown
+    pub group: Option<String>,
+}
+
+// Availability target for the systems listed by name and every system in the listed groups
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SlaConfig {
+    pub name: String,
+    pub target_percent: f64,
+    // Period a report covers when none is asked for: the last complete one
+    #[serde(default)]
+    pub period: SlaPeriod,
+    // Only time within business hours counts; around the clock when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub business_hours: Option<BusinessHours>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub systems: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum SlaPeriod {
+    #[default]
+    Month,
+    Quarter,
+    Year,
+}
+
+// Days and local times during which an SLA applies, e.g. Mon-Fri 09:00-17:00
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct BusinessHours {
+    pub days: Vec<Weekday>,
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+// Planned work; downtime inside a window doesn't count against SLAs. Applies to every
+// system when neither `systems` nor `groups` is given.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceWindow {
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub systems: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Keep every check on disk so history survives restarts and can be exported
+    pub enabled: bool,
+    pub directory: String,
+    pub retention_days: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct StateConfig {
+    // Save counters, states and open incidents so they carry over to the next launch
+    pub enabled: bool,
+    // Relative to the directory of the configuration file
+    pub path: String,
+    pub save_interval_seconds: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    // Also accept heartbeats as UDP datagrams on this address, e.g. "0.0.0.0:8125"
+    pub udp_bind_address: Option<String>,
+}
+
+// Built-in "Local" group watching this machine's own network: the interfaces carrying a
+// default route, the default gateways from /proc/net/route and the DNS resolvers
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct LocalNetworkConfig {
+    pub enabled: bool,
+    // Watched besides the interfaces with a default route
+    pub interfaces: Vec<String>,
+    pub gateways: bool,
+    pub resolvers: bool,
+    pub resolv_conf: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    // Checks running at the same time across all systems
+    pub max_concurrent_checks: usize,
+    // Further limits by protocol name ("ping", "tcp", "udp", "http", "https")
+    pub protocol_limits: BTreeMap<String, usize>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             systems: Vec::new(),
             check_interval_seconds: 30,
             timeout_seconds: 5,
+            api: ApiConfig::default(),
+            status_page: StatusPageConfig::default(),
+            history: HistoryConfig::default(),
+            state: StateConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            local_network: LocalNetworkConfig::default(),
+            default_thresholds: None,
+            slas: Vec::new(),
+            maintenance: Vec::new(),
+            include: Vec::new(),
+            templates: BTreeMap::new(),
+            source: None,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "127.0.0.1:8080".to_string(),
+            bearer_token: None,
+        }
+    }
+}
+
+impl Default for StatusPageConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            title: "Service Status".to_string(),
+            output_directory: None,
+            refresh_seconds: 60,
+            systems: Vec::new(),
+        }
+    }
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            method: None,
+            max_hops: 30,
+            probes_per_hop: 3,
+            probe_timeout_ms: 1000,
+            on_outage: true,
+            interval_seconds: None,
+            ecmp_tolerant: true,
+            max_changed_hops: 0,
+        }
+    }
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "monitor_state.json".to_string(),
+            save_interval_seconds: 60,
+        }
+    }
+}
+
+impl Default for LocalNetworkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interfaces: Vec::new(),
+            gateways: true,
+            resolvers: true,
+            resolv_conf: "/etc/resolv.conf".to_string(),
+        }
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_checks: 256,
+            // Every ping is a separate process
+            protocol_limits: BTreeMap::from([("ping".to_string(), 32)]),
+        }
+    }
+}
+
+impl Default for BusinessHours {
+    fn default() -> Self {
+        Self {
+            days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            start: NaiveTime::from_hms_opt(9, 0, 0).unwrap_or_default(),
+            end: NaiveTime::from_hms_opt(17, 0, 0).unwrap_or_default(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: "history".to_string(),
+            retention_days: 90,
         }
     }
 }
@@ -49,14 +455,24 @@ impl Config {
     }
 
     async fn load_from_file(path: &str) -> Result<Self> {
-        let content = fs::read_to_string(path).await?;
-        let config: Config = toml::from_str(&content)?;
+        let (table, mut source) = config_files::load(Path::new(path)).await?;
+        let mut config: Config = toml::Value::Table(table).try_into()?;
+        source.set_loaded(toml::Table::try_from(&config)?);
+        for (index, system) in config.systems.iter_mut().enumerate() {
+            system.origin = Some(source.origin(index));
+        }
+        config.source = Some(source);
         log::info!("Loaded configuration with {} systems", config.systems.len());
         Ok(config)
     }
 
     pub async fn save_to_file(&self, path: &str) -> Result<()> {
-        let content = toml::to_string_pretty(self)?;
+        let mut table = toml::Table::try_from(self)?;
+        if let Some(source) = &self.source {
+            let origins: Vec<Option<SystemOrigin>> = self.systems.iter().map(|system| system.origin).collect();
+            table = source.restore(table, &origins);
+        }
+        let content = toml::to_string_pretty(&table)?;
         fs::write(path, content).await?;
         log::info!("Configuration saved to {}", path);
         Ok(())
@@ -64,6 +480,7 @@ impl Config {
 
     fn create_default_config() -> Self {
         Config {
+            version: CONFIG_VERSION,
             systems: vec![
                 SystemConfig {
                     name: "Google DNS".to_string(),
@@ -71,6 +488,17 @@ impl Config {
                     port: None,
                     protocol: Protocol::Ping,
                     enabled: true,
+                    group: None,
+                    trace: None,
+                    address_family: AddressFamily::Auto,
+                    dns_server: None,
+                    path: None,
+                    thresholds: None,
+                    schedule: None,
+                    probe: None,
+                    parent: None,
+                    extends: None,
+                    origin: None,
                 },
                 SystemConfig {
                     name: "Cloudflare DNS".to_string(),
@@ -78,6 +506,17 @@ impl Config {
                     port: None,
                     protocol: Protocol::Ping,
                     enabled: true,
+                    group: None,
+                    trace: None,
+                    address_family: AddressFamily::Auto,
+                    dns_server: None,
+                    path: None,
+                    thresholds: None,
+                    schedule: None,
+                    probe: None,
+                    parent: None,
+                    extends: None,
+                    origin: None,
                 },
                 SystemConfig {
                     name: "Local HTTP".to_string(),
@@ -85,10 +524,34 @@ impl Config {
                     port: Some(80),
                     protocol: Protocol::Tcp,
                     enabled: false,
+                    group: None,
+                    trace: None,
+                    address_family: AddressFamily::Auto,
+                    dns_server: None,
+                    path: None,
+                    thresholds: None,
+                    schedule: None,
+                    probe: None,
+                    parent: None,
+                    extends: None,
+                    origin: None,
                 },
             ],
             check_interval_seconds: 30,
             timeout_seconds: 5,
+            api: ApiConfig::default(),
+            status_page: StatusPageConfig::default(),
+            history: HistoryConfig::default(),
+            state: StateConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            local_network: LocalNetworkConfig::default(),
+            default_thresholds: None,
+            slas: Vec::new(),
+            maintenance: Vec::new(),
+            include: Vec::new(),
+            templates: BTreeMap::new(),
+            source: None,
         }
     }
 
@@ -107,6 +570,16 @@ impl Config {
             self.systems[index] = system;
         }
     }
+
+    pub fn find_system(&self, name: &str) -> Option<&SystemConfig> {
+        self.systems.iter().find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    // JSON Schema of the configuration file, for editors to validate and complete it
+    pub fn json_schema() -> Result<String> {
+        let schema = schemars::schema_for!(Config);
+        Ok(serde_json::to_string_pretty(&schema)? + "\n")
+    }
 }
 
 impl SystemConfig {
@@ -117,7 +590,219 @@ impl SystemConfig {
             port,
             protocol,
             enabled: true,
+            group: None,
+            trace: None,
+            address_family: AddressFamily::Auto,
+            dns_server: None,
+            path: None,
+            thresholds: None,
+            schedule: None,
+            probe: None,
+            parent: None,
+            extends: None,
+            origin: None,
+        }
+    }
+
+    pub fn trace_settings(&self) -> TraceSettings {
+        self.trace.clone().unwrap_or_default()
+    }
+
+    // The system's own thresholds, else the configured defaults. Heartbeats only use their
+    // own: their "latency" is how long the job ran.
+    pub fn threshold_settings(&self, default: Option<&ThresholdSettings>) -> ThresholdSettings {
+        let default = default.filter(|_| self.protocol != Protocol::Heartbeat);
+        self.thresholds.clone().or_else(|| default.cloned()).unwrap_or_default()
+    }
+
+    // Delay before the next check, given the current state and how many checks in a row failed
+    pub fn next_interval(&self, state: SystemState, consecutive_failures: u32, default: Duration) -> Duration {
+        let schedule = self.schedule.clone().unwrap_or_default();
+        let normal = schedule.interval_seconds.map_or(default, interval_of).max(MIN_INTERVAL);
+        match state {
+            SystemState::Up => normal,
+            SystemState::Degraded => schedule.interval_when_degraded.map_or(normal, interval_of),
+            SystemState::Down => {
+                let Some(down) = schedule.interval_when_down.map(interval_of) else {
+                    return normal;
+                };
+                let Some(multiplier) = schedule.backoff_multiplier.filter(|m| *m > 1.0) else {
+                    return down;
+                };
+                let limit = schedule.max_interval_seconds.map_or(normal, interval_of).max(down);
+                let exponent = consecutive_failures.saturating_sub(1).min(64) as i32;
+                Duration::try_from_secs_f64(down.as_secs_f64() * multiplier.powi(exponent))
+                    .map_or(limit, |interval| interval.min(limit))
+            }
+        }
+    }
+
+    pub fn trace_method(&self) -> TraceMethod {
+        self.trace.as_ref().and_then(|t| t.method).unwrap_or(match self.protocol {
+            Protocol::Ping => TraceMethod::Icmp,
+            Protocol::Tcp | Protocol::Http | Protocol::Https => TraceMethod::Tcp,
+            Protocol::Udp => TraceMethod::Udp,
+            Protocol::Exec | Protocol::Heartbeat | Protocol::Local | Protocol::Process | Protocol::Interface
+            | Protocol::Custom(_) => TraceMethod::Icmp,
+        })
+    }
+
+    // Configured port, or the usual one for the protocol
+    pub fn effective_port(&self) -> Option<u16> {
+        self.port.or(match self.protocol {
+            Protocol::Ping => None,
+            Protocol::Tcp | Protocol::Http => Some(80),
+            Protocol::Udp => Some(53),
+            Protocol::Https => Some(443),
+            Protocol::Exec | Protocol::Heartbeat | Protocol::Local | Protocol::Process | Protocol::Interface
+            | Protocol::Custom(_) => None,
+        })
+    }
+
+    // Exec systems and processes with a `restart_command` run commands on this machine
+    pub fn runs_commands(&self) -> bool {
+        let restarts = self.probe.as_ref()
+            .and_then(|probe| probe.get("restart_command"))
+            .and_then(toml::Value::as_array)
+            .is_some_and(|command| !command.is_empty());
+        self.protocol == Protocol::Exec || (self.protocol == Protocol::Process && restarts)
+    }
+
+    // File-system friendly identifier derived from what is being checked
+    pub fn storage_key(&self) -> String {
+        key_of(&format!("{}-{}-{}-{}", self.name, self.host, self.port.unwrap_or(0), self.protocol))
+    }
+
+    // Like `storage_key` without the name, so it survives a rename
+    pub fn target_key(&self) -> String {
+        key_of(&format!("{}-{}-{}", self.host, self.port.unwrap_or(0), self.protocol))
+    }
+}
+
+// Configured intervals of 0 would check (or trace) in a busy loop
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn interval_of(seconds: u64) -> Duration {
+    Duration::from_secs(seconds).max(MIN_INTERVAL)
+}
+
+fn key_of(raw: &str) -> String {
+    let mut key = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' {
+            key.push(c.to_ascii_lowercase());
+        } else if !key.ends_with('-') {
+            key.push('-');
+        }
+    }
+    key.trim_matches('-').to_string()
+}
+
+impl StateConfig {
+    pub fn resolve_path(&self, config_path: &str) -> PathBuf {
+        Path::new(config_path).parent().unwrap_or(Path::new("")).join(&self.path)
+    }
+}
+
+impl SlaConfig {
+    pub fn covers(&self, system: &SystemConfig) -> bool {
+        listed(&self.systems, &self.groups, system)
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn applies_to(&self, system: &SystemConfig) -> bool {
+        (self.systems.is_empty() && self.groups.is_empty()) || listed(&self.systems, &self.groups, system)
+    }
+}
+
+fn listed(systems: &[String], groups: &[String], system: &SystemConfig) -> bool {
+    systems.iter().any(|name| name.eq_ignore_ascii_case(&system.name))
+        || system.group.as_ref().is_some_and(|group| groups.iter().any(|g| g.eq_ignore_ascii_case(group)))
+}
+
+impl std::fmt::Display for BusinessHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let days: Vec<String> = self.days.iter().map(|d| d.to_string()).collect();
+        write!(f, "{} {}-{}", days.join(","), self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+impl AddressFamily {
+    pub fn is_auto(&self) -> bool {
+        *self == AddressFamily::Auto
+    }
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressFamily::Auto => write!(f, "Auto"),
+            AddressFamily::V4 => write!(f, "IPv4"),
+            AddressFamily::V6 => write!(f, "IPv6"),
+            AddressFamily::Both => write!(f, "Dual-stack"),
+            AddressFamily::PreferV6 => write!(f, "Prefer IPv6"),
+        }
+    }
+}
+
+impl std::fmt::Display for TraceMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceMethod::Icmp => write!(f, "ICMP"),
+            TraceMethod::Udp => write!(f, "UDP"),
+            TraceMethod::Tcp => write!(f, "TCP"),
+        }
+    }
+}
+
+impl Protocol {
+    pub const BUILTIN: [Protocol; 10] = [
+        Protocol::Ping, Protocol::Tcp, Protocol::Udp, Protocol::Http, Protocol::Https, Protocol::Exec,
+        Protocol::Heartbeat, Protocol::Local, Protocol::Process, Protocol::Interface,
+    ];
+
+    // Key of the protocol's probe in the registry
+    pub fn name(&self) -> String {
+        self.to_string().to_ascii_lowercase()
+    }
+}
+
+// Case-insensitive; names that aren't built in are custom probes
+impl std::str::FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim();
+        if name.is_empty() {
+            anyhow::bail!("Empty protocol name");
         }
+        Ok(Protocol::BUILTIN.into_iter()
+            .find(|protocol| protocol.name().eq_ignore_ascii_case(name))
+            .unwrap_or_else(|| Protocol::Custom(name.to_string())))
+    }
+}
+
+// Built-in names in any case, e.g. "tcp", are the built-in protocol rather than a custom one
+impl<'de> Deserialize<'de> for Protocol {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// A built-in protocol, or any other name for a custom probe; the derive would describe
+// `Custom` as a table since it doesn't know about untagged variants
+impl JsonSchema for Protocol {
+    fn schema_name() -> String {
+        "Protocol".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let builtin: Vec<serde_json::Value> = Protocol::BUILTIN.iter()
+            .filter_map(|protocol| serde_json::to_value(protocol).ok())
+            .collect();
+        let schema = serde_json::json!({ "anyOf": [{ "enum": builtin }, { "type": "string" }] });
+        serde_json::from_value(schema).unwrap_or(schemars::schema::Schema::Bool(true))
     }
 }
 
@@ -127,6 +812,77 @@ impl std::fmt::Display for Protocol {
             Protocol::Ping => write!(f, "PING"),
             Protocol::Tcp => write!(f, "TCP"),
             Protocol::Udp => write!(f, "UDP"),
+            Protocol::Http => write!(f, "HTTP"),
+            Protocol::Https => write!(f, "HTTPS"),
+            Protocol::Exec => write!(f, "EXEC"),
+            Protocol::Heartbeat => write!(f, "HEARTBEAT"),
+            Protocol::Local => write!(f, "LOCAL"),
+            Protocol::Process => write!(f, "PROCESS"),
+            Protocol::Interface => write!(f, "INTERFACE"),
+            Protocol::Custom(name) => write!(f, "{}", name),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_interval() {
+        let mut system = SystemConfig::new("web".to_string(), "192.0.2.1".to_string(), None, Protocol::Ping);
+        let default = Duration::from_secs(30);
+        assert_eq!(system.next_interval(SystemState::Down, 3, default), default);
+
+        system.schedule = Some(ScheduleSettings {
+            interval_when_down: Some(5),
+            interval_when_degraded: Some(10),
+            backoff_multiplier: Some(2.0),
+            max_interval_seconds: Some(60),
+            ..ScheduleSettings::default()
+        });
+        assert_eq!(system.next_interval(SystemState::Up, 0, default), default);
+        assert_eq!(system.next_interval(SystemState::Degraded, 0, default), Duration::from_secs(10));
+        assert_eq!(system.next_interval(SystemState::Down, 1, default), Duration::from_secs(5));
+        assert_eq!(system.next_interval(SystemState::Down, 3, default), Duration::from_secs(20));
+        assert_eq!(system.next_interval(SystemState::Down, 50, default), Duration::from_secs(60));
+        // Zero intervals are raised to the minimum
+        system.schedule = Some(ScheduleSettings { interval_seconds: Some(0), interval_when_down: Some(0), ..ScheduleSettings::default() });
+        assert_eq!(system.next_interval(SystemState::Up, 0, default), MIN_INTERVAL);
+        assert_eq!(system.next_interval(SystemState::Down, 1, default), MIN_INTERVAL);
+    }
+
+    #[test]
+    fn test_threshold_settings() {
+        let mut system = SystemConfig::new("web".to_string(), "192.0.2.1".to_string(), None, Protocol::Ping);
+        // No latency limits unless configured
+        let none = system.threshold_settings(None);
+        assert_eq!((none.warning_latency_ms, none.critical_latency_ms), (None, None));
+
+        let defaults = ThresholdSettings { warning_latency_ms: Some(200.0), ..ThresholdSettings::default() };
+        assert_eq!(system.threshold_settings(Some(&defaults)).warning_latency_ms, Some(200.0));
+        system.thresholds = Some(ThresholdSettings { warning_latency_ms: Some(50.0), ..ThresholdSettings::default() });
+        assert_eq!(system.threshold_settings(Some(&defaults)).warning_latency_ms, Some(50.0));
+
+        let heartbeat = SystemConfig::new("job".to_string(), "job".to_string(), None, Protocol::Heartbeat);
+        assert_eq!(heartbeat.threshold_settings(Some(&defaults)).warning_latency_ms, None);
+    }
+
+    #[test]
+    fn test_protocol_names() {
+        let system: SystemConfig = toml::from_str("name = \"db\"\nhost = \"10.0.0.5\"\nprotocol = \"tcp\"\nenabled = true").unwrap();
+        assert_eq!(system.protocol, Protocol::Tcp);
+        assert_eq!(serde_json::from_str::<Protocol>("\"HTTPS\"").unwrap(), Protocol::Https);
+        assert_eq!(serde_json::from_str::<Protocol>("\"amqp\"").unwrap(), Protocol::Custom("amqp".to_string()));
+        assert_eq!(toml::to_string(&system).unwrap().lines().find(|l| l.starts_with("protocol")), Some("protocol = \"Tcp\""));
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&Config::json_schema().unwrap()).unwrap();
+        assert!(schema["properties"].get("version").is_some());
+        let protocol = &schema["definitions"]["Protocol"];
+        assert_eq!(protocol["anyOf"][0]["enum"][0], "Ping");
+        assert_eq!(protocol["anyOf"][1]["type"], "string");
+    }
+}
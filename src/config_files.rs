@@ -0,0 +1,509 @@
+use crate::config::SystemConfig;
+use crate::migrations;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+// Includes may include further files, up to this depth
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+// Shell commands, where `$` belongs to the shell: `${VAR}` is expanded when the command runs
+const VERBATIM_KEYS: &[&str] = &["command", "restart_command"];
+
+// Where a loaded system came from, so saving writes it back as that entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemOrigin {
+    // Position in the main file's `systems`
+    Main(usize),
+    // Included files are never written, so these systems can't be changed
+    Included,
+}
+
+// How the configuration looked on disk, so saving can write back what the user wrote:
+// `${VAR}` references instead of their values, settings inherited from templates left to the
+// template, and systems from included files left in those files.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    // The main file as written
+    raw: Table,
+    // The main file as loaded, serialized back from `Config`; set by `set_loaded`
+    loaded: Table,
+    // Systems of the main file, loaded and raw, in file order
+    systems: Vec<(Value, Value)>,
+    // Names of templates that came from included files
+    included_templates: BTreeSet<String>,
+}
+
+// Reads the file with its includes, upgrades older layouts, substitutes environment variables and applies templates.
+// The result deserializes into `Config`.
+pub async fn load(path: &Path) -> Result<(Table, ConfigSource)> {
+    load_with(path, &|name| std::env::var(name).ok()).await
+}
+
+pub async fn load_with(path: &Path, lookup: &dyn Fn(&str) -> Option<String>) -> Result<(Table, ConfigSource)> {
+    let mut raw = read_table(path).await?;
+    let version = migrations::version_of(&raw)?;
+    migrations::migrate(&mut raw, version, true)?;
+    if version < migrations::CONFIG_VERSION {
+        // Saving writes the upgraded layout over the file, so keep the original
+        if let Some(backup) = migrations::write_backup(path, version).await? {
+            log::info!("Upgraded {} from config version {}, the original is saved as {}", path.display(), version, backup.display());
+        }
+    }
+    let mut table = raw.clone();
+    substitute_table(&mut table, lookup).with_context(|| format!("In {}", path.display()))?;
+
+    let mut source = ConfigSource { raw, ..ConfigSource::default() };
+    let mut visited = BTreeSet::from([canonical(path)]);
+    let base = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let includes = string_list(&table, "include")?;
+    merge_includes(&mut table, &base, &includes, lookup, &mut visited, &mut source, 0).await?;
+
+    apply_templates(&mut table)?;
+    Ok((table, source))
+}
+
+async fn read_table(path: &Path) -> Result<Table> {
+    let content = tokio::fs::read_to_string(path).await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Invalid TOML in {}", path.display()))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn string_list(table: &Table, key: &str) -> Result<Vec<String>> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(items)) => items.iter()
+            .map(|item| item.as_str().map(str::to_string).ok_or_else(|| anyhow::anyhow!("'{}' must be a list of strings", key)))
+            .collect(),
+        Some(_) => anyhow::bail!("'{}' must be a list of strings", key),
+    }
+}
+
+// Included files add systems and templates; anything else belongs in the main file
+fn merge_includes<'a>(
+    table: &'a mut Table,
+    base: &'a Path,
+    patterns: &'a [String],
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+    visited: &'a mut BTreeSet<PathBuf>,
+    source: &'a mut ConfigSource,
+    depth: usize,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<()>> + 'a>> {
+    Box::pin(async move {
+        if depth >= MAX_INCLUDE_DEPTH && !patterns.is_empty() {
+            anyhow::bail!("Includes nested more than {} deep", MAX_INCLUDE_DEPTH);
+        }
+        for pattern in patterns {
+            for path in expand_pattern(base, pattern)? {
+                // Each file is read once, which also stops include cycles
+                if !visited.insert(canonical(&path)) {
+                    continue;
+                }
+                // Included files are never rewritten, so they're upgraded from their own version
+                // on every load
+                let mut included = read_table(&path).await?;
+                let version = migrations::version_of(&included).with_context(|| format!("In {}", path.display()))?;
+                migrations::migrate(&mut included, version, false).with_context(|| format!("In {}", path.display()))?;
+                substitute_table(&mut included, lookup).with_context(|| format!("In {}", path.display()))?;
+                if let Some(key) = included.keys().find(|key| !matches!(key.as_str(), "version" | "systems" | "templates" | "include")) {
+                    anyhow::bail!("{} sets '{}', included files may only contain a version, systems, templates and includes", path.display(), key);
+                }
+
+                if let Some(Value::Array(systems)) = included.remove("systems") {
+                    match table.entry("systems").or_insert_with(|| Value::Array(Vec::new())) {
+                        Value::Array(existing) => existing.extend(systems),
+                        _ => anyhow::bail!("'systems' must be a list"),
+                    }
+                }
+                if let Some(Value::Table(templates)) = included.remove("templates") {
+                    let Value::Table(existing) = table.entry("templates").or_insert_with(|| Value::Table(Table::new())) else {
+                        anyhow::bail!("'templates' must be a table");
+                    };
+                    for (name, template) in templates {
+                        if existing.contains_key(&name) {
+                            anyhow::bail!("Template '{}' in {} is already defined", name, path.display());
+                        }
+                        source.included_templates.insert(name.clone());
+                        existing.insert(name, template);
+                    }
+                }
+
+                let nested = string_list(&included, "include")?;
+                let nested_base = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                merge_includes(table, &nested_base, &nested, lookup, visited, source, depth + 1).await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+// "conf.d/*.toml" relative to `base`; wildcards (* and ?) are only allowed in the file name.
+// Matches are sorted so files are merged in a predictable order.
+pub fn expand_pattern(base: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = base.join(pattern);
+    let file_pattern = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+    let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    if directory.to_string_lossy().contains(['*', '?']) {
+        anyhow::bail!("Wildcards are only supported in the file name of include '{}'", pattern);
+    }
+    if !file_pattern.contains(['*', '?']) {
+        return Ok(vec![path]);
+    }
+
+    let directory_to_read = if directory.as_os_str().is_empty() { Path::new(".") } else { directory.as_path() };
+    let entries = match std::fs::read_dir(directory_to_read) {
+        Ok(entries) => entries,
+        // A missing conf.d simply has nothing to include
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", directory.display())),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| wildcard_match(&file_pattern, name)))
+        .map(|entry| directory.join(entry.file_name()))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Position after the last * and where in the name it resumed matching
+    let (mut p, mut n, mut star, mut resume) = (0, 0, None, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p + 1);
+            resume = n;
+            p += 1;
+        } else if let Some(after_star) = star {
+            resume += 1;
+            p = after_star;
+            n = resume;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Replaces `${NAME}` and `${NAME:-default}` in every string value except commands, see
+// VERBATIM_KEYS; `$$` is a literal `$`
+pub fn substitute(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after_dollar) = after.strip_prefix('$') {
+            output.push('$');
+            rest = after_dollar;
+        } else if let Some(reference) = after.strip_prefix('{') {
+            let end = reference.find('}')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated '${{' in '{}'", input))?;
+            let (name, default) = match reference[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&reference[..end], None),
+            };
+            let value = lookup(name).filter(|v| !v.is_empty()).or_else(|| default.map(str::to_string))
+                .ok_or_else(|| anyhow::anyhow!("Environment variable {} is not set and has no default", name))?;
+            output.push_str(&value);
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = after;
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn substitute_table(table: &mut Table, lookup: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+    for (key, value) in table.iter_mut() {
+        if !VERBATIM_KEYS.contains(&key.as_str()) {
+            substitute_value(value, lookup)?;
+        }
+    }
+    Ok(())
+}
+
+fn substitute_value(value: &mut Value, lookup: &dyn Fn(&str) -> Option<String>) -> Result<()> {
+    match value {
+        Value::String(s) if s.contains('$') => *s = substitute(s, lookup)?,
+        Value::Array(items) => {
+            for item in items {
+                substitute_value(item, lookup)?;
+            }
+        }
+        Value::Table(table) => substitute_table(table, lookup)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+// Fills in every system that `extends` a template with the template's settings. The system's
+// own settings win; tables such as `probe` are merged key by key. Templates may extend others.
+fn apply_templates(table: &mut Table) -> Result<()> {
+    let templates = match table.get("templates") {
+        Some(Value::Table(templates)) => templates.clone(),
+        Some(_) => anyhow::bail!("'templates' must be a table"),
+        None => Table::new(),
+    };
+    let Some(Value::Array(systems)) = table.get_mut("systems") else {
+        return Ok(());
+    };
+    for system in systems.iter_mut().filter_map(Value::as_table_mut) {
+        extend(system, &templates)?;
+    }
+    Ok(())
+}
+
+// A system as it will be after the next load, for systems added or changed at runtime: their
+// `extends` would otherwise only take effect after a restart
+pub fn resolve_template(config: &SystemConfig, templates: &BTreeMap<String, Table>) -> Result<SystemConfig> {
+    if config.extends.is_none() {
+        return Ok(config.clone());
+    }
+    let templates: Table = templates.iter().map(|(name, template)| (name.clone(), Value::Table(template.clone()))).collect();
+    let mut system = Table::try_from(config)?;
+    extend(&mut system, &templates)?;
+    let mut resolved: SystemConfig = Value::Table(system).try_into()?;
+    resolved.origin = config.origin;
+    Ok(resolved)
+}
+
+fn extend(system: &mut Table, templates: &Table) -> Result<()> {
+    let mut chain = Vec::new();
+    let mut next = system.get("extends").and_then(Value::as_str).map(str::to_string);
+    while let Some(name) = next {
+        if chain.contains(&name) {
+            anyhow::bail!("Template '{}' extends itself", name);
+        }
+        let Some(Value::Table(template)) = templates.get(&name) else {
+            anyhow::bail!("No template named '{}'", name);
+        };
+        next = template.get("extends").and_then(Value::as_str).map(str::to_string);
+        chain.push(name);
+        merge_missing(system, template);
+    }
+    Ok(())
+}
+
+fn merge_missing(target: &mut Table, defaults: &Table) {
+    for (key, value) in defaults {
+        // The system names its own template, not the template's parent
+        if key == "extends" {
+            continue;
+        }
+        match (target.get_mut(key), value) {
+            (None, _) => {
+                target.insert(key.clone(), value.clone());
+            }
+            (Some(Value::Table(existing)), Value::Table(defaults)) => merge_missing(existing, defaults),
+            _ => {}
+        }
+    }
+}
+
+impl ConfigSource {
+    // Records the configuration as it was deserialized, what `restore` compares against
+    pub fn set_loaded(&mut self, loaded: Table) {
+        let raw_systems = match self.raw.get("systems") {
+            Some(Value::Array(systems)) => systems.clone(),
+            _ => Vec::new(),
+        };
+        let loaded_systems = match loaded.get("systems") {
+            Some(Value::Array(systems)) => systems.clone(),
+            _ => Vec::new(),
+        };
+        // The main file's systems come first, included ones after
+        self.systems = loaded_systems.into_iter().zip(raw_systems).collect();
+        self.loaded = loaded;
+    }
+
+    // Of the loaded system at `index`
+    pub fn origin(&self, index: usize) -> SystemOrigin {
+        if index < self.systems.len() {
+            SystemOrigin::Main(index)
+        } else {
+            SystemOrigin::Included
+        }
+    }
+
+    // Turns the current configuration back into what goes into the main file: unchanged
+    // values as they were written, nothing from included files. `origins` are those of the
+    // current systems, which are matched to their entries by it rather than by name, so a
+    // renamed system keeps its references and inherited settings too.
+    pub fn restore(&self, current: Table, origins: &[Option<SystemOrigin>]) -> Table {
+        let mut current = current;
+        let systems = match current.remove("systems") {
+            Some(Value::Array(systems)) => systems,
+            _ => Vec::new(),
+        };
+        let mut restored = restore_table(current, Some(&self.loaded), Some(&self.raw));
+
+        if let Some(Value::Table(templates)) = restored.get_mut("templates") {
+            templates.retain(|name, _| !self.included_templates.contains(name));
+            if templates.is_empty() {
+                restored.remove("templates");
+            }
+        }
+
+        let systems: Vec<Value> = systems.into_iter()
+            .zip(origins.iter().copied().chain(std::iter::repeat(None)))
+            .filter_map(|(system, origin)| match origin {
+                Some(SystemOrigin::Main(index)) => match self.systems.get(index) {
+                    Some((loaded, raw)) => restore_value(system, Some(loaded), Some(raw)),
+                    None => Some(system),
+                },
+                Some(SystemOrigin::Included) => None,
+                None => Some(system),
+            })
+            .collect();
+        // Keep the systems where they were in the file, after the settings
+        restored.insert("systems".to_string(), Value::Array(systems));
+        restored
+    }
+}
+
+// None drops the key: it is unchanged and wasn't written, e.g. a default or inherited value
+fn restore_value(current: Value, loaded: Option<&Value>, raw: Option<&Value>) -> Option<Value> {
+    if loaded == Some(&current) {
+        return raw.cloned();
+    }
+    match (current, loaded, raw) {
+        (Value::Table(current), Some(Value::Table(loaded)), Some(Value::Table(raw))) => {
+            Some(Value::Table(restore_table(current, Some(loaded), Some(raw))))
+        }
+        (current, _, _) => Some(current),
+    }
+}
+
+fn restore_table(current: Table, loaded: Option<&Table>, raw: Option<&Table>) -> Table {
+    current.into_iter()
+        .filter_map(|(key, value)| {
+            let restored = restore_value(value, loaded.and_then(|l| l.get(&key)), raw.and_then(|r| r.get(&key)))?;
+            Some((key, restored))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let lookup = |name: &str| (name == "SITE").then(|| "ams".to_string());
+        assert_eq!(substitute("router.${SITE}.example.com", &lookup).unwrap(), "router.ams.example.com");
+        assert_eq!(substitute("${PORT:-8080} costs $$5 or $", &lookup).unwrap(), "8080 costs $5 or $");
+        assert!(substitute("${TOKEN}", &lookup).is_err());
+        assert!(substitute("${SITE", &lookup).is_err());
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.toml", "web.toml"));
+        assert!(wildcard_match("site-?.toml", "site-a.toml"));
+        assert!(wildcard_match("*-*.toml", "a-b-c.toml"));
+        assert!(!wildcard_match("*.toml", "web.toml.bak"));
+        assert!(!wildcard_match("site-?.toml", "site-ab.toml"));
+    }
+
+    #[tokio::test]
+    async fn test_templates_includes_and_round_trip() {
+        let directory = std::env::temp_dir().join(format!("system-monitor-config-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("conf.d")).unwrap();
+        let main = directory.join("monitor.toml");
+        std::fs::write(&main, r#"
+include = ["conf.d/*.toml"]
+check_interval_seconds = 30
+timeout_seconds = 5
+
+[api]
+bearer_token = "${MONITOR_TEST_TOKEN}"
+
+[templates.web]
+protocol = "Tcp"
+port = 443
+enabled = true
+probe = { timeout_ms = 2000 }
+
+[[systems]]
+name = "Shop"
+host = "shop.${MONITOR_TEST_SITE:-example.com}"
+extends = "web"
+port = 8443
+
+[[systems]]
+name = "Backup"
+host = "localhost"
+protocol = "Exec"
+enabled = true
+probe = { command = "test -s \"${BACKUP_DIR:-/backup}/latest\" && echo $$" }
+"#).unwrap();
+        std::fs::write(directory.join("conf.d/office.toml"), r#"
+[[systems]]
+name = "Printer"
+host = "192.0.2.10"
+port = 9100
+protocol = "tcp"
+extends = "web"
+"#).unwrap();
+        std::fs::write(directory.join("conf.d/site.toml"), r#"
+version = 1
+
+[[systems]]
+name = "Gateway"
+host = "192.0.2.1"
+protocol = "Ping"
+enabled = true
+"#).unwrap();
+
+        let lookup = |name: &str| (name == "MONITOR_TEST_TOKEN").then(|| "secret".to_string());
+        let (table, mut source) = load_with(&main, &lookup).await.unwrap();
+        let mut config: crate::config::Config = Value::Table(table).try_into().unwrap();
+        source.set_loaded(Table::try_from(&config).unwrap());
+        for (index, system) in config.systems.iter_mut().enumerate() {
+            system.origin = Some(source.origin(index));
+        }
+        assert_eq!(config.systems[2].origin, Some(SystemOrigin::Included));
+        assert_eq!(config.api.bearer_token.as_deref(), Some("secret"));
+        assert_eq!(config.systems.len(), 4);
+        assert_eq!((config.systems[0].host.as_str(), config.systems[0].port), ("shop.example.com", Some(8443)));
+        // Commands are left to the shell
+        let command = config.systems[1].probe.as_ref().and_then(|p| p.get("command")).and_then(Value::as_str);
+        assert_eq!(command, Some("test -s \"${BACKUP_DIR:-/backup}/latest\" && echo $$"));
+        assert_eq!(config.systems[2].port, Some(9100));
+        // The unversioned include is upgraded on its own, the versioned one is read as is
+        assert_eq!(config.systems[2].protocol, crate::config::Protocol::Tcp);
+        assert_eq!(config.systems[3].protocol, crate::config::Protocol::Ping);
+        assert!(config.systems[2].probe.as_ref().is_some_and(|p| p.contains_key("timeout_ms")));
+
+        config.systems[0].enabled = false;
+        config.systems[0].name = "Webshop".to_string();
+        let origins: Vec<_> = config.systems.iter().map(|system| system.origin).collect();
+        let saved = source.restore(Table::try_from(&config).unwrap(), &origins);
+        assert_eq!(saved["api"]["bearer_token"].as_str(), Some("${MONITOR_TEST_TOKEN}"));
+        let systems = saved["systems"].as_array().unwrap();
+        assert_eq!(systems.len(), 2);
+        assert_eq!(systems[0]["host"].as_str(), Some("shop.${MONITOR_TEST_SITE:-example.com}"));
+        assert_eq!(systems[0]["enabled"].as_bool(), Some(false));
+        assert_eq!(systems[0]["name"].as_str(), Some("Webshop"));
+        assert_eq!(systems[0]["extends"].as_str(), Some("web"));
+        assert!(systems[0].get("protocol").is_none());
+        assert!(systems[0].get("probe").is_none());
+        assert!(saved["templates"].get("web").is_some());
+        assert_eq!(saved["version"].as_integer(), Some(migrations::CONFIG_VERSION.into()));
+        assert!(directory.join("monitor.toml.v0.bak").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
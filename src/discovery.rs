@@ -0,0 +1,216 @@
+use crate::config::{Protocol, SystemConfig};
+use crate::probe::{ProbeRegistry, ProbeTarget};
+use crate::resolve::DnsResolver;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+// A /16, or a /112 for IPv6; larger ranges are better split up
+pub const MAX_SWEEP_ADDRESSES: usize = 65536;
+
+#[derive(Debug, Clone)]
+pub struct SweepOptions {
+    // Try an ICMP echo, needs the ping binary like ping checks do
+    pub icmp: bool,
+    // A host counts as found when any of these accepts a connection
+    pub ports: Vec<u16>,
+    // Addresses probed at the same time
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub reverse_dns: bool,
+}
+
+impl Default for SweepOptions {
+    fn default() -> Self {
+        Self {
+            icmp: true,
+            ports: vec![22, 80, 443],
+            concurrency: 64,
+            timeout: Duration::from_secs(1),
+            reverse_dns: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveredHost {
+    pub address: IpAddr,
+    pub hostname: Option<String>,
+    pub answers_ping: bool,
+    pub open_ports: Vec<u16>,
+    // Fastest answer of the ping and the connects
+    pub response_time_ms: Option<f64>,
+}
+
+impl DiscoveredHost {
+    // Checked by address, so a later DNS change doesn't silently move the system
+    pub fn to_system(&self, protocol: Protocol, group: Option<String>) -> SystemConfig {
+        let name = self.hostname.clone().unwrap_or_else(|| self.address.to_string());
+        let port = match protocol {
+            Protocol::Tcp => self.open_ports.first().copied(),
+            _ => None,
+        };
+        let mut system = SystemConfig::new(name, self.address.to_string(), port, protocol);
+        system.group = group;
+        system
+    }
+
+    // Whether a configured system already checks this host, by address or name
+    pub fn is_monitored(&self, systems: &[SystemConfig]) -> bool {
+        let address = self.address.to_string();
+        systems.iter().any(|system| {
+            system.host == address || self.hostname.as_ref().is_some_and(|h| system.host.eq_ignore_ascii_case(h))
+        })
+    }
+}
+
+// Addresses of "192.0.2.0/24", "2001:db8::/120" or a single address. The network and
+// broadcast addresses of IPv4 ranges are left out.
+pub fn parse_cidr(network: &str) -> Result<Vec<IpAddr>> {
+    let (address, prefix) = match network.trim().split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (network.trim(), None),
+    };
+    let address: IpAddr = address.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid network '{}', expected an address with an optional /prefix", network))?;
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    let prefix: u32 = match prefix {
+        Some(prefix) => prefix.parse().ok().filter(|p| *p <= bits)
+            .ok_or_else(|| anyhow::anyhow!("Invalid prefix length in '{}'", network))?,
+        None => bits,
+    };
+    // `::/0` has 2^128 addresses, one more than a u128 holds
+    let count = 1u128.checked_shl(bits - prefix).unwrap_or(u128::MAX);
+    if count > MAX_SWEEP_ADDRESSES as u128 {
+        anyhow::bail!("{} has {} addresses, at most {} can be swept at once", network, count, MAX_SWEEP_ADDRESSES);
+    }
+
+    let addresses = match address {
+        IpAddr::V4(v4) => {
+            let first = u32::from(v4) & u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            let last = first + (count - 1) as u32;
+            // /31 and /32 have no network or broadcast address
+            let range = if prefix < 31 { first + 1..=last - 1 } else { first..=last };
+            range.map(|a| IpAddr::V4(Ipv4Addr::from(a))).collect()
+        }
+        IpAddr::V6(v6) => {
+            let first = u128::from(v6) & u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            (0..count).map(|offset| IpAddr::V6(Ipv6Addr::from(first + offset))).collect()
+        }
+    };
+    Ok(addresses)
+}
+
+// Probes every address of the network, `options.concurrency` at a time, and returns the hosts
+// that answered, ordered by address. `progress` counts the addresses done so far.
+pub async fn sweep(network: &str, options: &SweepOptions, progress: Option<Arc<AtomicUsize>>) -> Result<Vec<DiscoveredHost>> {
+    let addresses = parse_cidr(network)?;
+    if !options.icmp && options.ports.is_empty() {
+        anyhow::bail!("Nothing to probe with, enable ping or give at least one port");
+    }
+
+    let timeout_ms = toml::Value::Integer(options.timeout.as_millis() as i64);
+    let settings = toml::Table::from_iter([("timeout_ms".to_string(), timeout_ms)]);
+    let registry = ProbeRegistry::builtin();
+    let ping_config = SystemConfig { probe: Some(settings.clone()), ..SystemConfig::new(String::new(), String::new(), None, Protocol::Ping) };
+    let tcp_config = SystemConfig { probe: Some(settings), ..SystemConfig::new(String::new(), String::new(), None, Protocol::Tcp) };
+    let ping = registry.create(&ping_config)?;
+    let tcp = registry.create(&tcp_config)?;
+    let resolver = Arc::new(DnsResolver::new());
+    let ping_config = Arc::new(ping_config);
+    let tcp_config = Arc::new(tcp_config);
+
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut tasks = tokio::task::JoinSet::new();
+    for address in addresses {
+        // Waiting for a permit before spawning keeps a large range from queueing thousands of tasks
+        let permit = Arc::clone(&semaphore).acquire_owned().await?;
+        let (ping, tcp, ping_config, tcp_config) = (Arc::clone(&ping), Arc::clone(&tcp), Arc::clone(&ping_config), Arc::clone(&tcp_config));
+        let (resolver, progress, options) = (Arc::clone(&resolver), progress.clone(), options.clone());
+        tasks.spawn(async move {
+            let mut response_times = Vec::new();
+            let mut answers_ping = false;
+            if options.icmp {
+                let target = ProbeTarget { config: &ping_config, address, port: None };
+                if let Ok(result) = ping.check(&target).await {
+                    answers_ping = true;
+                    response_times.extend(result.timings.connect_ms);
+                }
+            }
+
+            let mut open_ports = Vec::new();
+            for port in &options.ports {
+                let target = ProbeTarget { config: &tcp_config, address, port: Some(*port) };
+                if let Ok(result) = tcp.check(&target).await {
+                    open_ports.push(*port);
+                    response_times.extend(result.timings.connect_ms);
+                }
+            }
+
+            let found = answers_ping || !open_ports.is_empty();
+            let hostname = if found && options.reverse_dns { resolver.reverse(address).await } else { None };
+            drop(permit);
+            if let Some(progress) = progress {
+                progress.fetch_add(1, Ordering::Relaxed);
+            }
+            found.then(|| DiscoveredHost {
+                address,
+                hostname,
+                answers_ping,
+                open_ports,
+                response_time_ms: response_times.into_iter().reduce(f64::min),
+            })
+        });
+    }
+
+    let mut hosts = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(Some(host)) => hosts.push(host),
+            Ok(None) => {}
+            Err(e) => log::error!("Discovery task failed: {}", e),
+        }
+    }
+    hosts.sort_by_key(|host| host.address);
+    Ok(hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cidr() {
+        let addresses = parse_cidr("192.0.2.77/29").unwrap();
+        assert_eq!(addresses.len(), 6);
+        assert_eq!(addresses[0], "192.0.2.73".parse::<IpAddr>().unwrap());
+        assert_eq!(addresses[5], "192.0.2.78".parse::<IpAddr>().unwrap());
+        assert_eq!(parse_cidr("192.0.2.8/31").unwrap().len(), 2);
+        assert_eq!(parse_cidr("192.0.2.8").unwrap().len(), 1);
+        assert_eq!(parse_cidr("2001:db8::/126").unwrap().len(), 4);
+        assert!(parse_cidr("10.0.0.0/8").is_err());
+        assert!(parse_cidr("::/0").is_err());
+        assert!(parse_cidr("0.0.0.0/0").is_err());
+        assert!(parse_cidr("192.0.2.0/33").is_err());
+        assert!(parse_cidr("example.com/24").is_err());
+    }
+
+    #[test]
+    fn test_discovered_host_to_system() {
+        let host = DiscoveredHost {
+            address: "192.0.2.10".parse().unwrap(),
+            hostname: Some("printer.example.com".to_string()),
+            answers_ping: false,
+            open_ports: vec![631, 9100],
+            response_time_ms: Some(1.5),
+        };
+        let system = host.to_system(Protocol::Tcp, Some("Office".to_string()));
+        assert_eq!((system.name.as_str(), system.host.as_str(), system.port), ("printer.example.com", "192.0.2.10", Some(631)));
+        assert!(host.is_monitored(&[system]));
+        assert!(!host.is_monitored(&[SystemConfig::new("x".to_string(), "192.0.2.11".to_string(), None, Protocol::Ping)]));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::health::{Degradation, SystemState};
+use crate::history::CheckRecord;
+use crate::trace::PathHops;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MonitorEvent {
+    CheckCompleted {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+        record: CheckRecord,
+    },
+    StateChanged {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+        is_online: bool,
+        state: SystemState,
+        degradation: Option<Degradation>,
+        timestamp: DateTime<Utc>,
+        error: Option<String>,
+    },
+    TraceCompleted {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+        reached: bool,
+    },
+    RouteChanged {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+        timestamp: DateTime<Utc>,
+        old_hops: PathHops,
+        new_hops: PathHops,
+    },
+    SystemAdded {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+    },
+    SystemUpdated {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+    },
+    SystemRemoved {
+        system_id: Uuid,
+        name: String,
+        group: Option<String>,
+    },
+}
+
+impl MonitorEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            MonitorEvent::CheckCompleted { .. } => "check_completed",
+            MonitorEvent::StateChanged { .. } => "state_changed",
+            MonitorEvent::TraceCompleted { .. } => "trace_completed",
+            MonitorEvent::RouteChanged { .. } => "route_changed",
+            MonitorEvent::SystemAdded { .. } => "system_added",
+            MonitorEvent::SystemUpdated { .. } => "system_updated",
+            MonitorEvent::SystemRemoved { .. } => "system_removed",
+        }
+    }
+
+    pub fn system_id(&self) -> Uuid {
+        match self {
+            MonitorEvent::CheckCompleted { system_id, .. }
+            | MonitorEvent::StateChanged { system_id, .. }
+            | MonitorEvent::TraceCompleted { system_id, .. }
+            | MonitorEvent::RouteChanged { system_id, .. }
+            | MonitorEvent::SystemAdded { system_id, .. }
+            | MonitorEvent::SystemUpdated { system_id, .. }
+            | MonitorEvent::SystemRemoved { system_id, .. } => *system_id,
+        }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            MonitorEvent::CheckCompleted { group, .. }
+            | MonitorEvent::StateChanged { group, .. }
+            | MonitorEvent::TraceCompleted { group, .. }
+            | MonitorEvent::RouteChanged { group, .. }
+            | MonitorEvent::SystemAdded { group, .. }
+            | MonitorEvent::SystemUpdated { group, .. }
+            | MonitorEvent::SystemRemoved { group, .. } => group.as_deref(),
+        }
+    }
+}
+
+// Subscriber-side filter; every populated field must match for an event to pass
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    pub system: Option<Uuid>,
+    pub group: Option<String>,
+    // Comma separated list of event types, e.g. "state_changed,system_removed"
+    #[serde(rename = "type")]
+    pub event_types: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &MonitorEvent) -> bool {
+        if self.system.is_some_and(|id| id != event.system_id()) {
+            return false;
+        }
+        if let Some(group) = &self.group {
+            if event.group() != Some(group.as_str()) {
+                return false;
+            }
+        }
+        if let Some(types) = &self.event_types {
+            if !types.split(',').any(|t| t.trim() == event.event_type()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_filter_matches() {
+        let system_id = Uuid::new_v4();
+        let event = MonitorEvent::SystemUpdated { system_id, name: "Router".to_string(), group: Some("Office".to_string()) };
+
+        assert!(EventFilter::default().matches(&event));
+        assert!(EventFilter { system: Some(system_id), ..EventFilter::default() }.matches(&event));
+        assert!(!EventFilter { system: Some(Uuid::new_v4()), ..EventFilter::default() }.matches(&event));
+        assert!(EventFilter { group: Some("Office".to_string()), ..EventFilter::default() }.matches(&event));
+        assert!(!EventFilter { group: Some("Lab".to_string()), ..EventFilter::default() }.matches(&event));
+
+        let types = |types: &str| EventFilter { event_types: Some(types.to_string()), ..EventFilter::default() };
+        assert!(types("state_changed, system_updated").matches(&event));
+        assert!(!types("state_changed,system_removed").matches(&event));
+        // Every populated field has to match
+        let mixed = EventFilter { system: Some(system_id), group: Some("Lab".to_string()), event_types: None };
+        assert!(!mixed.matches(&event));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::health::{Degradation, Severity};
+use crate::history::FailureKind;
+use crate::probe::{Probe, ProbeError, ProbeFuture, ProbeResult, ProbeTarget, DEFAULT_TIMEOUT_MS};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+// Longest output kept, like the 4 KB Nagios itself keeps
+const MAX_OUTPUT_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecSettings {
+    pub command: String,
+    // `{host}`, `{address}` and `{port}` are replaced with the system's values
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub working_dir: Option<String>,
+    pub timeout_ms: u64,
+}
+
+impl Default for ExecSettings {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: Vec::new(),
+            env: BTreeMap::new(),
+            working_dir: None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+        }
+    }
+}
+
+pub struct ExecProbe {
+    pub settings: ExecSettings,
+}
+
+// Nagios plugin exit codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluginState {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl PluginState {
+    // Anything but 0-3, or being killed by a signal, counts as unknown
+    pub fn from_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => PluginState::Ok,
+            Some(1) => PluginState::Warning,
+            Some(2) => PluginState::Critical,
+            _ => PluginState::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for PluginState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginState::Ok => write!(f, "OK"),
+            PluginState::Warning => write!(f, "WARNING"),
+            PluginState::Critical => write!(f, "CRITICAL"),
+            PluginState::Unknown => write!(f, "UNKNOWN"),
+        }
+    }
+}
+
+// One `'label'=value[UOM];[warn];[crit];[min];[max]` item of a plugin's performance data
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfData {
+    pub label: String,
+    pub value: f64,
+    pub unit: String,
+    pub warning: Option<String>,
+    pub critical: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+// Splits plugin output into the status text and the performance data. The first line is
+// "TEXT | PERFDATA"; further lines are long text, where a `|` starts more performance data
+// that runs to the end of the output.
+pub fn parse_output(stdout: &str) -> (String, Vec<PerfData>) {
+    let mut lines = stdout.lines();
+    let (first, mut perf) = match lines.next() {
+        Some(line) => match line.split_once('|') {
+            Some((text, perf)) => (text.trim().to_string(), perf.to_string()),
+            None => (line.trim().to_string(), String::new()),
+        },
+        None => (String::new(), String::new()),
+    };
+
+    let mut text = vec![first];
+    let mut in_perf = false;
+    for line in lines {
+        if in_perf {
+            perf.push(' ');
+            perf.push_str(line);
+        } else if let Some((long_text, more)) = line.split_once('|') {
+            text.push(long_text.to_string());
+            perf.push(' ');
+            perf.push_str(more);
+            in_perf = true;
+        } else {
+            text.push(line.to_string());
+        }
+    }
+    let text = text.join("\n").trim().to_string();
+    (text, parse_perf_data(&perf))
+}
+
+// Items that can't be parsed, and values reported as unknown ("U"), are skipped
+pub fn parse_perf_data(perf: &str) -> Vec<PerfData> {
+    let mut items = Vec::new();
+    let mut rest = perf.trim_start();
+    while !rest.is_empty() {
+        // Labels may be quoted to contain spaces or '='
+        let (label, after_label) = match rest.strip_prefix('\'') {
+            Some(quoted) => match quoted.find("'=") {
+                Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+                None => break,
+            },
+            None => match rest.find('=') {
+                Some(end) => (rest[..end].to_string(), &rest[end..]),
+                None => break,
+            },
+        };
+        let Some(after_label) = after_label.strip_prefix('=') else {
+            break;
+        };
+        let end = after_label.find(char::is_whitespace).unwrap_or(after_label.len());
+        if let Some(item) = parse_perf_item(label, &after_label[..end]) {
+            items.push(item);
+        }
+        rest = after_label[end..].trim_start();
+    }
+    items
+}
+
+fn parse_perf_item(label: String, fields: &str) -> Option<PerfData> {
+    let mut fields = fields.split(';');
+    let value_field = fields.next()?;
+    let number_end = value_field
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(value_field.len());
+    let value = value_field[..number_end].parse().ok()?;
+    let mut next = || fields.next().map(str::trim).filter(|f| !f.is_empty());
+    let warning = next().map(str::to_string);
+    let critical = next().map(str::to_string);
+    let min = next().and_then(|f| f.parse().ok());
+    let max = next().and_then(|f| f.parse().ok());
+    Some(PerfData { label, value, unit: value_field[number_end..].to_string(), warning, critical, min, max })
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_OUTPUT_BYTES {
+        let mut end = MAX_OUTPUT_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+// Kills the command's whole process group when dropped, so children of a script that
+// timed out, or whose check was cancelled, don't outlive it
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            use nix::sys::signal::{killpg, Signal};
+            use nix::unistd::Pid;
+            // Fails with ESRCH once every process in the group has exited
+            let _ = killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+        }
+    }
+}
+
+impl Probe for ExecProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let settings = &self.settings;
+            if settings.command.is_empty() {
+                return Err(ProbeError::new(FailureKind::Unknown, "No command configured"));
+            }
+            let address = target.address.to_string();
+            let port = target.port.map(|p| p.to_string()).unwrap_or_default();
+            let substitute = |arg: &String| {
+                arg.replace("{host}", &target.config.host)
+                    .replace("{address}", &address)
+                    .replace("{port}", &port)
+            };
+
+            let mut command = Command::new(&settings.command);
+            command.args(settings.args.iter().map(substitute))
+                .envs(&settings.env)
+                .env("MONITOR_NAME", &target.config.name)
+                .env("MONITOR_HOST", &target.config.host)
+                .env("MONITOR_ADDRESS", &address)
+                .env("MONITOR_PORT", &port)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
+            if let Some(directory) = &settings.working_dir {
+                command.current_dir(directory);
+            }
+            #[cfg(unix)]
+            command.process_group(0);
+
+            let child = command.spawn().map_err(|e| {
+                ProbeError::new(FailureKind::Unknown, format!("Failed to run {}: {}", settings.command, e))
+            })?;
+            let _group = ProcessGroup(child.id());
+            let output = match timeout(Duration::from_millis(settings.timeout_ms), child.wait_with_output()).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(e)) => return Err(ProbeError::new(FailureKind::Unknown, format!("Failed to run {}: {}", settings.command, e))),
+                Err(_) => return Err(ProbeError::new(
+                    FailureKind::Timeout,
+                    format!("{} timed out after {}ms", settings.command, settings.timeout_ms),
+                )),
+            };
+
+            let state = PluginState::from_code(output.status.code());
+            let (mut text, perf_data) = parse_output(&String::from_utf8_lossy(&output.stdout));
+            if text.is_empty() {
+                text = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            }
+            let status_line = text.lines().next().unwrap_or_default().to_string();
+            let summary = match output.status.code() {
+                _ if !status_line.is_empty() => status_line,
+                Some(code) => format!("{} (exit code {})", state, code),
+                None => format!("{} (killed by a signal)", state),
+            };
+            let text = (!text.is_empty()).then(|| truncate(text));
+
+            match state {
+                PluginState::Ok | PluginState::Warning => {
+                    let metadata = perf_data.into_iter()
+                        .map(|perf| (perf.label, format!("{}{}", perf.value, perf.unit)))
+                        .collect();
+                    let degradation = (state == PluginState::Warning).then(|| Degradation {
+                        severity: Severity::Warning,
+                        reasons: vec![summary],
+                    });
+                    // How long the command ran is in the total time, it isn't network latency
+                    Ok(ProbeResult {
+                        metadata,
+                        degradation,
+                        output: text,
+                        ..ProbeResult::default()
+                    })
+                }
+                PluginState::Critical => Err(ProbeError::new(FailureKind::Response, summary).with_output(text)),
+                PluginState::Unknown => Err(ProbeError::new(FailureKind::Unknown, summary).with_output(text)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plugin_output() {
+        let stdout = "LICENSE OK - 12 of 50 seats used | seats=12;40;48;0;50 'wait time'=0.25s;;\n\
+                      server: lic01\n\
+                      port: 27000 | uptime=86400c\n\
+                      load=3%\n";
+        let (text, perf) = parse_output(stdout);
+        assert_eq!(text, "LICENSE OK - 12 of 50 seats used\nserver: lic01\nport: 27000");
+        assert_eq!(perf.len(), 4);
+        assert_eq!(perf[0], PerfData {
+            label: "seats".to_string(),
+            value: 12.0,
+            unit: String::new(),
+            warning: Some("40".to_string()),
+            critical: Some("48".to_string()),
+            min: Some(0.0),
+            max: Some(50.0),
+        });
+        assert_eq!((perf[1].label.as_str(), perf[1].value, perf[1].unit.as_str()), ("wait time", 0.25, "s"));
+        assert_eq!(perf[1].warning, None);
+        assert_eq!((perf[2].label.as_str(), perf[2].unit.as_str()), ("uptime", "c"));
+        assert_eq!((perf[3].label.as_str(), perf[3].value), ("load", 3.0));
+
+        assert_eq!(parse_perf_data("a=U;1;2 b=1"), vec![PerfData {
+            label: "b".to_string(),
+            value: 1.0,
+            unit: String::new(),
+            warning: None,
+            critical: None,
+            min: None,
+            max: None,
+        }]);
+        assert_eq!(PluginState::from_code(Some(1)), PluginState::Warning);
+        assert_eq!(PluginState::from_code(None), PluginState::Unknown);
+    }
+}
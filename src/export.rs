@@ -0,0 +1,306 @@
+use crate::health::SystemState;
+use crate::history::{CheckRecord, Incident, IncidentKind};
+use crate::latency;
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportKind {
+    Checks,
+    Incidents,
+    // Hourly latency percentiles
+    Latency,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampZone {
+    #[default]
+    Utc,
+    Local,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckRow {
+    timestamp: String,
+    result: &'static str,
+    rtt_ms: Option<u64>,
+    // Failed checks in the hour of this one
+    loss_percent: f64,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct IncidentRow {
+    kind: IncidentKind,
+    started_at: String,
+    ended_at: Option<String>,
+    duration_seconds: i64,
+    failed_checks: u64,
+    first_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyRow {
+    hour: String,
+    samples: u64,
+    loss_percent: f64,
+    mean_ms: Option<f64>,
+    min_ms: Option<f64>,
+    max_ms: Option<f64>,
+    p50_ms: Option<f64>,
+    p90_ms: Option<f64>,
+    p95_ms: Option<f64>,
+    p99_ms: Option<f64>,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+}
+
+impl TimestampZone {
+    pub fn format(&self, timestamp: DateTime<Utc>) -> String {
+        match self {
+            TimestampZone::Utc => timestamp.to_rfc3339(),
+            TimestampZone::Local => timestamp.with_timezone(&Local).to_rfc3339(),
+        }
+    }
+
+    // Accepts RFC 3339 timestamps, "YYYY-MM-DD HH:MM:SS" or a bare date; the latter two are
+    // interpreted in this zone. A bare date is the start of that day.
+    pub fn parse(&self, input: &str) -> Result<DateTime<Utc>> {
+        self.parse_bound(input, false)
+    }
+
+    // Like `parse`, but for the end of a range: a bare date is the end of that day, so
+    // `--to 2024-05-31` includes the 31st
+    pub fn parse_end(&self, input: &str) -> Result<DateTime<Utc>> {
+        self.parse_bound(input, true)
+    }
+
+    fn parse_bound(&self, input: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+        let input = input.trim();
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(input) {
+            return Ok(timestamp.with_timezone(&Utc));
+        }
+        let naive = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDate::parse_from_str(input, "%Y-%m-%d").map(|d| {
+                let time = if end_of_day { d.and_hms_nano_opt(23, 59, 59, 999_999_999) } else { d.and_hms_opt(0, 0, 0) };
+                time.unwrap_or_default()
+            }))
+            .map_err(|_| anyhow::anyhow!("Invalid time '{}', expected RFC 3339 or YYYY-MM-DD", input))?;
+        match self {
+            TimestampZone::Utc => Ok(Utc.from_utc_datetime(&naive)),
+            TimestampZone::Local => {
+                let local = Local.from_local_datetime(&naive);
+                if end_of_day { local.latest() } else { local.earliest() }
+                    .map(|t| t.with_timezone(&Utc))
+                    .ok_or_else(|| anyhow::anyhow!("'{}' does not exist in the local time zone", input))
+            }
+        }
+    }
+}
+
+// Share of failed checks per hour, the same buckets the latency export uses
+fn hourly_loss(records: &[CheckRecord]) -> BTreeMap<DateTime<Utc>, f64> {
+    let mut counts: BTreeMap<DateTime<Utc>, (u64, u64)> = BTreeMap::new();
+    for record in records {
+        let (failed, total) = counts.entry(latency::hour_of(record.timestamp)).or_default();
+        *total += 1;
+        if !record.is_online {
+            *failed += 1;
+        }
+    }
+    counts.into_iter()
+        .map(|(hour, (failed, total))| (hour, failed as f64 / total as f64 * 100.0))
+        .collect()
+}
+
+pub fn export_checks(records: &[CheckRecord], format: ExportFormat, zone: TimestampZone) -> Result<String> {
+    let loss = hourly_loss(records);
+    let rows: Vec<CheckRow> = records.iter()
+        .map(|record| CheckRow {
+            timestamp: zone.format(record.timestamp),
+            result: match record.state() {
+                SystemState::Up => "up",
+                SystemState::Degraded => "degraded",
+                SystemState::Down => "down",
+            },
+            rtt_ms: record.response_time_ms,
+            loss_percent: loss.get(&latency::hour_of(record.timestamp)).copied().unwrap_or_default(),
+            error: record.error.clone(),
+        })
+        .collect();
+    write_rows(&rows, format)
+}
+
+pub fn export_incidents(incidents: &[Incident], format: ExportFormat, zone: TimestampZone) -> Result<String> {
+    let rows: Vec<IncidentRow> = incidents.iter()
+        .map(|incident| IncidentRow {
+            kind: incident.kind,
+            started_at: zone.format(incident.started_at),
+            ended_at: incident.ended_at.map(|t| zone.format(t)),
+            duration_seconds: incident.duration_seconds(),
+            failed_checks: incident.failed_checks,
+            first_error: incident.first_error.clone(),
+        })
+        .collect();
+    write_rows(&rows, format)
+}
+
+pub fn export_latency(records: &[CheckRecord], format: ExportFormat, zone: TimestampZone) -> Result<String> {
+    let loss = hourly_loss(records);
+    let samples = records.iter().filter_map(|r| r.latency_ms().map(|ms| (r.timestamp, ms)));
+    let rows: Vec<LatencyRow> = latency::hourly_sketches(samples).into_iter()
+        .map(|(hour, sketch)| {
+            let summary = sketch.summary("1h");
+            LatencyRow {
+                hour: zone.format(hour),
+                samples: summary.count,
+                loss_percent: loss.get(&hour).copied().unwrap_or_default(),
+                mean_ms: summary.mean_ms,
+                min_ms: summary.min_ms,
+                max_ms: summary.max_ms,
+                p50_ms: summary.p50_ms,
+                p90_ms: summary.p90_ms,
+                p95_ms: summary.p95_ms,
+                p99_ms: summary.p99_ms,
+            }
+        })
+        .collect();
+    write_rows(&rows, format)
+}
+
+fn write_rows<T: Serialize>(rows: &[T], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(rows)?),
+        ExportFormat::Jsonl => {
+            let mut output = String::new();
+            for row in rows {
+                output.push_str(&serde_json::to_string(row)?);
+                output.push('\n');
+            }
+            Ok(output)
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" | "ndjson" => Ok(ExportFormat::Jsonl),
+            other => Err(anyhow::anyhow!("Unknown export format '{}', expected csv, json or jsonl", other)),
+        }
+    }
+}
+
+impl FromStr for ExportKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "checks" | "history" => Ok(ExportKind::Checks),
+            "incidents" => Ok(ExportKind::Incidents),
+            "latency" => Ok(ExportKind::Latency),
+            other => Err(anyhow::anyhow!("Unknown export kind '{}', expected checks, incidents or latency", other)),
+        }
+    }
+}
+
+impl FromStr for TimestampZone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "utc" => Ok(TimestampZone::Utc),
+            "local" => Ok(TimestampZone::Local),
+            other => Err(anyhow::anyhow!("Unknown time zone '{}', expected utc or local", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension().to_uppercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_checks_csv() {
+        let records = vec![CheckRecord {
+            timestamp: DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap(),
+            is_online: false,
+            response_time_ms: None,
+            error: Some("Connection refused, port 80".to_string()),
+            failure: None,
+            degraded: None,
+            timings: Default::default(),
+            output: None,
+        }];
+        let mut records = records;
+        records.push(CheckRecord {
+            timestamp: records[0].timestamp + chrono::Duration::seconds(30),
+            is_online: true,
+            response_time_ms: Some(12),
+            error: None,
+            ..records[0].clone()
+        });
+        let csv = export_checks(&records, ExportFormat::Csv, TimestampZone::Utc).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("timestamp,result,rtt_ms,loss_percent,error"));
+        assert_eq!(lines.next(), Some("2023-11-14T22:13:20+00:00,down,,50.0,\"Connection refused, port 80\""));
+        assert_eq!(lines.next(), Some("2023-11-14T22:13:50+00:00,up,12,50.0,"));
+    }
+
+    #[test]
+    fn test_parse_date_bounds() {
+        let zone = TimestampZone::Utc;
+        assert_eq!(zone.parse("2024-05-31").unwrap().to_rfc3339(), "2024-05-31T00:00:00+00:00");
+        let end = zone.parse_end("2024-05-31").unwrap();
+        assert!(end > zone.parse("2024-05-31 23:59:59").unwrap());
+        assert!(end < zone.parse("2024-06-01").unwrap());
+        // Explicit times are taken as given
+        assert_eq!(zone.parse_end("2024-05-31 12:00:00").unwrap(), zone.parse("2024-05-31 12:00:00").unwrap());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config::ThresholdSettings;
+use crate::history::CheckRecord;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemState {
+    Up,
+    Degraded,
+    #[default]
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+// Why an online system counts as degraded; `reasons` lists every breached threshold
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Degradation {
+    pub severity: Severity,
+    pub reasons: Vec<String>,
+}
+
+// Statistics over a window of recent checks, oldest first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowStats {
+    pub latency_ms: Option<f64>,
+    pub loss_percent: f64,
+    pub jitter_ms: Option<f64>,
+}
+
+impl WindowStats {
+    pub fn from_checks(checks: &[CheckRecord], percentile: Option<f64>) -> Self {
+        let latencies: Vec<f64> = checks.iter()
+            .filter(|c| c.is_online)
+            .filter_map(|c| c.response_time_ms.map(|ms| ms as f64))
+            .collect();
+
+        let latency_ms = match percentile {
+            _ if latencies.is_empty() => None,
+            Some(p) => Some(nearest_rank(&latencies, p)),
+            None => Some(latencies.iter().sum::<f64>() / latencies.len() as f64),
+        };
+        let failed = checks.iter().filter(|c| !c.is_online).count();
+        let loss_percent = if checks.is_empty() { 0.0 } else { failed as f64 / checks.len() as f64 * 100.0 };
+        // Mean difference between consecutive response times
+        let jitter_ms = (latencies.len() > 1).then(|| {
+            latencies.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (latencies.len() - 1) as f64
+        });
+
+        Self { latency_ms, loss_percent, jitter_ms }
+    }
+}
+
+fn nearest_rank(values: &[f64], percentile: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+// Compares the recent checks against the thresholds; None means the system is healthy
+pub fn evaluate(checks: &[CheckRecord], thresholds: &ThresholdSettings) -> Option<Degradation> {
+    let stats = WindowStats::from_checks(checks, thresholds.percentile);
+    let statistic = match thresholds.percentile {
+        Some(p) => format!("p{}", p),
+        None => "average".to_string(),
+    };
+
+    let mut severity = None;
+    let mut reasons = Vec::new();
+    let mut compare = |name: &str, value: Option<f64>, warning: Option<f64>, critical: Option<f64>, unit: &str| {
+        let Some(value) = value else {
+            return;
+        };
+        let breached = if critical.is_some_and(|limit| value >= limit) {
+            Some((Severity::Critical, critical))
+        } else if warning.is_some_and(|limit| value >= limit) {
+            Some((Severity::Warning, warning))
+        } else {
+            None
+        };
+        if let Some((level, Some(limit))) = breached {
+            severity = severity.max(Some(level));
+            reasons.push(format!("{} {:.1}{} over {}{}", name, value, unit, limit, unit));
+        }
+    };
+
+    compare(&format!("{} latency", statistic), stats.latency_ms,
+        thresholds.warning_latency_ms, thresholds.critical_latency_ms, "ms");
+    compare("loss", Some(stats.loss_percent),
+        thresholds.warning_loss_percent, thresholds.critical_loss_percent, "%");
+    compare("jitter", stats.jitter_ms,
+        thresholds.warning_jitter_ms, thresholds.critical_jitter_ms, "ms");
+
+    severity.map(|severity| Degradation { severity, reasons })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn check(response_time_ms: Option<u64>) -> CheckRecord {
+        CheckRecord {
+            timestamp: Utc::now(),
+            is_online: response_time_ms.is_some(),
+            response_time_ms,
+            error: None,
+            failure: None,
+            degraded: None,
+            timings: Default::default(),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_threshold_evaluation() {
+        let thresholds = ThresholdSettings {
+            warning_latency_ms: Some(100.0),
+            critical_latency_ms: Some(500.0),
+            warning_loss_percent: Some(20.0),
+            ..ThresholdSettings::default()
+        };
+        let healthy = vec![check(Some(20)), check(Some(40)), check(Some(30))];
+        assert_eq!(evaluate(&healthy, &thresholds), None);
+
+        let slow = vec![check(Some(90)), check(Some(150)), check(Some(120))];
+        let degradation = evaluate(&slow, &thresholds).unwrap();
+        assert_eq!(degradation.severity, Severity::Warning);
+        assert_eq!(degradation.reasons.len(), 1);
+
+        let lossy = vec![check(None), check(Some(600)), check(Some(600))];
+        let degradation = evaluate(&lossy, &thresholds).unwrap();
+        assert_eq!(degradation.severity, Severity::Critical);
+        assert_eq!(degradation.reasons.len(), 2);
+
+        let p90 = ThresholdSettings { percentile: Some(90.0), ..thresholds };
+        let spiky = vec![check(Some(10)), check(Some(10)), check(Some(10)), check(Some(200))];
+        assert_eq!(evaluate(&spiky, &p90).unwrap().severity, Severity::Warning);
+    }
+}
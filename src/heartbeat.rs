@@ -0,0 +1,183 @@
+use crate::history::FailureKind;
+use crate::monitor::MonitorManager;
+use crate::probe::{Probe, ProbeError, ProbeFuture, ProbeTarget};
+use anyhow::Result;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeartbeatSettings {
+    // How often the client reports in
+    pub period_seconds: u64,
+    // Extra time allowed before a late heartbeat counts as missed
+    pub grace_seconds: u64,
+    // Part of the heartbeat URL and all a client needs to report, so required; best random
+    pub token: Option<String>,
+}
+
+impl Default for HeartbeatSettings {
+    fn default() -> Self {
+        Self { period_seconds: 3600, grace_seconds: 300, token: None }
+    }
+}
+
+// Passive: never run, the system reports in instead
+pub struct HeartbeatProbe {
+    pub settings: HeartbeatSettings,
+}
+
+impl Probe for HeartbeatProbe {
+    fn check<'a>(&'a self, _target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async { Err(ProbeError::new(FailureKind::Unknown, "Heartbeat systems are not checked actively")) })
+    }
+
+    fn heartbeat_timeout(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.settings.period_seconds + self.settings.grace_seconds))
+    }
+
+    fn heartbeat_token(&self) -> Option<&str> {
+        self.settings.token.as_deref().filter(|token| !token.is_empty())
+    }
+}
+
+// What a client reports; an empty heartbeat means success
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Heartbeat {
+    pub exit_code: Option<i32>,
+    // How long the job took
+    pub duration_ms: Option<u64>,
+    pub message: Option<String>,
+}
+
+impl Heartbeat {
+    pub fn is_success(&self) -> bool {
+        self.exit_code.unwrap_or(0) == 0
+    }
+
+    // Fields set in `other` take precedence, e.g. a JSON body over query parameters
+    pub fn merge(self, other: Heartbeat) -> Heartbeat {
+        Heartbeat {
+            exit_code: other.exit_code.or(self.exit_code),
+            duration_ms: other.duration_ms.or(self.duration_ms),
+            message: other.message.or(self.message),
+        }
+    }
+}
+
+// Heartbeat systems by token. Lookups go through a keyed hash, so a request is only compared
+// with the tokens it hashes to, and those in constant time.
+#[derive(Default)]
+pub struct TokenIndex {
+    hasher: RandomState,
+    systems: DashMap<u64, Vec<(String, Uuid)>>,
+}
+
+impl TokenIndex {
+    pub fn insert(&self, token: &str, id: Uuid) {
+        self.systems.entry(self.hasher.hash_one(token)).or_default().push((token.to_string(), id));
+    }
+
+    pub fn remove(&self, id: Uuid) {
+        self.systems.retain(|_, systems| {
+            systems.retain(|(_, system)| *system != id);
+            !systems.is_empty()
+        });
+    }
+
+    pub fn get(&self, token: &str) -> Option<Uuid> {
+        self.systems.get(&self.hasher.hash_one(token))?.iter()
+            .find(|(candidate, _)| bool::from(candidate.as_bytes().ct_eq(token.as_bytes())))
+            .map(|(_, id)| *id)
+    }
+}
+
+// "<token> [exit code] [duration ms] [message...]", or the same as a JSON object with a `token` field
+pub fn parse_datagram(datagram: &str) -> Option<(String, Heartbeat)> {
+    let datagram = datagram.trim();
+    if datagram.starts_with('{') {
+        #[derive(Deserialize)]
+        struct Report {
+            token: String,
+            #[serde(flatten)]
+            heartbeat: Heartbeat,
+        }
+        let report: Report = serde_json::from_str(datagram).ok()?;
+        return Some((report.token, report.heartbeat));
+    }
+
+    let mut fields = datagram.splitn(4, char::is_whitespace);
+    let token = fields.next().filter(|token| !token.is_empty())?.to_string();
+    let exit_code = match fields.next() {
+        Some(code) => Some(code.parse().ok()?),
+        None => None,
+    };
+    let duration_ms = match fields.next() {
+        Some(duration) => Some(duration.parse().ok()?),
+        None => None,
+    };
+    let message = fields.next().map(|message| message.trim().to_string()).filter(|m| !m.is_empty());
+    Some((token, Heartbeat { exit_code, duration_ms, message }))
+}
+
+pub async fn run_udp_listener(bind_address: String, manager: Arc<RwLock<MonitorManager>>) -> Result<()> {
+    let socket = UdpSocket::bind(&bind_address).await?;
+    log::info!("Listening for heartbeats on udp://{}", bind_address);
+    let mut buffer = vec![0u8; 65536];
+    loop {
+        let (length, sender) = socket.recv_from(&mut buffer).await?;
+        let datagram = String::from_utf8_lossy(&buffer[..length]);
+        let Some((token, heartbeat)) = parse_datagram(&datagram) else {
+            log::warn!("Ignoring malformed heartbeat from {}", sender);
+            continue;
+        };
+        if let Err(e) = manager.read().await.record_heartbeat(&token, heartbeat).await {
+            log::warn!("Ignoring heartbeat from {}: {}", sender, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_datagram() {
+        assert_eq!(parse_datagram("nightly-backup\n"), Some(("nightly-backup".to_string(), Heartbeat::default())));
+        assert_eq!(
+            parse_datagram("nightly-backup 2 5400000 disk full on /backup"),
+            Some(("nightly-backup".to_string(), Heartbeat {
+                exit_code: Some(2),
+                duration_ms: Some(5400000),
+                message: Some("disk full on /backup".to_string()),
+            })),
+        );
+        let (token, heartbeat) = parse_datagram(r#"{"token": "sync", "exit_code": 0, "message": "ok"}"#).unwrap();
+        assert_eq!(token, "sync");
+        assert!(heartbeat.is_success());
+        assert_eq!(parse_datagram("sync soon"), None);
+        assert_eq!(parse_datagram("  "), None);
+    }
+
+    #[test]
+    fn test_token_index() {
+        let index = TokenIndex::default();
+        let (nightly, weekly) = (Uuid::new_v4(), Uuid::new_v4());
+        index.insert("nightly-7f3a", nightly);
+        index.insert("weekly-91c2", weekly);
+        assert_eq!(index.get("nightly-7f3a"), Some(nightly));
+        assert_eq!(index.get("nightly-7f3"), None);
+        assert_eq!(index.get(&nightly.to_string()), None);
+        index.remove(nightly);
+        assert_eq!(index.get("nightly-7f3a"), None);
+        assert_eq!(index.get("weekly-91c2"), Some(weekly));
+    }
+}
@@ -0,0 +1,502 @@
+use crate::health::{Severity, SystemState};
+use crate::latency::LatencyWindows;
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+const MAX_CHECK_RECORDS: usize = 2880; // 24 hours at the default 30 second interval
+const MAX_INCIDENTS: usize = 100;
+pub const DAILY_RETENTION_DAYS: usize = 90;
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckRecord {
+    pub timestamp: DateTime<Utc>,
+    pub is_online: bool,
+    pub response_time_ms: Option<u64>,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureKind>,
+    // Set when the system was online but breaching its thresholds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degraded: Option<Severity>,
+    #[serde(default, skip_serializing_if = "CheckTimings::is_empty")]
+    pub timings: CheckTimings,
+    // First line of what a script or heartbeat reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+// Duration of each phase of a check in milliseconds; phases a protocol doesn't have stay
+// unset. `connect_ms` is one network round trip: the TCP handshake, or the echo reply for ping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CheckTimings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<f64>,
+}
+
+impl CheckTimings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // (label, duration) of every measured phase, in the order they happen
+    pub fn phases(&self) -> Vec<(&'static str, f64)> {
+        [("DNS", self.dns_ms), ("Connect", self.connect_ms), ("TLS", self.tls_ms), ("First byte", self.first_byte_ms)]
+            .into_iter()
+            .filter_map(|(label, ms)| ms.map(|ms| (label, ms)))
+            .collect()
+    }
+}
+
+// Why a check failed, so a DNS problem isn't mistaken for the host being down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Resolution,
+    Timeout,
+    Refused,
+    Unreachable,
+    Tls,
+    // The service answered, but with an error
+    Response,
+    // The check itself couldn't tell, e.g. a plugin exiting with UNKNOWN
+    Unknown,
+    // A watched local process isn't running
+    NotRunning,
+    // A local network interface is down or missing
+    LinkDown,
+    // The system's parent was down at the time, see `SystemConfig::parent`
+    ParentDown,
+}
+
+impl std::fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureKind::Resolution => write!(f, "resolution failed"),
+            FailureKind::Timeout => write!(f, "timed out"),
+            FailureKind::Refused => write!(f, "connection refused"),
+            FailureKind::Unreachable => write!(f, "host unreachable"),
+            FailureKind::Tls => write!(f, "TLS failed"),
+            FailureKind::Response => write!(f, "bad response"),
+            FailureKind::Unknown => write!(f, "state unknown"),
+            FailureKind::NotRunning => write!(f, "not running"),
+            FailureKind::LinkDown => write!(f, "link down"),
+            FailureKind::ParentDown => write!(f, "parent down"),
+        }
+    }
+}
+
+impl CheckRecord {
+    // Network time of a successful check, with sub-millisecond precision when phases were timed
+    pub fn latency_ms(&self) -> Option<f64> {
+        if !self.is_online {
+            return None;
+        }
+        let phases: Vec<f64> = [self.timings.connect_ms, self.timings.tls_ms, self.timings.first_byte_ms]
+            .into_iter()
+            .flatten()
+            .collect();
+        if phases.is_empty() {
+            self.response_time_ms.map(|ms| ms as f64)
+        } else {
+            Some(phases.iter().sum())
+        }
+    }
+
+    pub fn state(&self) -> SystemState {
+        match (self.is_online, self.degraded) {
+            (false, _) => SystemState::Down,
+            (true, Some(_)) => SystemState::Degraded,
+            (true, None) => SystemState::Up,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncidentKind {
+    #[default]
+    Outage,
+    Degraded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Incident {
+    #[serde(default)]
+    pub kind: IncidentKind,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub failed_checks: u64,
+    pub first_error: Option<String>,
+}
+
+impl Incident {
+    pub fn is_open(&self) -> bool {
+        self.ended_at.is_none()
+    }
+
+    pub fn duration_seconds(&self) -> i64 {
+        let end = self.ended_at.unwrap_or_else(Utc::now);
+        (end - self.started_at).num_seconds()
+    }
+}
+
+// Per-day check counts (UTC days), kept much longer than individual checks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyUptime {
+    pub date: NaiveDate,
+    pub total_checks: u64,
+    pub successful_checks: u64,
+}
+
+impl DailyUptime {
+    pub fn uptime_percentage(&self) -> f64 {
+        if self.total_checks > 0 {
+            (self.successful_checks as f64 / self.total_checks as f64) * 100.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SystemHistory {
+    checks: VecDeque<CheckRecord>,
+    incidents: VecDeque<Incident>,
+    daily: BTreeMap<NaiveDate, DailyUptime>,
+    latency: LatencyWindows,
+}
+
+impl SystemHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, record: CheckRecord) {
+        // An outage ends any degraded period, the system can't be slow while it's down. Failures
+        // while the parent is down are the parent's incident, so they neither open nor end one.
+        let state = record.state();
+        let parent_down = record.failure == Some(FailureKind::ParentDown);
+        for kind in [IncidentKind::Outage, IncidentKind::Degraded].into_iter().filter(|_| !parent_down) {
+            let active = match kind {
+                IncidentKind::Outage => state == SystemState::Down,
+                IncidentKind::Degraded => state == SystemState::Degraded,
+            };
+            match (self.open_incident_mut(kind), active) {
+                (Some(incident), true) => incident.failed_checks += 1,
+                (Some(incident), false) => incident.ended_at = Some(record.timestamp),
+                (None, true) => {
+                    self.incidents.push_back(Incident {
+                        kind,
+                        started_at: record.timestamp,
+                        ended_at: None,
+                        failed_checks: 1,
+                        first_error: record.error.clone(),
+                    });
+                    if self.incidents.len() > MAX_INCIDENTS {
+                        self.incidents.pop_front();
+                    }
+                }
+                (None, false) => {}
+            }
+        }
+
+        self.count_day(&record);
+
+        if let Some(latency) = record.latency_ms() {
+            self.latency.add(record.timestamp, latency);
+        }
+
+        self.checks.push_back(record);
+        if self.checks.len() > MAX_CHECK_RECORDS {
+            self.checks.pop_front();
+        }
+    }
+
+    // Rebuilds day counts and ended incidents from stored checks, oldest first, so the long
+    // views survive a restart. Incidents still open come from the saved state instead.
+    pub fn seed(&mut self, records: &[CheckRecord]) {
+        for record in records {
+            self.count_day(record);
+        }
+        let ended: Vec<Incident> = incidents_from_checks(records).into_iter()
+            .filter(|i| !i.is_open())
+            .collect();
+        let skip = ended.len().saturating_sub(MAX_INCIDENTS.saturating_sub(self.incidents.len()));
+        let current = std::mem::take(&mut self.incidents);
+        self.incidents = ended.into_iter().skip(skip).chain(current).collect();
+    }
+
+    fn count_day(&mut self, record: &CheckRecord) {
+        let date = record.timestamp.date_naive();
+        let day = self.daily.entry(date).or_insert(DailyUptime {
+            date,
+            total_checks: 0,
+            successful_checks: 0,
+        });
+        day.total_checks += 1;
+        if record.is_online {
+            day.successful_checks += 1;
+        }
+        while self.daily.len() > DAILY_RETENTION_DAYS {
+            self.daily.pop_first();
+        }
+    }
+
+    // Most recent checks last; `limit` keeps only the newest entries
+    pub fn checks(&self, limit: Option<usize>) -> Vec<CheckRecord> {
+        let skip = limit.map_or(0, |l| self.checks.len().saturating_sub(l));
+        self.checks.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn incidents(&self) -> Vec<Incident> {
+        self.incidents.iter().cloned().collect()
+    }
+
+    pub fn daily_uptime(&self) -> Vec<DailyUptime> {
+        self.daily.values().cloned().collect()
+    }
+
+    pub fn latency(&self) -> &LatencyWindows {
+        &self.latency
+    }
+
+    // Outage and degraded periods that haven't ended
+    pub fn open_incidents(&self) -> Vec<Incident> {
+        self.incidents.iter().filter(|i| i.is_open()).cloned().collect()
+    }
+
+    // Carries incidents that were open at the last shutdown over, so checks continue them
+    pub fn resume_incidents(&mut self, incidents: Vec<Incident>) {
+        self.incidents.extend(incidents.into_iter().filter(|i| i.is_open()));
+    }
+
+    // The ongoing outage, if any
+    pub fn current_incident(&self) -> Option<&Incident> {
+        self.incidents.iter().rev().find(|i| i.kind == IncidentKind::Outage && i.is_open())
+    }
+
+    fn open_incident_mut(&mut self, kind: IncidentKind) -> Option<&mut Incident> {
+        self.incidents.iter_mut().rev().find(|i| i.kind == kind && i.is_open())
+    }
+}
+
+// Rebuilds the incident list from a sequence of checks, oldest first
+pub fn incidents_from_checks(records: &[CheckRecord]) -> Vec<Incident> {
+    let mut history = SystemHistory::new();
+    let mut incidents = Vec::new();
+    for record in records {
+        history.record(record.clone());
+        // Take incidents out as they close so the bounded list never drops any
+        while let Some(index) = history.incidents.iter().position(|i| !i.is_open()) {
+            incidents.extend(history.incidents.remove(index));
+        }
+    }
+    incidents.extend(history.incidents.drain(..));
+    incidents.sort_by_key(|i| i.started_at);
+    incidents
+}
+
+// Append-only JSON Lines log of every check, one file per system
+pub struct HistoryStore {
+    directory: PathBuf,
+    retention_days: u32,
+    // Pruning rewrites files; checks appended meanwhile would be lost without this
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl HistoryStore {
+    pub fn new(directory: impl Into<PathBuf>, retention_days: u32) -> Self {
+        Self {
+            directory: directory.into(),
+            retention_days,
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.jsonl", key))
+    }
+
+    pub async fn append(&self, key: &str, record: &CheckRecord) -> Result<()> {
+        fs::create_dir_all(&self.directory).await?;
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let _guard = self.write_lock.lock().await;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(key))
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    // Checks between `from` and `to` (inclusive), oldest first
+    pub async fn load(
+        &self,
+        key: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<CheckRecord>> {
+        let content = match fs::read_to_string(self.path(key)).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let records = content.lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str::<CheckRecord>(line) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    log::warn!("Skipping unreadable history line for {}: {}", key, e);
+                    None
+                }
+            })
+            .filter(|record| from.is_none_or(|from| record.timestamp >= from))
+            .filter(|record| to.is_none_or(|to| record.timestamp <= to))
+            .collect();
+        Ok(records)
+    }
+
+    // Moves a system's checks to its new key after a rename. A file already under the new key
+    // is left from a removed system and gets replaced.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let _guard = self.write_lock.lock().await;
+        match fs::rename(self.path(from), self.path(to)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Drops checks older than the retention period from every file
+    pub async fn prune(&self) -> Result<()> {
+        let cutoff = Utc::now() - Duration::days(self.retention_days as i64);
+        let mut entries = match fs::read_dir(&self.directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Some(key) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else {
+                continue;
+            };
+            if path.extension().and_then(|e| e.to_str()) != Some("jsonl") {
+                continue;
+            }
+
+            let _guard = self.write_lock.lock().await;
+            let records = self.load(&key, Some(cutoff), None).await?;
+            let mut content = String::new();
+            for record in &records {
+                content.push_str(&serde_json::to_string(record)?);
+                content.push('\n');
+            }
+            fs::write(&path, content).await?;
+        }
+        Ok(())
+    }
+}
+
+// Prunes at startup and then daily, so a monitor left running keeps to its retention period
+pub async fn run_pruner(store: Arc<HistoryStore>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = store.prune().await {
+            log::warn!("Failed to prune old history: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(offset_secs: i64, is_online: bool) -> CheckRecord {
+        CheckRecord {
+            timestamp: DateTime::<Utc>::from_timestamp(1_700_000_000 + offset_secs, 0).unwrap(),
+            is_online,
+            response_time_ms: is_online.then_some(10),
+            error: (!is_online).then(|| "timeout".to_string()),
+            failure: (!is_online).then_some(FailureKind::Timeout),
+            degraded: None,
+            timings: CheckTimings::default(),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_incident_opens_and_closes() {
+        let mut history = SystemHistory::new();
+        history.record(check(0, true));
+        history.record(check(30, false));
+        history.record(check(60, false));
+        assert_eq!(history.current_incident().unwrap().failed_checks, 2);
+
+        history.record(check(90, true));
+        let incidents = history.incidents();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].duration_seconds(), 60);
+        assert!(history.current_incident().is_none());
+        assert_eq!(history.checks(Some(2)).len(), 2);
+
+        let daily = history.daily_uptime();
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].uptime_percentage(), 50.0);
+    }
+
+    #[test]
+    fn test_incidents_from_checks() {
+        let records = vec![
+            check(0, false),
+            check(30, true),
+            check(60, true),
+            check(90, false),
+            check(120, false),
+        ];
+        let incidents = incidents_from_checks(&records);
+        assert_eq!(incidents.len(), 2);
+        assert_eq!(incidents[0].ended_at, Some(records[1].timestamp));
+        assert!(incidents[1].is_open());
+        assert_eq!(incidents[1].failed_checks, 2);
+
+        // Seeding keeps the ended incident and every day's counts, not the open incident
+        let mut history = SystemHistory::new();
+        history.seed(&records);
+        assert_eq!(history.incidents().len(), 1);
+        assert!(history.current_incident().is_none());
+        assert_eq!(history.daily_uptime()[0].total_checks, 5);
+        assert!(history.checks(None).is_empty());
+    }
+    #[tokio::test]
+    async fn test_store_rename() {
+        let directory = std::env::temp_dir().join(format!("system-monitor-history-{}", std::process::id()));
+        let store = HistoryStore::new(&directory, 90);
+        store.append("router-192.168.1.1-0-ping", &check(0, true)).await.unwrap();
+        store.rename("router-192.168.1.1-0-ping", "gateway-192.168.1.1-0-ping").await.unwrap();
+        assert!(store.load("router-192.168.1.1-0-ping", None, None).await.unwrap().is_empty());
+        assert_eq!(store.load("gateway-192.168.1.1-0-ping", None, None).await.unwrap().len(), 1);
+        // Nothing stored yet isn't an error
+        store.rename("unknown", "other").await.unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use crate::history::CheckTimings;
+use crate::probe::elapsed_ms;
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+const USER_AGENT: &str = concat!("system-monitor/", env!("CARGO_PKG_VERSION"));
+const MAX_STATUS_LINE: usize = 1024;
+
+// Failures that mean the service answered badly rather than the network failing
+#[derive(Debug)]
+pub enum HttpError {
+    Tls(std::io::Error),
+    Status(u16),
+    InvalidResponse,
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpError::Tls(e) => write!(f, "TLS handshake failed: {}", e),
+            HttpError::Status(status) => write!(f, "HTTP {}", status),
+            HttpError::InvalidResponse => write!(f, "Invalid HTTP response"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+pub struct HttpTarget<'a> {
+    pub host: &'a str,
+    pub address: IpAddr,
+    pub port: u16,
+    pub tls: bool,
+    pub path: &'a str,
+}
+
+// Sends a GET and waits for the status line only; 4xx and 5xx responses count as failures
+pub async fn check(target: &HttpTarget<'_>, limit: Duration) -> Result<CheckTimings> {
+    let mut timings = CheckTimings::default();
+
+    let connect_start = Instant::now();
+    let stream = timeout(limit, TcpStream::connect(SocketAddr::new(target.address, target.port))).await??;
+    timings.connect_ms = Some(elapsed_ms(connect_start));
+
+    if target.tls {
+        let server_name = ServerName::try_from(target.host.to_string())
+            .map_err(|_| anyhow::anyhow!("'{}' is not a valid TLS server name", target.host))?;
+        let tls_start = Instant::now();
+        let stream = timeout(limit, connector().connect(server_name, stream)).await?
+            .map_err(HttpError::Tls)?;
+        timings.tls_ms = Some(elapsed_ms(tls_start));
+        timings.first_byte_ms = Some(request(stream, target, limit).await?);
+    } else {
+        timings.first_byte_ms = Some(request(stream, target, limit).await?);
+    }
+    Ok(timings)
+}
+
+// Returns the time from sending the request to the first byte of the response
+async fn request<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, target: &HttpTarget<'_>, limit: Duration) -> Result<f64> {
+    let default_port = if target.tls { 443 } else { 80 };
+    let host = match target.host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]", ip),
+        _ => target.host.to_string(),
+    };
+    let host = if target.port == default_port { host } else { format!("{}:{}", host, target.port) };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        target.path, host, USER_AGENT,
+    );
+
+    let sent = Instant::now();
+    timeout(limit, stream.write_all(request.as_bytes())).await??;
+
+    let mut buffer = [0u8; MAX_STATUS_LINE];
+    let mut len = 0;
+    let mut first_byte_ms = None;
+    while !buffer[..len].contains(&b'\n') && len < buffer.len() {
+        let read = timeout(limit, stream.read(&mut buffer[len..])).await??;
+        if read == 0 {
+            break;
+        }
+        first_byte_ms.get_or_insert_with(|| elapsed_ms(sent));
+        len += read;
+    }
+    let first_byte_ms = first_byte_ms.ok_or_else(|| anyhow::anyhow!("Connection closed without a response"))?;
+
+    let status_line = String::from_utf8_lossy(&buffer[..len]);
+    let status: u16 = status_line.lines().next()
+        .filter(|line| line.starts_with("HTTP/"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or(HttpError::InvalidResponse)?;
+    if status >= 400 {
+        return Err(HttpError::Status(status).into());
+    }
+    Ok(first_byte_ms)
+}
+
+fn connector() -> TlsConnector {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+    });
+    TlsConnector::from(Arc::clone(config))
+}
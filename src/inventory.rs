@@ -0,0 +1,468 @@
+use crate::config::{Protocol, SystemConfig};
+use crate::export::ExportFormat;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Csv,
+    Json,
+    // /etc/hosts style: an address followed by names
+    Hosts,
+    // XML output of `nmap -oX`
+    Nmap,
+}
+
+impl ImportFormat {
+    // Guessed from the file name, CSV when nothing matches
+    pub fn detect(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => ImportFormat::Json,
+            "xml" => ImportFormat::Nmap,
+            _ if path.file_name().is_some_and(|name| name == "hosts") => ImportFormat::Hosts,
+            _ => ImportFormat::Csv,
+        }
+    }
+}
+
+// Column (CSV) or key (JSON) each field is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMapping {
+    pub name: String,
+    pub host: String,
+    pub port: String,
+    pub protocol: String,
+    pub group: String,
+    pub enabled: String,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            name: "name".to_string(),
+            host: "host".to_string(),
+            port: "port".to_string(),
+            protocol: "protocol".to_string(),
+            group: "group".to_string(),
+            enabled: "enabled".to_string(),
+        }
+    }
+}
+
+impl FieldMapping {
+    // "host=ip_address" reads the host from the ip_address column
+    pub fn set(&mut self, spec: &str) -> Result<()> {
+        let (field, column) = spec.split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Invalid mapping '{}', expected field=column", spec))?;
+        let column = column.trim().to_string();
+        match field.trim().to_ascii_lowercase().as_str() {
+            "name" => self.name = column,
+            "host" => self.host = column,
+            "port" => self.port = column,
+            "protocol" => self.protocol = column,
+            "group" => self.group = column,
+            "enabled" => self.enabled = column,
+            other => anyhow::bail!("Unknown field '{}', expected name, host, port, protocol, group or enabled", other),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    pub mapping: FieldMapping,
+    // For rows that don't name one
+    pub protocol: Protocol,
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RowStatus {
+    New,
+    // Same name or target as a configured system or an earlier row
+    Duplicate { of: String },
+    Invalid { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportRow {
+    // Record number in the file, counting from 1
+    pub row: usize,
+    pub system: Option<SystemConfig>,
+    pub status: RowStatus,
+}
+
+// What an import would do, row by row; nothing is changed until the new systems are added
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportPreview {
+    pub rows: Vec<ImportRow>,
+}
+
+impl ImportPreview {
+    pub fn new_systems(&self) -> Vec<SystemConfig> {
+        self.rows.iter()
+            .filter(|row| row.status == RowStatus::New)
+            .filter_map(|row| row.system.clone())
+            .collect()
+    }
+
+    // New, duplicate and invalid rows
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let count = |f: fn(&RowStatus) -> bool| self.rows.iter().filter(|row| f(&row.status)).count();
+        (
+            count(|s| *s == RowStatus::New),
+            count(|s| matches!(s, RowStatus::Duplicate { .. })),
+            count(|s| matches!(s, RowStatus::Invalid { .. })),
+        )
+    }
+}
+
+// Parses the file and checks every row with `validate`, e.g. for protocols without a probe, and
+// against `existing` and the rows before it
+pub fn preview(
+    content: &str,
+    options: &ImportOptions,
+    existing: &[SystemConfig],
+    validate: &dyn Fn(&SystemConfig) -> Result<()>,
+) -> Result<ImportPreview> {
+    let parsed = match options.format {
+        ImportFormat::Csv => parse_csv(content, options)?,
+        ImportFormat::Json => parse_json(content, options)?,
+        ImportFormat::Hosts => parse_hosts(content, options),
+        ImportFormat::Nmap => parse_nmap(content, options)?,
+    };
+
+    let mut rows: Vec<ImportRow> = Vec::new();
+    for (index, result) in parsed.into_iter().enumerate() {
+        let row = match result {
+            Ok(system) => {
+                if let Err(e) = validate(&system) {
+                    rows.push(ImportRow { row: index + 1, system: Some(system), status: RowStatus::Invalid { reason: e.to_string() } });
+                    continue;
+                }
+                let earlier = rows.iter()
+                    .filter(|r| r.status == RowStatus::New)
+                    .find(|r| r.system.as_ref().is_some_and(|s| same_system(s, &system)))
+                    .map(|r| format!("row {}", r.row));
+                let configured = existing.iter()
+                    .find(|s| same_system(s, &system))
+                    .map(|s| format!("configured system '{}'", s.name));
+                let status = match configured.or(earlier) {
+                    Some(of) => RowStatus::Duplicate { of },
+                    None => RowStatus::New,
+                };
+                ImportRow { row: index + 1, system: Some(system), status }
+            }
+            Err(reason) => ImportRow { row: index + 1, system: None, status: RowStatus::Invalid { reason } },
+        };
+        rows.push(row);
+    }
+    Ok(ImportPreview { rows })
+}
+
+fn same_system(a: &SystemConfig, b: &SystemConfig) -> bool {
+    a.name.eq_ignore_ascii_case(&b.name)
+        || (a.host.eq_ignore_ascii_case(&b.host) && a.effective_port() == b.effective_port() && a.protocol == b.protocol)
+}
+
+type ParsedRow = Result<SystemConfig, String>;
+
+// Builds a system from a row of text fields, see FieldMapping
+fn system_from_fields(fields: &BTreeMap<String, String>, options: &ImportOptions) -> ParsedRow {
+    let mapping = &options.mapping;
+    let field = |column: &str| fields.get(column).map(|v| v.trim()).filter(|v| !v.is_empty());
+
+    let host = field(&mapping.host).ok_or_else(|| format!("Missing {}", mapping.host))?;
+    if host.chars().any(char::is_whitespace) {
+        return Err(format!("Invalid host '{}'", host));
+    }
+    let port = field(&mapping.port)
+        .map(|p| p.parse::<u16>().map_err(|_| format!("Invalid port '{}'", p)))
+        .transpose()?;
+    let protocol = match field(&mapping.protocol) {
+        Some(protocol) => Protocol::from_str(protocol).map_err(|e| e.to_string())?,
+        None => options.protocol.clone(),
+    };
+    let enabled = match field(&mapping.enabled).map(str::to_ascii_lowercase).as_deref() {
+        None | Some("true" | "yes" | "y" | "1") => true,
+        Some("false" | "no" | "n" | "0") => false,
+        Some(other) => return Err(format!("Invalid enabled value '{}'", other)),
+    };
+
+    let name = field(&mapping.name).unwrap_or(host);
+    let mut system = SystemConfig::new(name.to_string(), host.to_string(), port, protocol);
+    system.enabled = enabled;
+    system.group = field(&mapping.group).map(str::to_string).or_else(|| options.group.clone());
+    Ok(system)
+}
+
+fn parse_csv(content: &str, options: &ImportOptions) -> Result<Vec<ParsedRow>> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).trim(csv::Trim::All).from_reader(content.as_bytes());
+    let headers = reader.headers()?.clone();
+    if !headers.iter().any(|h| h == options.mapping.host) {
+        anyhow::bail!("No '{}' column; map the host with host=<column>", options.mapping.host);
+    }
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = record
+            .map_err(|e| e.to_string())
+            .and_then(|record| {
+                let fields = headers.iter().zip(record.iter()).map(|(h, v)| (h.to_string(), v.to_string())).collect();
+                system_from_fields(&fields, options)
+            });
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// An array of objects. Objects in the shape `export_systems` writes keep all their settings
+// when the default mapping is used.
+fn parse_json(content: &str, options: &ImportOptions) -> Result<Vec<ParsedRow>> {
+    let value: serde_json::Value = serde_json::from_str(content)?;
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        // Also accepts {"systems": [...]}
+        serde_json::Value::Object(mut object) => match object.remove("systems") {
+            Some(serde_json::Value::Array(items)) => items,
+            _ => anyhow::bail!("Expected an array of systems"),
+        },
+        _ => anyhow::bail!("Expected an array of systems"),
+    };
+
+    let rows = items.into_iter()
+        .map(|item| {
+            let serde_json::Value::Object(object) = item else {
+                return Err("Not an object".to_string());
+            };
+            if options.mapping == FieldMapping::default() {
+                if let Ok(mut system) = serde_json::from_value::<SystemConfig>(serde_json::Value::Object(object.clone())) {
+                    system.group = system.group.or_else(|| options.group.clone());
+                    return Ok(system);
+                }
+            }
+            let fields = object.into_iter()
+                .filter_map(|(key, value)| {
+                    let text = match value {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Number(n) => n.to_string(),
+                        serde_json::Value::Bool(b) => b.to_string(),
+                        _ => return None,
+                    };
+                    Some((key, text))
+                })
+                .collect();
+            system_from_fields(&fields, options)
+        })
+        .collect();
+    Ok(rows)
+}
+
+// One row per line with an address; the first name becomes the system's name
+fn parse_hosts(content: &str, options: &ImportOptions) -> Vec<ParsedRow> {
+    content.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let address = fields.next().unwrap_or_default();
+            let ip: IpAddr = address.parse().map_err(|_| format!("Invalid address '{}'", address))?;
+            if ip.is_loopback() || ip.is_multicast() || ip.is_unspecified() {
+                return Err(format!("{} is not a remote host", ip));
+            }
+            let name = fields.next().unwrap_or(address);
+            let mut system = SystemConfig::new(name.to_string(), address.to_string(), None, options.protocol.clone());
+            system.group = options.group.clone();
+            Ok(system)
+        })
+        .collect()
+}
+
+// One row per host nmap found up. TCP systems check the first open port.
+fn parse_nmap(content: &str, options: &ImportOptions) -> Result<Vec<ParsedRow>> {
+    let document = roxmltree::Document::parse(content)?;
+    if document.root_element().tag_name().name() != "nmaprun" {
+        anyhow::bail!("Not nmap XML output, expected an <nmaprun> document");
+    }
+    let rows = document.root_element().children()
+        .filter(|node| node.has_tag_name("host"))
+        .filter(|host| child(*host, "status").and_then(|s| s.attribute("state")) != Some("down"))
+        .map(|host| {
+            let address = host.children()
+                .filter(|node| node.has_tag_name("address"))
+                .find(|node| matches!(node.attribute("addrtype"), Some("ipv4" | "ipv6")))
+                .and_then(|node| node.attribute("addr"))
+                .ok_or_else(|| "Host without an IP address".to_string())?;
+            let hostname = child(host, "hostnames")
+                .and_then(|names| child(names, "hostname"))
+                .and_then(|name| name.attribute("name"));
+            let open_port = child(host, "ports").and_then(|ports| {
+                ports.children()
+                    .filter(|port| port.has_tag_name("port") && port.attribute("protocol") == Some("tcp"))
+                    .find(|port| child(*port, "state").and_then(|s| s.attribute("state")) == Some("open"))
+                    .and_then(|port| port.attribute("portid"))
+                    .and_then(|id| id.parse::<u16>().ok())
+            });
+            let port = if options.protocol == Protocol::Tcp { open_port } else { None };
+            let mut system = SystemConfig::new(hostname.unwrap_or(address).to_string(), address.to_string(), port, options.protocol.clone());
+            system.group = options.group.clone();
+            Ok(system)
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|c| c.has_tag_name(name))
+}
+
+#[derive(Debug, Serialize)]
+struct SystemRow<'a> {
+    name: &'a str,
+    host: &'a str,
+    port: Option<u16>,
+    protocol: String,
+    group: Option<&'a str>,
+    enabled: bool,
+}
+
+// CSV has the columns the default mapping reads; JSON keeps every setting
+pub fn export_systems(systems: &[SystemConfig], format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for system in systems {
+                writer.serialize(SystemRow {
+                    name: &system.name,
+                    host: &system.host,
+                    port: system.port,
+                    protocol: system.protocol.name(),
+                    group: system.group.as_deref(),
+                    enabled: system.enabled,
+                })?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(systems)?),
+        ExportFormat::Jsonl => {
+            let mut output = String::new();
+            for system in systems {
+                output.push_str(&serde_json::to_string(system)?);
+                output.push('\n');
+            }
+            Ok(output)
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ImportFormat::Csv),
+            "json" => Ok(ImportFormat::Json),
+            "hosts" => Ok(ImportFormat::Hosts),
+            "nmap" | "xml" => Ok(ImportFormat::Nmap),
+            other => Err(anyhow::anyhow!("Unknown import format '{}', expected csv, json, hosts or nmap", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportFormat::Csv => write!(f, "CSV"),
+            ImportFormat::Json => write!(f, "JSON"),
+            ImportFormat::Hosts => write!(f, "hosts file"),
+            ImportFormat::Nmap => write!(f, "nmap XML"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(format: ImportFormat) -> ImportOptions {
+        ImportOptions { format, mapping: FieldMapping::default(), protocol: Protocol::Ping, group: Some("Imported".to_string()) }
+    }
+
+    fn valid(_: &SystemConfig) -> Result<()> {
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_csv_with_mapping() {
+        let existing = vec![SystemConfig::new("Router".to_string(), "10.0.0.1".to_string(), None, Protocol::Ping)];
+        let mut options = options(ImportFormat::Csv);
+        options.mapping.set("host=ip").unwrap();
+        options.mapping.set("name=hostname").unwrap();
+        let content = "hostname,ip,port,protocol,enabled\n\
+            web,10.0.0.10,443,https,yes\n\
+            gw,10.0.0.1,,,\n\
+            web-copy,10.0.0.10,443,HTTPS,\n\
+            bad,10.0.0.11,http,,\n\
+            ,,,,\n\
+            typo,10.0.0.12,443,htps,\n";
+        let known = |system: &SystemConfig| match &system.protocol {
+            Protocol::Custom(name) => anyhow::bail!("Unknown protocol '{}'", name),
+            _ => Ok(()),
+        };
+        let preview = preview(content, &options, &existing, &known).unwrap();
+        assert_eq!(preview.counts(), (1, 2, 3));
+        let web = preview.rows[0].system.as_ref().unwrap();
+        assert_eq!((web.protocol.clone(), web.port, web.group.as_deref()), (Protocol::Https, Some(443), Some("Imported")));
+        assert_eq!(preview.rows[1].status, RowStatus::Duplicate { of: "configured system 'Router'".to_string() });
+        assert_eq!(preview.rows[2].status, RowStatus::Duplicate { of: "row 1".to_string() });
+        assert_eq!(preview.rows[3].status, RowStatus::Invalid { reason: "Invalid port 'http'".to_string() });
+        assert_eq!(preview.rows[5].status, RowStatus::Invalid { reason: "Unknown protocol 'htps'".to_string() });
+        assert_eq!(preview.new_systems().len(), 1);
+    }
+
+    #[test]
+    fn test_preview_hosts_and_nmap() {
+        let hosts = "127.0.0.1 localhost\n# comment\n192.0.2.5  nas nas.local\n::1 ip6-localhost\n";
+        let preview_hosts = preview(hosts, &options(ImportFormat::Hosts), &[], &valid).unwrap();
+        assert_eq!(preview_hosts.counts(), (1, 0, 2));
+        assert_eq!(preview_hosts.new_systems()[0].name, "nas");
+
+        let nmap = r#"<?xml version="1.0"?><nmaprun scanner="nmap">
+            <host><status state="up"/><address addr="192.0.2.7" addrtype="ipv4"/><address addr="00:11:22:33:44:55" addrtype="mac"/>
+              <hostnames><hostname name="printer.lan" type="PTR"/></hostnames>
+              <ports><port protocol="tcp" portid="22"><state state="closed"/></port><port protocol="tcp" portid="9100"><state state="open"/></port></ports>
+            </host>
+            <host><status state="down"/><address addr="192.0.2.8" addrtype="ipv4"/></host>
+        </nmaprun>"#;
+        let mut nmap_options = options(ImportFormat::Nmap);
+        nmap_options.protocol = Protocol::Tcp;
+        let systems = preview(nmap, &nmap_options, &[], &valid).unwrap().new_systems();
+        assert_eq!(systems.len(), 1);
+        assert_eq!((systems[0].name.as_str(), systems[0].host.as_str(), systems[0].port), ("printer.lan", "192.0.2.7", Some(9100)));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut system = SystemConfig::new("api".to_string(), "api.example.com".to_string(), Some(8443), Protocol::Https);
+        system.group = Some("Web".to_string());
+        system.path = Some("/health".to_string());
+        let systems = vec![system];
+
+        let csv = export_systems(&systems, ExportFormat::Csv).unwrap();
+        assert_eq!(csv.lines().next(), Some("name,host,port,protocol,group,enabled"));
+        let from_csv = preview(&csv, &options(ImportFormat::Csv), &[], &valid).unwrap().new_systems();
+        assert_eq!((from_csv[0].protocol.clone(), from_csv[0].group.as_deref()), (Protocol::Https, Some("Web")));
+
+        let json = export_systems(&systems, ExportFormat::Json).unwrap();
+        let from_json = preview(&json, &options(ImportFormat::Json), &[], &valid).unwrap().new_systems();
+        assert_eq!(from_json[0].path.as_deref(), Some("/health"));
+        assert_eq!(preview(&json, &options(ImportFormat::Json), &systems, &valid).unwrap().counts(), (0, 1, 0));
+    }
+}
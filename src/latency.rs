@@ -0,0 +1,233 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Every quantile is within 1% of the true value
+const RELATIVE_ACCURACY: f64 = 0.01;
+// Anything faster than this is counted as zero
+const MIN_TRACKED_MS: f64 = 0.001;
+// Bucket bounds for histograms, in milliseconds
+pub const HISTOGRAM_BOUNDS_MS: [f64; 12] = [1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+pub const QUANTILES: [f64; 4] = [0.5, 0.9, 0.95, 0.99];
+
+// Mergeable quantile sketch with logarithmic bins (DDSketch). Two sketches merge into the
+// sketch of the combined samples, so hourly sketches add up to daily and weekly ones.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencySketch {
+    bins: BTreeMap<i32, u64>,
+    zero_count: u64,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+fn bin_index(value: f64) -> i32 {
+    (value.ln() / gamma().ln()).ceil() as i32
+}
+
+// Representative value of a bin, within the relative accuracy of every value in it
+fn bin_value(index: i32) -> f64 {
+    2.0 * gamma().powi(index) / (gamma() + 1.0)
+}
+
+impl LatencySketch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, value_ms: f64) {
+        let value_ms = value_ms.max(0.0);
+        if value_ms < MIN_TRACKED_MS {
+            self.zero_count += 1;
+        } else {
+            *self.bins.entry(bin_index(value_ms)).or_default() += 1;
+        }
+        if self.count == 0 {
+            self.min = value_ms;
+            self.max = value_ms;
+        } else {
+            self.min = self.min.min(value_ms);
+            self.max = self.max.max(value_ms);
+        }
+        self.count += 1;
+        self.sum += value_ms;
+    }
+
+    pub fn merge(&mut self, other: &LatencySketch) {
+        if other.count == 0 {
+            return;
+        }
+        for (index, count) in &other.bins {
+            *self.bins.entry(*index).or_default() += count;
+        }
+        if self.count == 0 {
+            self.min = other.min;
+            self.max = other.max;
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.zero_count += other.zero_count;
+        self.count += other.count;
+        self.sum += other.sum;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64).round() as u64;
+        let mut seen = self.zero_count;
+        if rank < seen {
+            return Some(0.0);
+        }
+        for (index, count) in &self.bins {
+            seen += count;
+            if rank < seen {
+                return Some(bin_value(*index).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    // Number of samples at or below `bound_ms`, as cumulative histogram buckets count them
+    pub fn count_at_most(&self, bound_ms: f64) -> u64 {
+        self.zero_count + self.bins.iter()
+            .filter(|(index, _)| bin_value(**index) <= bound_ms)
+            .map(|(_, count)| count)
+            .sum::<u64>()
+    }
+
+    pub fn summary(&self, window: &str) -> LatencySummary {
+        LatencySummary {
+            window: window.to_string(),
+            count: self.count,
+            mean_ms: (self.count > 0).then(|| self.sum / self.count as f64),
+            min_ms: (self.count > 0).then_some(self.min),
+            max_ms: (self.count > 0).then_some(self.max),
+            p50_ms: self.quantile(0.5),
+            p90_ms: self.quantile(0.9),
+            p95_ms: self.quantile(0.95),
+            p99_ms: self.quantile(0.99),
+        }
+    }
+
+    pub fn histogram(&self) -> Vec<HistogramBucket> {
+        HISTOGRAM_BOUNDS_MS.iter()
+            .map(|&le_ms| HistogramBucket { le_ms, count: self.count_at_most(le_ms) })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub window: String,
+    pub count: u64,
+    pub mean_ms: Option<f64>,
+    pub min_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+// Cumulative: `count` includes every sample at or below `le_ms`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistogramBucket {
+    pub le_ms: f64,
+    pub count: u64,
+}
+
+// Latency sketches per UTC hour over a rolling retention period
+#[derive(Debug, Clone, Default)]
+pub struct LatencyWindows {
+    hourly: BTreeMap<DateTime<Utc>, LatencySketch>,
+    lifetime: LatencySketch,
+}
+
+pub const HOURLY_RETENTION_HOURS: i64 = 24 * 7;
+
+impl LatencyWindows {
+    pub fn add(&mut self, timestamp: DateTime<Utc>, value_ms: f64) {
+        self.hourly.entry(hour_of(timestamp)).or_default().add(value_ms);
+        self.lifetime.add(value_ms);
+        let cutoff = hour_of(timestamp) - Duration::hours(HOURLY_RETENTION_HOURS);
+        while self.hourly.first_key_value().is_some_and(|(hour, _)| *hour <= cutoff) {
+            self.hourly.pop_first();
+        }
+    }
+
+    // Every sample since the monitor started; what Prometheus histograms are built from
+    pub fn lifetime(&self) -> &LatencySketch {
+        &self.lifetime
+    }
+
+    // Hours starting within `duration` of now are merged, so windows have hourly granularity
+    pub fn window(&self, duration: Duration) -> LatencySketch {
+        let since = hour_of(Utc::now() - duration);
+        let mut merged = LatencySketch::new();
+        for sketch in self.hourly.range(since..).map(|(_, sketch)| sketch) {
+            merged.merge(sketch);
+        }
+        merged
+    }
+
+    pub fn summaries(&self) -> Vec<LatencySummary> {
+        [("1h", Duration::hours(1)), ("24h", Duration::hours(24)), ("7d", Duration::days(7))]
+            .into_iter()
+            .map(|(label, duration)| self.window(duration).summary(label))
+            .collect()
+    }
+}
+
+// One sketch per UTC hour, for exports over arbitrary ranges
+pub fn hourly_sketches(samples: impl IntoIterator<Item = (DateTime<Utc>, f64)>) -> BTreeMap<DateTime<Utc>, LatencySketch> {
+    let mut hourly: BTreeMap<DateTime<Utc>, LatencySketch> = BTreeMap::new();
+    for (timestamp, value_ms) in samples {
+        hourly.entry(hour_of(timestamp)).or_default().add(value_ms);
+    }
+    hourly
+}
+
+pub fn hour_of(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    timestamp.duration_trunc(Duration::hours(1)).unwrap_or(timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_quantiles_and_merge() {
+        let mut low = LatencySketch::new();
+        let mut high = LatencySketch::new();
+        for i in 1..=500 {
+            low.add(i as f64);
+            high.add((i + 500) as f64);
+        }
+        low.merge(&high);
+
+        assert_eq!(low.count(), 1000);
+        for (q, expected) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+            let estimate = low.quantile(q).unwrap();
+            assert!((estimate - expected).abs() / expected <= 0.02, "q{} = {}", q, estimate);
+        }
+        assert_eq!(low.quantile(1.0), Some(1000.0));
+        assert_eq!(low.count_at_most(5000.0), 1000);
+        assert!(LatencySketch::new().quantile(0.5).is_none());
+    }
+}
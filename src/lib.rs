@@ -1,3 +1,31 @@
+pub mod api;
+pub mod cli;
 pub mod config;
+pub mod config_files;
+pub mod discovery;
+pub mod events;
+pub mod exec_check;
+pub mod export;
+pub mod health;
+pub mod heartbeat;
+pub mod history;
+pub mod inventory;
+pub mod http_check;
+pub mod latency;
+pub mod local_check;
+pub mod metrics;
+pub mod migrations;
 pub mod monitor;
-pub mod ui;
\ No newline at end of file
+pub mod net_check;
+pub mod probe;
+pub mod process_check;
+pub mod report;
+pub mod resolve;
+pub mod scheduler;
+pub mod sla;
+pub mod state;
+pub mod status_page;
+pub mod trace;
+pub mod ui;
+
+pub use probe::{Probe, ProbeError, ProbeFuture, ProbeRegistry, ProbeResult, ProbeTarget};
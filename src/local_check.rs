@@ -0,0 +1,316 @@
+use crate::health::{Degradation, Severity};
+use crate::history::FailureKind;
+use crate::probe::{Probe, ProbeError, ProbeFuture, ProbeResult, ProbeTarget};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::timeout;
+
+// How long CPU usage is sampled on the first check, when there is no earlier sample
+const CPU_SAMPLE_TIME: Duration = Duration::from_millis(250);
+// A hung network mount must not hang the check
+const STATVFS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    // 1 minute load average per CPU
+    #[default]
+    Load,
+    Cpu,
+    Memory,
+    Swap,
+    Disk,
+    Inodes,
+}
+
+impl Resource {
+    // Warning and critical limits when the settings don't give any
+    fn default_limits(self) -> (f64, f64) {
+        match self {
+            Resource::Load => (1.0, 2.0),
+            Resource::Cpu => (80.0, 95.0),
+            Resource::Memory => (85.0, 95.0),
+            Resource::Swap => (50.0, 80.0),
+            Resource::Disk | Resource::Inodes => (80.0, 90.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalSettings {
+    pub resource: Resource,
+    // Mount point for disk and inode checks
+    pub mount: String,
+    // Percent used, or load per CPU; see `Resource::default_limits`
+    pub warning: Option<f64>,
+    pub critical: Option<f64>,
+}
+
+impl Default for LocalSettings {
+    fn default() -> Self {
+        Self { resource: Resource::Load, mount: "/".to_string(), warning: None, critical: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuSample {
+    pub busy: u64,
+    pub total: u64,
+}
+
+// Checks a resource of the machine the monitor runs on, reading /proc and statvfs
+pub struct LocalProbe {
+    settings: LocalSettings,
+    // CPU usage is measured between two checks
+    previous_cpu: Mutex<Option<CpuSample>>,
+}
+
+impl LocalProbe {
+    pub fn new(settings: LocalSettings) -> Self {
+        Self { settings, previous_cpu: Mutex::new(None) }
+    }
+
+    async fn read(&self) -> Result<Reading> {
+        match self.settings.resource {
+            Resource::Load => {
+                let (one, five, fifteen) = parse_loadavg(&read_proc("/proc/loadavg").await?)
+                    .context("Unexpected /proc/loadavg format")?;
+                let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+                Ok(Reading {
+                    value: one / cpus as f64,
+                    label: "load".to_string(),
+                    unit: " per CPU",
+                    summary: format!("load {:.2} {:.2} {:.2} on {} CPU{}", one, five, fifteen, cpus, if cpus == 1 { "" } else { "s" }),
+                    metadata: BTreeMap::from([
+                        ("load1".to_string(), format!("{:.2}", one)),
+                        ("load5".to_string(), format!("{:.2}", five)),
+                        ("load15".to_string(), format!("{:.2}", fifteen)),
+                        ("cpus".to_string(), cpus.to_string()),
+                    ]),
+                })
+            }
+            Resource::Cpu => {
+                let sample = read_cpu_sample().await?;
+                let previous = self.previous_cpu.lock().ok().and_then(|mut previous| previous.replace(sample));
+                let (earlier, later) = match previous {
+                    Some(previous) => (previous, sample),
+                    None => {
+                        tokio::time::sleep(CPU_SAMPLE_TIME).await;
+                        let later = read_cpu_sample().await?;
+                        if let Ok(mut previous) = self.previous_cpu.lock() {
+                            *previous = Some(later);
+                        }
+                        (sample, later)
+                    }
+                };
+                let percent = cpu_percent(earlier, later);
+                Ok(Reading {
+                    value: percent,
+                    label: "CPU".to_string(),
+                    unit: "%",
+                    summary: format!("CPU {:.1}% busy", percent),
+                    metadata: BTreeMap::from([("used_percent".to_string(), format!("{:.1}", percent))]),
+                })
+            }
+            Resource::Memory | Resource::Swap => {
+                let content = read_proc("/proc/meminfo").await?;
+                let meminfo = parse_meminfo(&content);
+                let field = |name: &str| meminfo.get(name).copied().context(format!("No {} in /proc/meminfo", name));
+                let (label, total, used) = if self.settings.resource == Resource::Memory {
+                    let total = field("MemTotal")?;
+                    ("memory", total, total.saturating_sub(field("MemAvailable")?))
+                } else {
+                    let total = field("SwapTotal")?;
+                    ("swap", total, total.saturating_sub(field("SwapFree")?))
+                };
+                Ok(usage(label.to_string(), used * 1024, total * 1024))
+            }
+            Resource::Disk | Resource::Inodes => {
+                let mount = self.settings.mount.clone();
+                let stats = timeout(STATVFS_TIMEOUT, tokio::task::spawn_blocking(move || filesystem_usage(&mount)))
+                    .await
+                    .with_context(|| format!("statvfs of {} timed out", self.settings.mount))???;
+                if self.settings.resource == Resource::Disk {
+                    Ok(usage(format!("disk {}", self.settings.mount), stats.used_bytes, stats.total_bytes))
+                } else {
+                    let (used, total) = (stats.used_inodes, stats.total_inodes);
+                    let percent = if total == 0 { 0.0 } else { used as f64 / total as f64 * 100.0 };
+                    Ok(Reading {
+                        value: percent,
+                        label: format!("inodes {}", self.settings.mount),
+                        unit: "%",
+                        summary: format!("inodes {} {:.1}% used ({} of {})", self.settings.mount, percent, used, total),
+                        metadata: BTreeMap::from([
+                            ("used_percent".to_string(), format!("{:.1}", percent)),
+                            ("used".to_string(), used.to_string()),
+                            ("total".to_string(), total.to_string()),
+                        ]),
+                    })
+                }
+            }
+        }
+    }
+}
+
+struct Reading {
+    // Compared against the limits
+    value: f64,
+    label: String,
+    unit: &'static str,
+    summary: String,
+    metadata: BTreeMap<String, String>,
+}
+
+fn usage(label: String, used: u64, total: u64) -> Reading {
+    let percent = if total == 0 { 0.0 } else { used as f64 / total as f64 * 100.0 };
+    Reading {
+        value: percent,
+        summary: format!("{} {:.1}% used ({} of {})", label, percent, format_bytes(used), format_bytes(total)),
+        label,
+        unit: "%",
+        metadata: BTreeMap::from([
+            ("used_percent".to_string(), format!("{:.1}", percent)),
+            ("used_bytes".to_string(), used.to_string()),
+            ("total_bytes".to_string(), total.to_string()),
+        ]),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+struct FilesystemUsage {
+    used_bytes: u64,
+    total_bytes: u64,
+    used_inodes: u64,
+    total_inodes: u64,
+}
+
+#[cfg(unix)]
+fn filesystem_usage(mount: &str) -> Result<FilesystemUsage> {
+    let stats = nix::sys::statvfs::statvfs(mount).with_context(|| format!("statvfs of {} failed", mount))?;
+    let block = stats.fragment_size() as u64;
+    let used_bytes = (stats.blocks() as u64).saturating_sub(stats.blocks_free() as u64) * block;
+    let total_inodes = stats.files() as u64;
+    Ok(FilesystemUsage {
+        used_bytes,
+        // Like df, space reserved for root counts as neither used nor available
+        total_bytes: used_bytes + stats.blocks_available() as u64 * block,
+        used_inodes: total_inodes.saturating_sub(stats.files_free() as u64),
+        total_inodes,
+    })
+}
+
+#[cfg(not(unix))]
+fn filesystem_usage(_mount: &str) -> Result<FilesystemUsage> {
+    anyhow::bail!("Disk checks are only supported on Unix")
+}
+
+async fn read_proc(path: &str) -> Result<String> {
+    tokio::fs::read_to_string(path).await.with_context(|| format!("Failed to read {}", path))
+}
+
+async fn read_cpu_sample() -> Result<CpuSample> {
+    parse_cpu_sample(&read_proc("/proc/stat").await?).context("Unexpected /proc/stat format")
+}
+
+// "0.52 0.58 0.59 1/389 12345"
+pub fn parse_loadavg(content: &str) -> Option<(f64, f64, f64)> {
+    let mut fields = content.split_whitespace().map(|field| field.parse::<f64>());
+    Some((fields.next()?.ok()?, fields.next()?.ok()?, fields.next()?.ok()?))
+}
+
+// The aggregate "cpu" line: user nice system idle iowait irq softirq steal guest guest_nice.
+// Guest time is already part of user time.
+pub fn parse_cpu_sample(content: &str) -> Option<CpuSample> {
+    let line = content.lines().find(|line| line.starts_with("cpu "))?;
+    let values: Vec<u64> = line.split_whitespace().skip(1).take(8).map(|v| v.parse().ok()).collect::<Option<_>>()?;
+    let total: u64 = values.iter().sum();
+    let idle = values.get(3)? + values.get(4).unwrap_or(&0);
+    Some(CpuSample { busy: total - idle, total })
+}
+
+pub fn cpu_percent(earlier: CpuSample, later: CpuSample) -> f64 {
+    let total = later.total.saturating_sub(earlier.total);
+    if total == 0 {
+        return 0.0;
+    }
+    later.busy.saturating_sub(earlier.busy) as f64 / total as f64 * 100.0
+}
+
+// Values in kB by field name
+pub fn parse_meminfo(content: &str) -> HashMap<&str, u64> {
+    content.lines()
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            Some((name, rest.split_whitespace().next()?.parse().ok()?))
+        })
+        .collect()
+}
+
+impl Probe for LocalProbe {
+    fn check<'a>(&'a self, _target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let reading = self.read().await
+                .map_err(|e| ProbeError::new(FailureKind::Unknown, format!("{:#}", e)))?;
+
+            let (default_warning, default_critical) = self.settings.resource.default_limits();
+            let warning = self.settings.warning.unwrap_or(default_warning);
+            let critical = self.settings.critical.unwrap_or(default_critical);
+            let breached = if reading.value >= critical {
+                Some((Severity::Critical, critical))
+            } else if reading.value >= warning {
+                Some((Severity::Warning, warning))
+            } else {
+                None
+            };
+            let degradation = breached.map(|(severity, limit)| Degradation {
+                severity,
+                reasons: vec![format!("{} {:.1}{} over {}{}", reading.label, reading.value, reading.unit, limit, reading.unit)],
+            });
+
+            Ok(ProbeResult {
+                metadata: reading.metadata,
+                degradation,
+                output: Some(reading.summary),
+                ..ProbeResult::default()
+            })
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_files() {
+        assert_eq!(parse_loadavg("0.52 0.58 0.59 1/389 12345\n"), Some((0.52, 0.58, 0.59)));
+
+        let earlier = parse_cpu_sample("cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 100 0 50 800 50 0 0 0 0 0\n").unwrap();
+        assert_eq!(earlier, CpuSample { busy: 150, total: 1000 });
+        let later = parse_cpu_sample("cpu  175 0 75 900 50 0 0 0 0 0\n").unwrap();
+        assert_eq!(cpu_percent(earlier, later), 50.0);
+
+        let meminfo = parse_meminfo("MemTotal:       16303412 kB\nMemFree:         1203400 kB\nMemAvailable:    8151706 kB\nHugePages_Total:       0\n");
+        assert_eq!(meminfo.get("MemAvailable"), Some(&8151706));
+        assert_eq!(meminfo.get("HugePages_Total"), Some(&0));
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024 / 2), "1.5 GiB");
+    }
+}
@@ -1,39 +1,122 @@
+use clap::Parser;
 use eframe::egui;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
-mod config;
-mod monitor;
-mod ui;
-
-use config::Config;
-use monitor::MonitorManager;
+use system_monitor::api::{self, ApiState};
+use system_monitor::cli::{self, Cli};
+use system_monitor::config::{Config, SharedConfig};
+use system_monitor::history::{self, HistoryStore};
+use system_monitor::monitor::MonitorManager;
+use system_monitor::state::{self, StateStore};
+use system_monitor::{heartbeat, net_check, status_page, ui};
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
+    let args = Cli::parse();
 
     // Create the main runtime
     let rt = tokio::runtime::Runtime::new()?;
+
+    if let Some(command) = args.command {
+        return rt.block_on(async {
+            match command {
+                // The schema doesn't depend on the configuration, so it's written even when there's
+                // no valid one yet
+                cli::Command::ConfigSchema(schema) => cli::run_config_schema(schema).await,
+                command => {
+                    let config = Config::load_or_create(&args.config).await?;
+                    cli::run(command, &config, &args.config).await
+                }
+            }
+        });
+    }
+    let config_path = args.config;
     
     // Run async setup
     let (config, monitor_manager) = rt.block_on(async {
         // Load or create configuration
-        let config = Config::load_or_create("monitor_config.toml").await?;
+        let config = Config::load_or_create(&config_path).await?;
         
         // Initialize monitor manager
-        let monitor_manager = Arc::new(RwLock::new(MonitorManager::new()));
+        let mut manager = MonitorManager::new()
+            .with_check_interval(std::time::Duration::from_secs(config.check_interval_seconds))
+            .with_default_thresholds(config.default_thresholds.clone())
+            .with_scheduler_config(config.scheduler.clone());
+        if config.history.enabled {
+            let store = HistoryStore::new(&config.history.directory, config.history.retention_days);
+            manager = manager.with_history_store(store);
+        }
+        if config.state.enabled {
+            let path = config.state.resolve_path(&config_path);
+            match StateStore::new(&path).load().await {
+                Ok(saved) => manager = manager.with_saved_state(saved),
+                Err(e) => log::warn!("Ignoring saved state in {}: {}", path.display(), e),
+            }
+        }
+        let monitor_manager = Arc::new(RwLock::new(manager));
         
         // Start monitoring systems from config
         {
             let mut manager = monitor_manager.write().await;
             for system in &config.systems {
-                manager.add_system(system.clone()).await?;
+                // One bad entry, e.g. an unknown custom protocol, shouldn't keep the others from running
+                if let Err(e) = manager.add_system(system.clone()).await {
+                    log::error!("Skipping system {}: {}", system.name, e);
+                }
+            }
+            if config.local_network.enabled {
+                match net_check::discover(&config.local_network).await {
+                    Ok(systems) => {
+                        for system in systems {
+                            if let Err(e) = manager.add_builtin_system(system.clone()).await {
+                                log::error!("Skipping local system {}: {}", system.name, e);
+                            }
+                        }
+                    }
+                    Err(e) => log::warn!("Failed to discover the local network: {}", e),
+                }
             }
         }
         
         Ok::<_, anyhow::Error>((config, monitor_manager))
     })?;
 
+    // The GUI and the API change and save the same configuration
+    let shared_config: SharedConfig = Arc::new(Mutex::new(config.clone()));
+
+    if config.api.enabled {
+        let state = rt.block_on(ApiState::new(Arc::clone(&monitor_manager), Arc::clone(&shared_config), &config_path));
+        let bind_address = config.api.bind_address.clone();
+        rt.spawn(async move {
+            if let Err(e) = api::serve(bind_address, state).await {
+                log::error!("API server stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(bind_address) = config.heartbeat.udp_bind_address.clone() {
+        let manager = Arc::clone(&monitor_manager);
+        rt.spawn(async move {
+            if let Err(e) = heartbeat::run_udp_listener(bind_address, manager).await {
+                log::error!("Heartbeat listener stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(store) = rt.block_on(monitor_manager.read()).history_store() {
+        rt.spawn(history::run_pruner(store));
+    }
+
+    if config.status_page.output_directory.is_some() {
+        rt.spawn(status_page::run_writer(config.status_page.clone(), Arc::clone(&monitor_manager)));
+    }
+
+    if config.state.enabled {
+        let path = config.state.resolve_path(&config_path);
+        rt.spawn(state::run_writer(config.state.clone(), path, Arc::clone(&monitor_manager)));
+    }
+
     // Start the GUI
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -41,12 +124,10 @@ fn main() -> anyhow::Result<()> {
         ..Default::default()
     };
 
-    let app = ui::MonitorApp::new(config, monitor_manager, rt);
-    
     let result = eframe::run_native(
         "System Uptime Monitor",
         options,
-        Box::new(|_cc| Box::new(app)),
+        Box::new(move |cc| Box::new(ui::MonitorApp::new(shared_config, config_path, monitor_manager, rt, cc.egui_ctx.clone()))),
     );
     
     match result {
@@ -0,0 +1,98 @@
+use crate::health::SystemState;
+use crate::latency::{HISTOGRAM_BOUNDS_MS, QUANTILES};
+use crate::monitor::MonitorManager;
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// Prometheus text exposition of every system's state and latency
+pub fn render(manager: &MonitorManager) -> String {
+    let systems = manager.get_systems();
+    let mut out = String::new();
+
+    let labels = |name: &str, group: Option<&str>| {
+        format!("system=\"{}\",group=\"{}\"", escape(name), escape(group.unwrap_or("")))
+    };
+
+    out.push_str("# HELP system_monitor_up Whether the last check succeeded.\n# TYPE system_monitor_up gauge\n");
+    for system in &systems {
+        let _ = writeln!(out, "system_monitor_up{{{}}} {}",
+            labels(&system.config.name, system.config.group.as_deref()), system.is_online as u8);
+    }
+
+    out.push_str("# HELP system_monitor_state Current state of the system, 1 for the active state.\n# TYPE system_monitor_state gauge\n");
+    for system in &systems {
+        for (state, label) in [(SystemState::Up, "up"), (SystemState::Degraded, "degraded"), (SystemState::Down, "down")] {
+            let _ = writeln!(out, "system_monitor_state{{{},state=\"{}\"}} {}",
+                labels(&system.config.name, system.config.group.as_deref()), label, (system.state == state) as u8);
+        }
+    }
+
+    out.push_str("# HELP system_monitor_checks_total Checks run since the monitor started.\n# TYPE system_monitor_checks_total counter\n");
+    for system in &systems {
+        let _ = writeln!(out, "system_monitor_checks_total{{{}}} {}",
+            labels(&system.config.name, system.config.group.as_deref()), system.total_checks);
+    }
+
+    out.push_str("# HELP system_monitor_failed_checks_total Failed checks since the monitor started.\n# TYPE system_monitor_failed_checks_total counter\n");
+    for system in &systems {
+        let _ = writeln!(out, "system_monitor_failed_checks_total{{{}}} {}",
+            labels(&system.config.name, system.config.group.as_deref()), system.total_checks - system.successful_checks);
+    }
+
+    out.push_str("# HELP system_monitor_response_time_seconds Response time of successful checks.\n# TYPE system_monitor_response_time_seconds histogram\n");
+    for system in &systems {
+        let Some(sketch) = manager.get_latency_sketch(system.id) else {
+            continue;
+        };
+        let labels = labels(&system.config.name, system.config.group.as_deref());
+        for bound in HISTOGRAM_BOUNDS_MS {
+            let _ = writeln!(out, "system_monitor_response_time_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound / 1000.0, sketch.count_at_most(bound));
+        }
+        let _ = writeln!(out, "system_monitor_response_time_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, sketch.count());
+        let _ = writeln!(out, "system_monitor_response_time_seconds_sum{{{}}} {}", labels, sketch.sum() / 1000.0);
+        let _ = writeln!(out, "system_monitor_response_time_seconds_count{{{}}} {}", labels, sketch.count());
+    }
+
+    out.push_str("# HELP system_monitor_response_time_quantile_seconds Response time percentiles over the last hour.\n# TYPE system_monitor_response_time_quantile_seconds gauge\n");
+    for system in &systems {
+        let Some(report) = manager.get_latency(system.id) else {
+            continue;
+        };
+        let Some(last_hour) = report.windows.first() else {
+            continue;
+        };
+        let labels = labels(&system.config.name, system.config.group.as_deref());
+        let values = [last_hour.p50_ms, last_hour.p90_ms, last_hour.p95_ms, last_hour.p99_ms];
+        for (quantile, value) in QUANTILES.iter().zip(values) {
+            if let Some(value) = value {
+                let _ = writeln!(out, "system_monitor_response_time_quantile_seconds{{{},quantile=\"{}\"}} {}",
+                    labels, quantile, value / 1000.0);
+            }
+        }
+    }
+
+    let scheduler = manager.scheduler_stats();
+    out.push_str("# HELP system_monitor_scheduler_checks Scheduled systems, and due checks waiting for a permit or running.\n# TYPE system_monitor_scheduler_checks gauge\n");
+    for (label, value) in [("scheduled", scheduler.scheduled), ("waiting", scheduler.waiting), ("running", scheduler.running)] {
+        let _ = writeln!(out, "system_monitor_scheduler_checks{{state=\"{}\"}} {}", label, value);
+    }
+    out.push_str("# HELP system_monitor_scheduler_started_total Checks started by the scheduler.\n# TYPE system_monitor_scheduler_started_total counter\n");
+    let _ = writeln!(out, "system_monitor_scheduler_started_total {}", scheduler.checks_started);
+    out.push_str("# HELP system_monitor_scheduler_lag_seconds How late checks started over the last hour.\n# TYPE system_monitor_scheduler_lag_seconds gauge\n");
+    if let Some(last_hour) = scheduler.lag.first() {
+        let values = [last_hour.p50_ms, last_hour.p90_ms, last_hour.p95_ms, last_hour.p99_ms];
+        for (quantile, value) in QUANTILES.iter().zip(values) {
+            if let Some(value) = value {
+                let _ = writeln!(out, "system_monitor_scheduler_lag_seconds{{quantile=\"{}\"}} {}", quantile, value / 1000.0);
+            }
+        }
+    }
+
+    out
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,111 @@
+use crate::config::Protocol;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+// Layout written by this build. A change that older files need upgrading for bumps this and
+// adds a step to MIGRATIONS.
+pub const CONFIG_VERSION: u32 = 1;
+
+// MIGRATIONS[n] upgrades a version n layout to version n + 1; files without a `version` are
+// version 0. Steps see the main file and each included file, which only hold `systems`,
+// `templates` and `include`, and leave `version` to `migrate`.
+const MIGRATIONS: &[fn(&mut Table) -> Result<()>] = &[normalize_protocol_names];
+
+// Version of a raw configuration file
+pub fn version_of(table: &Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(Value::Integer(version)) => u32::try_from(*version)
+            .map_err(|_| anyhow::anyhow!("Invalid config version {}", version)),
+        Some(_) => anyhow::bail!("'version' must be a number"),
+    }
+}
+
+// Upgrades a file of `from` to the current layout in place. The main file also gets its
+// `version` set, as its first key.
+pub fn migrate(table: &mut Table, from: u32, is_main: bool) -> Result<()> {
+    if from > CONFIG_VERSION {
+        anyhow::bail!("Config version {} is newer than this build supports ({}), please upgrade", from, CONFIG_VERSION);
+    }
+    for (version, step) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        step(table).with_context(|| format!("Failed to upgrade the config from version {}", version))?;
+    }
+    if is_main {
+        let mut upgraded = Table::from_iter([("version".to_string(), Value::Integer(CONFIG_VERSION.into()))]);
+        upgraded.extend(std::mem::take(table).into_iter().filter(|(key, _)| key != "version"));
+        *table = upgraded;
+    }
+    Ok(())
+}
+
+// Copy of a file about to be upgraded: monitor_config.toml.v0.bak. An existing backup is kept,
+// it is the older of the two.
+pub async fn write_backup(path: &Path, version: u32) -> Result<Option<PathBuf>> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup = path.with_file_name(name);
+    if tokio::fs::try_exists(&backup).await.unwrap_or(false) {
+        return Ok(None);
+    }
+    tokio::fs::copy(path, &backup).await
+        .with_context(|| format!("Failed to back up {} to {}", path.display(), backup.display()))?;
+    Ok(Some(backup))
+}
+
+// 0 to 1: built-in protocols written in another case, e.g. "ping", were read as custom
+// protocols and so missed the handling specific to them. They're spelled as the variant now.
+fn normalize_protocol_names(table: &mut Table) -> Result<()> {
+    let normalize = |entry: &mut Table| -> Result<()> {
+        let Some(Value::String(name)) = entry.get("protocol") else {
+            return Ok(());
+        };
+        let protocol: Protocol = name.parse()?;
+        if !matches!(protocol, Protocol::Custom(_)) {
+            entry.insert("protocol".to_string(), Value::try_from(protocol)?);
+        }
+        Ok(())
+    };
+    if let Some(Value::Array(systems)) = table.get_mut("systems") {
+        for system in systems.iter_mut().filter_map(Value::as_table_mut) {
+            normalize(system)?;
+        }
+    }
+    if let Some(Value::Table(templates)) = table.get_mut("templates") {
+        for (_, template) in templates.iter_mut() {
+            if let Some(template) = template.as_table_mut() {
+                normalize(template)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_from_unversioned() {
+        let mut table: Table = toml::from_str(r#"
+check_interval_seconds = 30
+
+[[systems]]
+name = "Router"
+protocol = "ping"
+
+[[systems]]
+name = "Queue"
+protocol = "amqp"
+"#).unwrap();
+        let from = version_of(&table).unwrap();
+        assert_eq!(from, 0);
+        migrate(&mut table, from, true).unwrap();
+        assert_eq!(table.keys().next().map(String::as_str), Some("version"));
+        assert_eq!(version_of(&table).unwrap(), CONFIG_VERSION);
+        assert_eq!(table["systems"][0]["protocol"].as_str(), Some("Ping"));
+        assert_eq!(table["systems"][1]["protocol"].as_str(), Some("amqp"));
+
+        assert!(migrate(&mut table, CONFIG_VERSION + 1, true).is_err());
+    }
+}
@@ -1,14 +1,24 @@
-use crate::config::{Protocol, SystemConfig};
+use crate::config::{interval_of, Protocol, SchedulerConfig, SharedConfig, SystemConfig, ThresholdSettings, TraceMethod, MIN_INTERVAL};
+use crate::config_files::SystemOrigin;
+use crate::events::{MonitorEvent, EVENT_CHANNEL_CAPACITY};
+use crate::health::{self, Degradation, SystemState};
+use crate::heartbeat::{Heartbeat, TokenIndex};
+use crate::history::{CheckRecord, CheckTimings, DailyUptime, FailureKind, HistoryStore, Incident, SystemHistory, DAILY_RETENTION_DAYS};
+use crate::latency::{HistogramBucket, LatencySketch, LatencySummary};
+use crate::probe::{elapsed_ms, Probe, ProbeRegistry, ProbeResult, ProbeTarget};
+use crate::resolve::DnsResolver;
+use crate::scheduler::{self, CheckRunner, Scheduler, SchedulerStats};
+use crate::state::{SavedStatus, StateSnapshot};
+use crate::trace::{self, TraceState};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use std::net::{SocketAddr, ToSocketAddrs, IpAddr};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::net::{TcpStream, UdpSocket};
-use tokio::time::{sleep, timeout};
-use surge_ping::{Client, Config, PingIdentifier, PingSequence};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +26,10 @@ pub struct SystemStatus {
     pub id: Uuid,
     pub config: SystemConfig,
     pub is_online: bool,
+    #[serde(default)]
+    pub state: SystemState,
+    #[serde(default)]
+    pub degradation: Option<Degradation>,
     pub last_check: DateTime<Utc>,
     pub last_online: Option<DateTime<Utc>>,
     pub last_offline: Option<DateTime<Utc>>,
@@ -24,14 +38,83 @@ pub struct SystemStatus {
     pub total_checks: u64,
     pub successful_checks: u64,
     pub error_message: Option<String>,
+    // Per-address results of the last check; more than one in dual-stack mode
+    #[serde(default)]
+    pub addresses: Vec<AddressStatus>,
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    // Phase breakdown of the last check; response_time_ms excludes DNS
+    #[serde(default)]
+    pub timings: CheckTimings,
+    // What the probe reported about the target in the last check, e.g. a plugin's output
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub last_heartbeat: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyReport {
+    pub windows: Vec<LatencySummary>,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressStatus {
+    pub address: IpAddr,
+    pub is_online: bool,
+    pub response_time_ms: Option<u64>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    #[serde(default)]
+    pub timings: CheckTimings,
+    // Extra details from the probe, e.g. the TTL of a ping reply
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degradation: Option<Degradation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+struct CheckOutcome {
+    is_online: bool,
+    response_time: Option<u64>,
+    error: Option<String>,
+    failure: Option<FailureKind>,
+    timings: CheckTimings,
+    addresses: Vec<AddressStatus>,
+    // Reported by the probe, on top of the threshold checks
+    degradation: Option<Degradation>,
+    output: Option<String>,
+}
+
+impl CheckOutcome {
+    fn failed(failure: FailureKind, error: String) -> Self {
+        Self {
+            is_online: false,
+            response_time: None,
+            error: Some(error),
+            failure: Some(failure),
+            timings: CheckTimings::default(),
+            addresses: Vec::new(),
+            degradation: None,
+            output: None,
+        }
+    }
+}
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 impl SystemStatus {
     pub fn new(config: SystemConfig) -> Self {
         Self {
             id: Uuid::new_v4(),
             config,
             is_online: false,
+            state: SystemState::Down,
+            degradation: None,
             last_check: Utc::now(),
             last_online: None,
             last_offline: None,
@@ -40,11 +123,52 @@ impl SystemStatus {
             total_checks: 0,
             successful_checks: 0,
             error_message: None,
+            addresses: Vec::new(),
+            failure: None,
+            timings: CheckTimings::default(),
+            output: None,
+            last_heartbeat: None,
         }
     }
 
-    pub fn update_status(&mut self, is_online: bool, response_time: Option<u64>, error: Option<String>) {
+    // Picks up where the previous run left off, including the id so API clients keep working
+    fn restore(&mut self, saved: SavedStatus) {
+        self.id = saved.id;
+        self.is_online = saved.is_online;
+        self.state = saved.state;
+        self.degradation = saved.degradation;
+        self.last_check = saved.last_check;
+        self.last_online = saved.last_online;
+        self.last_offline = saved.last_offline;
+        self.response_time_ms = saved.response_time_ms;
+        self.total_checks = saved.total_checks;
+        self.successful_checks = saved.successful_checks;
+        self.error_message = saved.error_message;
+        self.failure = saved.failure;
+        self.last_heartbeat = saved.last_heartbeat;
+        self.uptime_percentage = if self.total_checks > 0 {
+            (self.successful_checks as f64 / self.total_checks as f64) * 100.0
+        } else {
+            0.0
+        };
+    }
+
+    // Returns true when the system moved between up, degraded and down, or between degraded severities
+    pub fn update_status(
+        &mut self,
+        is_online: bool,
+        response_time: Option<u64>,
+        error: Option<String>,
+        degradation: Option<Degradation>,
+    ) -> bool {
         let now = Utc::now();
+        let state = match (is_online, &degradation) {
+            (false, _) => SystemState::Down,
+            (true, Some(_)) => SystemState::Degraded,
+            (true, None) => SystemState::Up,
+        };
+        let severity = |d: &Option<Degradation>| d.as_ref().map(|d| d.severity);
+        let changed = state != self.state || severity(&degradation) != severity(&self.degradation);
         
         self.last_check = now;
         self.total_checks += 1;
@@ -62,141 +186,1031 @@ impl SystemStatus {
             log::warn!("{} is now OFFLINE", self.config.name);
         }
 
+        match &degradation {
+            Some(d) if changed => log::warn!("{} is now DEGRADED: {}", self.config.name, d.reasons.join(", ")),
+            None if changed && self.state == SystemState::Degraded && is_online => {
+                log::info!("{} is no longer degraded", self.config.name);
+            }
+            _ => {}
+        }
+
         self.is_online = is_online;
+        self.state = state;
+        self.degradation = degradation;
         self.uptime_percentage = if self.total_checks > 0 {
             (self.successful_checks as f64 / self.total_checks as f64) * 100.0
         } else {
             0.0
         };
+
+        changed
     }
 }
 
 pub struct MonitorManager {
     systems: Arc<DashMap<Uuid, SystemStatus>>,
-    monitoring_tasks: DashMap<Uuid, tokio::task::JoinHandle<()>>,
+    histories: Arc<DashMap<Uuid, SystemHistory>>,
+    traces: Arc<DashMap<Uuid, TraceState>>,
+    check_states: Arc<DashMap<Uuid, CheckState>>,
+    scheduler: Scheduler,
+    order: Vec<Uuid>,
+    events: broadcast::Sender<MonitorEvent>,
+    history_store: Option<Arc<HistoryStore>>,
+    resolver: Arc<DnsResolver>,
+    // Statuses from the previous run, taken by `add_system` for the matching system
+    saved: DashMap<String, SavedStatus>,
+    // Used for systems without their own interval
+    check_interval: Duration,
+    default_thresholds: Option<ThresholdSettings>,
+    registry: ProbeRegistry,
+    // Probe of every system, built from its settings when it is added or updated
+    probes: Arc<DashMap<Uuid, Arc<dyn Probe>>>,
+    // Systems the monitor discovered itself, which aren't part of the configuration
+    builtin: HashSet<Uuid>,
+    heartbeat_tokens: TokenIndex,
+    // Lowercased system names, to find parents by
+    names: Arc<DashMap<String, Vec<Uuid>>>,
 }
 
 impl MonitorManager {
     pub fn new() -> Self {
         Self {
             systems: Arc::new(DashMap::new()),
-            monitoring_tasks: DashMap::new(),
+            histories: Arc::new(DashMap::new()),
+            traces: Arc::new(DashMap::new()),
+            check_states: Arc::new(DashMap::new()),
+            scheduler: Scheduler::new(SchedulerConfig::default()),
+            order: Vec::new(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            history_store: None,
+            resolver: Arc::new(DnsResolver::new()),
+            saved: DashMap::new(),
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            default_thresholds: None,
+            registry: ProbeRegistry::builtin(),
+            probes: Arc::new(DashMap::new()),
+            builtin: HashSet::new(),
+            heartbeat_tokens: TokenIndex::default(),
+            names: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    // Replaces the built-in probes, e.g. with `ProbeRegistry::builtin()` plus custom ones
+    pub fn with_probe_registry(mut self, registry: ProbeRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    // Records a heartbeat for the system with the token, see heartbeat.rs. The system is
+    // then due again after its period and grace time.
+    pub async fn record_heartbeat(&self, token: &str, heartbeat: Heartbeat) -> Result<Uuid> {
+        let (id, config) = self.heartbeat_tokens.get(token)
+            .and_then(|id| Some((id, self.systems.get(&id)?.config.clone())))
+            .ok_or_else(|| anyhow::anyhow!("No heartbeat system with token {}", token))?;
+        let timeout = self.probes.get(&id).and_then(|probe| probe.heartbeat_timeout())
+            .ok_or_else(|| anyhow::anyhow!("{} is not a heartbeat system", config.name))?;
+        if let Some(mut system) = self.systems.get_mut(&id) {
+            system.last_heartbeat = Some(Utc::now());
+        }
+
+        if config.enabled {
+            let success = heartbeat.is_success();
+            let error = (!success).then(|| {
+                let code = heartbeat.exit_code.unwrap_or_default();
+                match &heartbeat.message {
+                    Some(message) => format!("Exited with code {}: {}", code, message),
+                    None => format!("Exited with code {}", code),
+                }
+            });
+            let duration_ms = heartbeat.duration_ms.map(|ms| ms as f64);
+            let outcome = CheckOutcome {
+                is_online: success,
+                response_time: heartbeat.duration_ms,
+                error,
+                failure: (!success).then_some(FailureKind::Response),
+                timings: CheckTimings { connect_ms: duration_ms, total_ms: duration_ms, ..CheckTimings::default() },
+                addresses: Vec::new(),
+                degradation: None,
+                output: heartbeat.message,
+            };
+            self.checker().record(id, &config, outcome).await;
+        }
+        self.schedule_checks(id, tokio::time::Instant::now() + timeout);
+        Ok(id)
+    }
+
+    // Fails for protocols without a probe and for invalid probe settings. `id` is the system the
+    // settings are for, None for a new one.
+    pub fn validate_probe(&self, config: &SystemConfig, id: Option<Uuid>) -> Result<()> {
+        self.create_probe(config, id).map(|_| ())
+    }
+
+    fn create_probe(&self, config: &SystemConfig, id: Option<Uuid>) -> Result<Arc<dyn Probe>> {
+        let probe = self.registry.create(config)?;
+        // Anyone with the token can report, so it is chosen rather than derived from the system
+        if probe.heartbeat_timeout().is_some() && probe.heartbeat_token().is_none() {
+            anyhow::bail!("{} needs a `token` in its probe settings to receive heartbeats", config.name);
+        }
+        // Heartbeats are matched by token alone, a second system with it would never get any
+        if let Some(owner) = probe.heartbeat_token().and_then(|token| self.heartbeat_tokens.get(token)) {
+            if Some(owner) != id {
+                let other = self.systems.get(&owner).map(|s| s.config.name.clone()).unwrap_or_default();
+                anyhow::bail!("{} uses the same heartbeat token as {}", config.name, other);
+            }
+        }
+        Ok(probe)
+    }
+
+    // Protocol names that can be checked
+    pub fn protocols(&self) -> Vec<String> {
+        self.registry.names()
+    }
+
+    pub fn with_default_thresholds(mut self, thresholds: Option<ThresholdSettings>) -> Self {
+        self.default_thresholds = thresholds;
+        self
+    }
+
+    pub fn with_scheduler_config(mut self, config: SchedulerConfig) -> Self {
+        self.scheduler = Scheduler::new(config);
+        self
+    }
+
+    pub fn scheduler_stats(&self) -> SchedulerStats {
+        self.scheduler.stats()
+    }
+
+    pub fn with_saved_state(mut self, saved: HashMap<String, SavedStatus>) -> Self {
+        self.saved.extend(saved);
+        self
+    }
+
+    pub fn with_history_store(mut self, store: HistoryStore) -> Self {
+        self.history_store = Some(Arc::new(store));
+        self
+    }
+
+    pub fn history_store(&self) -> Option<Arc<HistoryStore>> {
+        self.history_store.clone()
+    }
+
+    // Every check result, state change and configuration change is published here
+    pub fn subscribe(&self) -> broadcast::Receiver<MonitorEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: MonitorEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    fn index_name(&self, id: Uuid, name: &str) {
+        self.names.entry(name.to_lowercase()).or_default().push(id);
+    }
+
+    fn unindex_name(&self, id: Uuid, name: &str) {
+        self.names.remove_if_mut(&name.to_lowercase(), |_, ids| {
+            ids.retain(|existing| *existing != id);
+            ids.is_empty()
+        });
+    }
+
+    // The status saved under the system's key, else the only one saved for its target: the same
+    // system before a rename
+    fn take_saved(&self, config: &SystemConfig) -> Option<SavedStatus> {
+        if let Some((_, saved)) = self.saved.remove(&config.storage_key()) {
+            return Some(saved);
+        }
+        let target = config.target_key();
+        let keys: Vec<String> = self.saved.iter()
+            .filter(|saved| saved.target == target)
+            .map(|saved| saved.key().clone())
+            .collect();
+        match keys.as_slice() {
+            [key] => self.saved.remove(key).map(|(_, saved)| saved),
+            _ => None,
         }
     }
 
     pub async fn add_system(&mut self, config: SystemConfig) -> Result<Uuid> {
-        let status = SystemStatus::new(config);
+        let probe = self.create_probe(&config, None)?;
+        let mut status = SystemStatus::new(config);
+        let mut history = SystemHistory::new();
+        // Two systems with the same target can't share an id
+        let saved = self.take_saved(&status.config).filter(|saved| !self.systems.contains_key(&saved.id));
+        if let Some(store) = &self.history_store {
+            let key = status.config.storage_key();
+            // Renamed in the file since the last run: its checks are still under the old name
+            if let Some(saved) = saved.as_ref().filter(|saved| saved.key != key) {
+                if let Err(e) = store.rename(&saved.key, &key).await {
+                    log::warn!("Failed to move the stored history of {}: {}", status.config.name, e);
+                }
+            }
+            // Day counts and past incidents otherwise start over on every restart
+            let from = Utc::now() - chrono::Duration::days(DAILY_RETENTION_DAYS as i64);
+            match store.load(&key, Some(from), None).await {
+                Ok(records) => history.seed(&records),
+                Err(e) => log::warn!("Failed to read the stored history of {}: {}", status.config.name, e),
+            }
+        }
+        if let Some(saved) = saved {
+            history.resume_incidents(saved.open_incidents.clone());
+            status.restore(saved);
+        }
         let id = status.id;
-        
+        let config_name = status.config.name.clone();
+        let event = MonitorEvent::SystemAdded {
+            system_id: id,
+            name: status.config.name.clone(),
+            group: status.config.group.clone(),
+        };
+
+        // Spread first checks over the interval so systems don't all fire together. Heartbeat
+        // clients get a full period to report in after a restart.
+        let interval = status.config.next_interval(SystemState::Up, 0, self.check_interval);
+        let delay = probe.heartbeat_timeout()
+            .unwrap_or_else(|| scheduler::phase(&status.config.storage_key(), interval));
         self.systems.insert(id, status);
-        self.start_monitoring_task(id).await?;
-        
+        self.histories.insert(id, history);
+        if let Some(token) = probe.heartbeat_token() {
+            self.heartbeat_tokens.insert(token, id);
+        }
+        self.index_name(id, &config_name);
+        self.probes.insert(id, probe);
+        self.order.push(id);
+        self.schedule_checks(id, tokio::time::Instant::now() + delay);
+        // Only announced once it exists, subscribers may look it up straight away
+        self.publish(event);
+
         Ok(id)
     }
 
+    // Adds a system such as the local gateway that the monitor found by itself. It is checked
+    // like any other but left out of `system_configs`, so it is never saved to the config file.
+    pub async fn add_builtin_system(&mut self, config: SystemConfig) -> Result<Uuid> {
+        let id = self.add_system(config).await?;
+        self.builtin.insert(id);
+        Ok(id)
+    }
+
+    pub fn is_builtin(&self, id: Uuid) -> bool {
+        self.builtin.contains(&id)
+    }
+
+    // Systems from included files are never saved, so changes to them would silently be lost
+    pub fn ensure_editable(&self, id: Uuid) -> Result<()> {
+        match self.systems.get(&id) {
+            Some(system) if system.config.origin == Some(SystemOrigin::Included) => {
+                anyhow::bail!("{} is defined in an included file, change it there", system.config.name)
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn remove_system(&mut self, id: Uuid) {
-        self.systems.remove(&id);
-        if let Some((_, task)) = self.monitoring_tasks.remove(&id) {
-            task.abort();
+        if let Some((_, status)) = self.systems.remove(&id) {
+            self.unindex_name(id, &status.config.name);
+            self.publish(MonitorEvent::SystemRemoved {
+                system_id: id,
+                name: status.config.name,
+                group: status.config.group,
+            });
         }
+        self.histories.remove(&id);
+        self.traces.remove(&id);
+        self.order.retain(|existing| *existing != id);
+        self.check_states.remove(&id);
+        self.probes.remove(&id);
+        self.heartbeat_tokens.remove(id);
+        self.builtin.remove(&id);
+        self.scheduler.remove(id);
+    }
+
+    // Replaces the configuration of an existing system and restarts its checks.
+    // Counters are kept unless the target itself (host, port or protocol) changed.
+    pub async fn update_system(&mut self, id: Uuid, mut config: SystemConfig) -> Result<()> {
+        self.ensure_editable(id)?;
+        let probe = self.create_probe(&config, Some(id))?;
+        let event = MonitorEvent::SystemUpdated {
+            system_id: id,
+            name: config.name.clone(),
+            group: config.group.clone(),
+        };
+        let renamed = {
+            let mut status = self.systems.get_mut(&id)
+                .ok_or_else(|| anyhow::anyhow!("System {} not found", id))?;
+            // Still saved as the entry it was loaded from
+            config.origin = status.config.origin;
+            if status.config.name != config.name {
+                self.unindex_name(id, &status.config.name);
+                self.index_name(id, &config.name);
+            }
+            let target_changed = status.config.host != config.host
+                || status.config.port != config.port
+                || status.config.protocol != config.protocol;
+            if target_changed {
+                *status = SystemStatus { id, ..SystemStatus::new(config) };
+                self.histories.insert(id, SystemHistory::new());
+                self.traces.remove(&id);
+                self.check_states.remove(&id);
+                None
+            } else {
+                let keys = (status.config.storage_key(), config.storage_key());
+                status.config = config;
+                (keys.0 != keys.1).then_some(keys)
+            }
+        };
+        // Stored checks follow a renamed system, or exports and reports would lose them
+        if let (Some(store), Some((from, to))) = (&self.history_store, renamed) {
+            if let Err(e) = store.rename(&from, &to).await {
+                log::warn!("Failed to move the stored history from {} to {}: {}", from, to, e);
+            }
+        }
+
+        let delay = probe.heartbeat_timeout().unwrap_or_default();
+        self.heartbeat_tokens.remove(id);
+        if let Some(token) = probe.heartbeat_token() {
+            self.heartbeat_tokens.insert(token, id);
+        }
+        self.probes.insert(id, probe);
+        // Check the changed system straight away, then at its usual cadence
+        self.schedule_checks(id, tokio::time::Instant::now() + delay);
+        self.publish(event);
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, id: Uuid, enabled: bool) -> Result<()> {
+        let event = {
+            let mut status = self.systems.get_mut(&id)
+                .ok_or_else(|| anyhow::anyhow!("System {} not found", id))?;
+            status.config.enabled = enabled;
+            MonitorEvent::SystemUpdated {
+                system_id: id,
+                name: status.config.name.clone(),
+                group: status.config.group.clone(),
+            }
+        };
+        self.publish(event);
+        Ok(())
     }
 
     pub fn get_systems(&self) -> Vec<SystemStatus> {
-        self.systems.iter().map(|entry| entry.value().clone()).collect()
+        self.order.iter()
+            .filter_map(|id| self.get_system(*id))
+            .collect()
     }
 
     pub fn get_system(&self, id: Uuid) -> Option<SystemStatus> {
         self.systems.get(&id).map(|entry| entry.value().clone())
     }
 
-    async fn start_monitoring_task(&self, id: Uuid) -> Result<()> {
-        let systems = Arc::clone(&self.systems);
-        
-        let task = tokio::spawn(async move {
-            loop {
-                if let Some(mut system_ref) = systems.get_mut(&id) {
-                    let config = system_ref.config.clone();
-                    
-                    if config.enabled {
-                        let (is_online, response_time, error) = 
-                            Self::check_system_status(&config).await;
-                        
-                        system_ref.update_status(is_online, response_time, error);
+    // System configurations in the order they were added, suitable for saving back to disk
+    pub fn system_configs(&self) -> Vec<SystemConfig> {
+        self.get_systems().into_iter()
+            .filter(|status| !self.builtin.contains(&status.id))
+            .map(|status| status.config)
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        let systems = self.get_systems().into_iter()
+            .map(|status| SavedStatus {
+                key: status.config.storage_key(),
+                target: status.config.target_key(),
+                id: status.id,
+                is_online: status.is_online,
+                state: status.state,
+                degradation: status.degradation,
+                last_check: status.last_check,
+                last_online: status.last_online,
+                last_offline: status.last_offline,
+                response_time_ms: status.response_time_ms,
+                total_checks: status.total_checks,
+                successful_checks: status.successful_checks,
+                error_message: status.error_message,
+                failure: status.failure,
+                last_heartbeat: status.last_heartbeat,
+                open_incidents: self.histories.get(&status.id).map(|h| h.open_incidents()).unwrap_or_default(),
+            })
+            .collect();
+        StateSnapshot { saved_at: Utc::now(), systems }
+    }
+
+    pub fn get_history(&self, id: Uuid, limit: Option<usize>) -> Option<Vec<CheckRecord>> {
+        self.histories.get(&id).map(|history| history.checks(limit))
+    }
+
+    pub fn get_incidents(&self, id: Uuid) -> Option<Vec<Incident>> {
+        self.histories.get(&id).map(|history| history.incidents())
+    }
+
+    pub fn get_daily_uptime(&self, id: Uuid) -> Option<Vec<DailyUptime>> {
+        self.histories.get(&id).map(|history| history.daily_uptime())
+    }
+
+    // Percentiles over the rolling windows plus a 24 hour histogram
+    pub fn get_latency(&self, id: Uuid) -> Option<LatencyReport> {
+        self.histories.get(&id).map(|history| LatencyReport {
+            windows: history.latency().summaries(),
+            histogram: history.latency().window(chrono::Duration::hours(24)).histogram(),
+        })
+    }
+
+    pub fn get_latency_sketch(&self, id: Uuid) -> Option<LatencySketch> {
+        self.histories.get(&id).map(|history| history.latency().lifetime().clone())
+    }
+
+    pub fn get_trace(&self, id: Uuid) -> Option<TraceState> {
+        self.traces.get(&id).map(|state| state.clone())
+    }
+
+    // Starts a path analysis in the background; returns false if one is already running
+    pub fn start_trace(&self, id: Uuid) -> Result<bool> {
+        let config = self.get_system(id)
+            .ok_or_else(|| anyhow::anyhow!("System {} not found", id))?
+            .config;
+        Ok(Self::spawn_trace(id, config, Arc::clone(&self.traces), self.events.clone(), Arc::clone(&self.resolver)))
+    }
+
+    fn spawn_trace(
+        id: Uuid,
+        config: SystemConfig,
+        traces: Arc<DashMap<Uuid, TraceState>>,
+        events: broadcast::Sender<MonitorEvent>,
+        resolver: Arc<DnsResolver>,
+    ) -> bool {
+        {
+            let mut state = traces.entry(id).or_default();
+            if state.running {
+                return false;
+            }
+            state.running = true;
+        }
+
+        tokio::spawn(async move {
+            let method = config.trace_method();
+            // UDP traces rely on "port unreachable" from the target, so they use the traceroute ports
+            let port = (method == TraceMethod::Tcp).then(|| config.effective_port().unwrap_or(80));
+            let target = resolver.resolve(&config.host, config.address_family, config.dns_server.as_deref()).await
+                .map(|resolution| resolution.addresses[0]);
+            let result = match target {
+                Ok(target) => trace::trace(target, method, port, &config.trace_settings()).await,
+                Err(e) => {
+                    if let Some(mut state) = traces.get_mut(&id) {
+                        state.running = false;
                     }
-                } else {
-                    // System was removed, exit task
-                    break;
+                    log::warn!("Path analysis for {} failed: {}", config.name, e);
+                    return;
                 }
-                
-                sleep(Duration::from_secs(30)).await; // Default check interval
+            };
+
+            log::info!("Path analysis for {} finished after {} hops (reached: {})",
+                config.name, result.hops.len(), result.reached);
+            let reached = result.reached;
+            let route_change = match traces.get_mut(&id) {
+                Some(mut state) => state.record(result, &config.trace_settings()),
+                // System was removed while tracing
+                None => return,
+            };
+            if let Some(change) = route_change {
+                log::warn!("Route to {} changed", config.name);
+                let _ = events.send(MonitorEvent::RouteChanged {
+                    system_id: id,
+                    name: config.name.clone(),
+                    group: config.group.clone(),
+                    timestamp: change.timestamp,
+                    old_hops: change.old_hops,
+                    new_hops: change.new_hops,
+                });
             }
+            let _ = events.send(MonitorEvent::TraceCompleted {
+                system_id: id,
+                name: config.name.clone(),
+                group: config.group.clone(),
+                reached,
+            });
         });
-
-        self.monitoring_tasks.insert(id, task);
-        Ok(())
+        true
     }
 
-    async fn check_system_status(config: &SystemConfig) -> (bool, Option<u64>, Option<String>) {
-        let start_time = std::time::Instant::now();
-        
-        let result = match config.protocol {
-            Protocol::Ping => Self::ping_check(&config.host).await,
-            Protocol::Tcp => Self::tcp_check(&config.host, config.port.unwrap_or(80)).await,
-            Protocol::Udp => Self::udp_check(&config.host, config.port.unwrap_or(53)).await,
+    // Checks in a time range, read from the on-disk store when there is one so the range
+    // isn't limited to what is kept in memory
+    pub async fn load_checks(
+        &self,
+        id: Uuid,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Option<Vec<CheckRecord>>> {
+        let Some(status) = self.get_system(id) else {
+            return Ok(None);
         };
+        if let Some(store) = &self.history_store {
+            return Ok(Some(store.load(&status.config.storage_key(), from, to).await?));
+        }
+        let checks = self.get_history(id, None).unwrap_or_default()
+            .into_iter()
+            .filter(|record| from.is_none_or(|from| record.timestamp >= from))
+            .filter(|record| to.is_none_or(|to| record.timestamp <= to))
+            .collect();
+        Ok(Some(checks))
+    }
 
-        let response_time = start_time.elapsed().as_millis() as u64;
+    // Hands the system to the scheduler, which runs its first check at `due`
+    fn schedule_checks(&self, id: Uuid, due: tokio::time::Instant) {
+        let Some(config) = self.systems.get(&id).map(|s| s.config.clone()) else {
+            return;
+        };
+        self.scheduler.start(self.runner());
+        self.scheduler.schedule(id, config.protocol.name(), due);
+    }
 
-        match result {
-            Ok(()) => (true, Some(response_time), None),
-            Err(e) => (false, None, Some(e.to_string())),
+    fn checker(&self) -> Checker {
+        Checker {
+            systems: Arc::clone(&self.systems),
+            histories: Arc::clone(&self.histories),
+            traces: Arc::clone(&self.traces),
+            check_states: Arc::clone(&self.check_states),
+            probes: Arc::clone(&self.probes),
+            events: self.events.clone(),
+            history_store: self.history_store.clone(),
+            resolver: Arc::clone(&self.resolver),
+            default_interval: self.check_interval,
+            default_thresholds: self.default_thresholds.clone(),
+            names: Arc::clone(&self.names),
         }
     }
 
-    async fn ping_check(host: &str) -> Result<()> {
-        // Try using system ping command as fallback
-        let output = tokio::process::Command::new("ping")
-            .args(&["-c", "1", "-W", "5", host])
-            .output()
-            .await?;
+    fn runner(&self) -> CheckRunner {
+        let checker = self.checker();
+        Arc::new(move |id| Box::pin(checker.clone().run(id)))
+    }
 
-        if output.status.success() {
-            Ok(())
+    async fn check_system_status(config: &SystemConfig, probe: Arc<dyn Probe>, resolver: &DnsResolver) -> CheckOutcome {
+        let (addresses, dns_ms) = if probe.is_local() {
+            (vec![IpAddr::from(std::net::Ipv4Addr::LOCALHOST)], None)
         } else {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            Err(anyhow::anyhow!("Ping failed: {}", stderr))
+            match resolver.resolve(&config.host, config.address_family, config.dns_server.as_deref()).await {
+                Ok(resolution) => (resolution.addresses, resolution.elapsed_ms),
+                Err(e) => return CheckOutcome::failed(FailureKind::Resolution, e.to_string()),
+            }
+        };
+
+        // Every address is checked concurrently so dual-stack checks take no longer than one
+        let mut checks = tokio::task::JoinSet::new();
+        for (index, address) in addresses.into_iter().enumerate() {
+            let config = config.clone();
+            let probe = Arc::clone(&probe);
+            checks.spawn(async move { (index, Self::check_address(&config, probe.as_ref(), address, dns_ms).await) });
         }
-    }
+        let mut results = Vec::new();
+        while let Some(joined) = checks.join_next().await {
+            match joined {
+                Ok(result) => results.push(result),
+                Err(e) => log::error!("Check task for {} failed: {}", config.name, e),
+            }
+        }
+        results.sort_by_key(|(index, _)| *index);
+        let results: Vec<AddressStatus> = results.into_iter().map(|(_, status)| status).collect();
 
-    async fn tcp_check(host: &str, port: u16) -> Result<()> {
-        let addr = format!("{}:{}", host, port);
-        let socket_addr: SocketAddr = addr.to_socket_addrs()?.next()
-            .ok_or_else(|| anyhow::anyhow!("Could not resolve address"))?;
-        
-        timeout(
-            Duration::from_secs(5),
-            TcpStream::connect(socket_addr)
-        ).await??;
-        
-        Ok(())
+        // A dual-stack system is only online when every address answers
+        let is_online = !results.is_empty() && results.iter().all(|r| r.is_online);
+        let response_time = if is_online {
+            results.iter().filter_map(|r| r.response_time_ms).max()
+        } else {
+            None
+        };
+        let error = match results.as_slice() {
+            [single] => single.error.clone(),
+            _ if is_online => None,
+            _ => Some(results.iter()
+                .filter_map(|r| r.error.as_ref().map(|e| format!("{}: {}", r.address, e)))
+                .collect::<Vec<_>>()
+                .join("; ")),
+        };
+
+        let failure = results.iter().find_map(|r| r.failure);
+        // Report the phases of the slowest address, the one that determined the response time
+        let timings = results.iter()
+            .map(|r| r.timings)
+            .max_by(|a, b| a.total_ms.unwrap_or(0.0).total_cmp(&b.total_ms.unwrap_or(0.0)))
+            .unwrap_or_default();
+
+        let degradation = if is_online {
+            results.iter().filter_map(|r| r.degradation.clone()).reduce(merge_degradation)
+        } else {
+            None
+        };
+        let output = results.iter().find_map(|r| r.output.clone());
+
+        CheckOutcome { is_online, response_time, error, failure, timings, addresses: results, degradation, output }
     }
 
-    async fn udp_check(host: &str, port: u16) -> Result<()> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        let addr = format!("{}:{}", host, port);
-        
-        // Send a simple UDP packet
-        timeout(
-            Duration::from_secs(5),
-            socket.send_to(b"ping", &addr)
-        ).await??;
-        
-        Ok(())
+    async fn check_address(config: &SystemConfig, probe: &dyn Probe, address: IpAddr, dns_ms: Option<f64>) -> AddressStatus {
+        let start_time = Instant::now();
+        let target = ProbeTarget {
+            config,
+            address,
+            port: config.effective_port().or(probe.default_port()),
+        };
+
+        match probe.check(&target).await {
+            Ok(ProbeResult { mut timings, metadata, degradation, output }) => {
+                timings.dns_ms = dns_ms;
+                timings.total_ms = Some(dns_ms.unwrap_or(0.0) + elapsed_ms(start_time));
+                // The network phases only, so process start-up and DNS don't count as latency.
+                // Probes without any, e.g. commands and local checks, have no response time.
+                let network_ms = [timings.connect_ms, timings.tls_ms, timings.first_byte_ms]
+                    .into_iter()
+                    .flatten()
+                    .reduce(|total, phase| total + phase);
+                AddressStatus {
+                    address,
+                    is_online: true,
+                    response_time_ms: network_ms.map(|ms| ms.round() as u64),
+                    error: None,
+                    failure: None,
+                    timings,
+                    metadata,
+                    degradation,
+                    output,
+                }
+            }
+            Err(e) => AddressStatus {
+                address,
+                is_online: false,
+                response_time_ms: None,
+                error: Some(e.message),
+                failure: Some(e.kind),
+                timings: CheckTimings {
+                    dns_ms,
+                    total_ms: Some(dns_ms.unwrap_or(0.0) + elapsed_ms(start_time)),
+                    ..CheckTimings::default()
+                },
+                metadata: BTreeMap::new(),
+                degradation: None,
+                output: e.output,
+            },
+        }
     }
 }
 
+// Copies the manager's systems into the shared configuration and writes it out. The config is
+// locked before the manager and for the whole write, so the GUI and the API can't save over
+// each other's changes.
+pub async fn save_config(config: &SharedConfig, manager: &tokio::sync::RwLock<MonitorManager>, path: &str) -> Result<()> {
+    let mut config = config.lock().await;
+    config.systems = manager.read().await.system_configs();
+    config.save_to_file(path).await
+}
+
+// Worst severity of the two, with the reasons of both
+fn merge_degradation(mut first: Degradation, second: Degradation) -> Degradation {
+    first.severity = first.severity.max(second.severity);
+    first.reasons.extend(second.reasons);
+    first
+}
+
 impl Default for MonitorManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+// Everything a check needs, shared with the scheduler's tasks
+#[derive(Clone)]
+struct Checker {
+    systems: Arc<DashMap<Uuid, SystemStatus>>,
+    histories: Arc<DashMap<Uuid, SystemHistory>>,
+    traces: Arc<DashMap<Uuid, TraceState>>,
+    check_states: Arc<DashMap<Uuid, CheckState>>,
+    probes: Arc<DashMap<Uuid, Arc<dyn Probe>>>,
+    events: broadcast::Sender<MonitorEvent>,
+    history_store: Option<Arc<HistoryStore>>,
+    resolver: Arc<DnsResolver>,
+    default_interval: Duration,
+    default_thresholds: Option<ThresholdSettings>,
+    names: Arc<DashMap<String, Vec<Uuid>>>,
+}
+
+// Carried from one check of a system to the next
+#[derive(Debug, Clone, Copy, Default)]
+struct CheckState {
+    last_trace: Option<Instant>,
+    // Last announced state while transitions are held back because the parent is down
+    held_back: Option<SystemState>,
+    consecutive_failures: u32,
+}
+
+impl Checker {
+    // Checks the system once and returns the delay until the next check, None once it was removed
+    async fn run(self, id: Uuid) -> Option<Duration> {
+        // Don't hold the map entry across the check, it would block readers for the whole timeout
+        let config = self.systems.get(&id)?.config.clone();
+        let probe = Arc::clone(self.probes.get(&id)?.value());
+        if let Some(timeout) = probe.heartbeat_timeout() {
+            return self.check_heartbeat(id, &config, timeout).await;
+        }
+
+        if config.enabled {
+            let outcome = MonitorManager::check_system_status(&config, probe, &self.resolver).await;
+            self.record(id, &config, outcome).await;
+        }
+
+        // Checks speed up while the system is down or degraded, see ScheduleSettings
+        let consecutive_failures = self.check_states.get(&id).map_or(0, |state| state.consecutive_failures);
+        let state = match self.systems.get(&id) {
+            Some(system_ref) if config.enabled => system_ref.state,
+            Some(_) => SystemState::Up,
+            None => return None,
+        };
+        Some(config.next_interval(state, consecutive_failures, self.default_interval))
+    }
+
+    // A missed heartbeat is recorded once per timeout for as long as none arrives
+    async fn check_heartbeat(&self, id: Uuid, config: &SystemConfig, timeout: Duration) -> Option<Duration> {
+        let last_heartbeat = self.systems.get(&id)?.last_heartbeat;
+        let since = last_heartbeat.map(|t| (Utc::now() - t).to_std().unwrap_or_default());
+        if let Some(since) = since.filter(|since| *since < timeout) {
+            return Some(timeout - since);
+        }
+        if config.enabled {
+            let error = match last_heartbeat {
+                Some(t) => format!("No heartbeat since {}", t.format("%Y-%m-%d %H:%M:%S UTC")),
+                None => "No heartbeat received".to_string(),
+            };
+            self.record(id, config, CheckOutcome::failed(FailureKind::Timeout, error)).await;
+        }
+        Some(timeout)
+    }
+
+    // Name of the system's parent when that is enabled and down; a parent that wasn't checked
+    // yet doesn't count
+    fn parent_down(&self, id: Uuid, config: &SystemConfig) -> Option<String> {
+        let parent = config.parent.as_ref()?;
+        let parent_id = self.names.get(&parent.to_lowercase())?.iter().copied().find(|parent_id| *parent_id != id)?;
+        self.systems.get(&parent_id)
+            .filter(|s| s.config.enabled && s.total_checks > 0 && s.state == SystemState::Down)
+            .map(|s| s.config.name.clone())
+    }
+
+    // Applies the result of a check or heartbeat to the status, history and subscribers
+    async fn record(&self, id: Uuid, config: &SystemConfig, outcome: CheckOutcome) {
+        let mut check_state = self.check_states.get(&id).map(|state| *state).unwrap_or_default();
+        let CheckOutcome { is_online, response_time, mut error, mut failure, timings, addresses, degradation: reported, output } = outcome;
+
+        // A failure while the parent is down is most likely caused by it; the parent's own
+        // outage is what gets traced and reported
+        let parent_down = if is_online { None } else { self.parent_down(id, config) };
+        if let Some(parent) = &parent_down {
+            failure = Some(FailureKind::ParentDown);
+            error = Some(match error {
+                Some(error) => format!("{} ({} is down)", error, parent),
+                None => format!("{} is down", parent),
+            });
+        }
+
+        // Thresholds cover this check and the ones before it
+        let thresholds = config.threshold_settings(self.default_thresholds.as_ref());
+        let degradation = if is_online {
+            let mut window = self.histories.get(&id)
+                .map(|h| h.checks(Some(thresholds.window.saturating_sub(1))))
+                .unwrap_or_default();
+            window.push(CheckRecord {
+                timestamp: Utc::now(),
+                is_online,
+                response_time_ms: response_time,
+                error: None,
+                failure: None,
+                degraded: None,
+                timings,
+                output: None,
+            });
+            [reported, health::evaluate(&window, &thresholds)].into_iter().flatten().reduce(merge_degradation)
+        } else {
+            None
+        };
+        let degraded = degradation.as_ref().map(|d| d.severity);
+        check_state.consecutive_failures = if is_online { 0 } else { check_state.consecutive_failures.saturating_add(1) };
+
+        let updated = self.systems.get_mut(&id).map(|mut system_ref| {
+            let previous = system_ref.state;
+            let changed = system_ref.update_status(is_online, response_time, error.clone(), degradation.clone());
+            system_ref.failure = failure;
+            system_ref.timings = timings;
+            system_ref.addresses = addresses;
+            let record = CheckRecord {
+                timestamp: system_ref.last_check,
+                is_online,
+                response_time_ms: response_time,
+                error,
+                failure,
+                degraded,
+                timings,
+                output: output.as_ref().and_then(|o| o.lines().next()).map(str::to_string),
+            };
+            system_ref.output = output;
+            (record, changed, previous)
+        });
+
+        if let Some((record, changed, previous)) = updated {
+            if let Some(mut history) = self.histories.get_mut(&id) {
+                history.record(record.clone());
+            }
+
+            if let Some(store) = &self.history_store {
+                // The system may have been renamed while it was checked
+                let key = self.systems.get(&id).map_or_else(|| config.storage_key(), |s| s.config.storage_key());
+                if let Err(e) = store.append(&key, &record).await {
+                    log::error!("Failed to write history for {}: {}", config.name, e);
+                }
+            }
+
+            let settings = config.trace_settings();
+            let outage_started = changed && !is_online;
+            let trace_due = settings.interval_seconds.is_some_and(|interval| {
+                check_state.last_trace.is_none_or(|t| t.elapsed() >= interval_of(interval))
+            });
+            let trace_outage = outage_started && parent_down.is_none() && config.protocol == Protocol::Ping && settings.on_outage;
+            if trace_outage || trace_due {
+                MonitorManager::spawn_trace(id, config.clone(), Arc::clone(&self.traces), self.events.clone(), Arc::clone(&self.resolver));
+                check_state.last_trace = Some(Instant::now());
+            }
+
+            // Transitions while the parent is down are left to the parent. Once it is back, the
+            // system is announced only if it didn't come back to the state announced before.
+            let announce = match (&parent_down, check_state.held_back) {
+                (Some(_), held_back) => {
+                    if changed && held_back.is_none() {
+                        check_state.held_back = Some(previous);
+                    }
+                    false
+                }
+                (None, Some(announced)) => {
+                    check_state.held_back = None;
+                    record.state() != announced
+                }
+                (None, None) => changed,
+            };
+            if announce {
+                let _ = self.events.send(MonitorEvent::StateChanged {
+                    system_id: id,
+                    name: config.name.clone(),
+                    group: config.group.clone(),
+                    is_online,
+                    state: record.state(),
+                    degradation: degradation.clone(),
+                    timestamp: record.timestamp,
+                    error: record.error.clone(),
+                });
+            }
+            let _ = self.events.send(MonitorEvent::CheckCompleted {
+                system_id: id,
+                name: config.name.clone(),
+                group: config.group.clone(),
+                record,
+            });
+            // Only while the system exists, a check finishing after its removal would leak it
+            self.check_states.insert(id, check_state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_restore_saved_state() {
+        let mut config = SystemConfig::new("web".to_string(), "192.0.2.1".to_string(), None, Protocol::Ping);
+        config.enabled = false;
+        let started_at = Utc::now() - chrono::Duration::minutes(5);
+        let saved = SavedStatus {
+            key: config.storage_key(),
+            target: config.target_key(),
+            id: Uuid::new_v4(),
+            is_online: false,
+            state: SystemState::Down,
+            degradation: None,
+            last_check: Utc::now(),
+            last_online: Some(started_at),
+            last_offline: Some(started_at),
+            response_time_ms: None,
+            total_checks: 10,
+            successful_checks: 8,
+            error_message: Some("Request timed out".to_string()),
+            failure: Some(FailureKind::Timeout),
+            last_heartbeat: None,
+            open_incidents: vec![Incident {
+                kind: crate::history::IncidentKind::Outage,
+                started_at,
+                ended_at: None,
+                failed_checks: 2,
+                first_error: Some("Request timed out".to_string()),
+            }],
+        };
+        let saved_id = saved.id;
+
+        let mut manager = MonitorManager::new()
+            .with_saved_state(HashMap::from([(saved.key.clone(), saved)]));
+        let id = manager.add_system(config.clone()).await.unwrap();
+        assert_eq!(id, saved_id);
+        let status = manager.get_system(id).unwrap();
+        assert_eq!((status.total_checks, status.successful_checks), (10, 8));
+        assert_eq!(status.uptime_percentage, 80.0);
+        assert_eq!(manager.get_incidents(id).unwrap()[0].started_at, started_at);
+
+        // Only one system can take over a saved status
+        let other = manager.add_system(config).await.unwrap();
+        assert_ne!(other, saved_id);
+        assert_eq!(manager.get_system(other).unwrap().total_checks, 0);
+
+        let snapshot = manager.snapshot();
+        assert_eq!(snapshot.systems.len(), 2);
+        assert_eq!(snapshot.systems[0].open_incidents.len(), 1);
+
+        // A renamed system finds its status through the target
+        let saved = snapshot.systems.into_iter().find(|saved| saved.id == id).unwrap();
+        let mut renamed = manager.get_system(id).unwrap().config;
+        renamed.name = "website".to_string();
+        let mut restarted = MonitorManager::new().with_saved_state(HashMap::from([(saved.key.clone(), saved)]));
+        assert_eq!(restarted.add_system(renamed).await.unwrap(), id);
+    }
+
+    #[tokio::test]
+    async fn test_record_heartbeat() {
+        let mut config = SystemConfig::new("backup".to_string(), "backup01".to_string(), None, Protocol::Heartbeat);
+        config.probe = Some(toml::from_str("token = \"nightly\"\nperiod_seconds = 86400").unwrap());
+        let mut manager = MonitorManager::new();
+        let id = manager.add_system(config.clone()).await.unwrap();
+        assert!(manager.record_heartbeat("weekly", Heartbeat::default()).await.is_err());
+        assert!(manager.record_heartbeat(&id.to_string(), Heartbeat::default()).await.is_err());
+        // Without a token there would be nothing a client has to know to report
+        config.probe = None;
+        assert!(manager.validate_probe(&config, None).is_err());
+        // A token can only belong to one system, which may keep it when it's changed
+        let mut copy = manager.get_system(id).unwrap().config;
+        copy.name = "backup copy".to_string();
+        assert!(manager.add_system(copy.clone()).await.is_err());
+        assert!(manager.validate_probe(&copy, Some(id)).is_ok());
+
+        // A long run isn't a latency problem
+        let took_90_minutes = Heartbeat { duration_ms: Some(5_400_000), ..Heartbeat::default() };
+        assert_eq!(manager.record_heartbeat("nightly", took_90_minutes).await.unwrap(), id);
+        let status = manager.get_system(id).unwrap();
+        assert_eq!((status.state, status.response_time_ms), (SystemState::Up, Some(5_400_000)));
+        assert!(status.last_heartbeat.is_some());
+
+        let failed = Heartbeat { exit_code: Some(1), message: Some("disk full".to_string()), ..Heartbeat::default() };
+        manager.record_heartbeat("nightly", failed).await.unwrap();
+        assert_eq!(manager.get_system(id).unwrap().state, SystemState::Down);
+        let history = manager.get_history(id, None).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].error.as_deref(), Some("Exited with code 1: disk full"));
+        assert_eq!(history[1].output.as_deref(), Some("disk full"));
+    }
+
+    #[tokio::test]
+    async fn test_parent_down() {
+        let heartbeat = |name: &str, token: &str| {
+            let mut config = SystemConfig::new(name.to_string(), name.to_string(), None, Protocol::Heartbeat);
+            config.probe = Some(toml::from_str(&format!("token = \"{}\"\nperiod_seconds = 3600", token)).unwrap());
+            config
+        };
+        let mut manager = MonitorManager::new();
+        let mut child = heartbeat("app", "app");
+        child.parent = Some("Default gateway".to_string());
+        let child_id = manager.add_system(child).await.unwrap();
+        let gateway_id = manager.add_builtin_system(heartbeat("Default gateway", "gw")).await.unwrap();
+        assert_eq!(manager.system_configs().len(), 1);
+        assert!(manager.is_builtin(gateway_id));
+
+        let failed = Heartbeat { exit_code: Some(2), ..Heartbeat::default() };
+        // Before the parent was ever checked the failure is the child's own
+        manager.record_heartbeat("app", failed.clone()).await.unwrap();
+        assert_ne!(manager.get_system(child_id).unwrap().failure, Some(FailureKind::ParentDown));
+        manager.record_heartbeat("app", Heartbeat::default()).await.unwrap();
+
+        manager.record_heartbeat("gw", failed.clone()).await.unwrap();
+        let mut events = manager.subscribe();
+        manager.record_heartbeat("app", failed).await.unwrap();
+        let status = manager.get_system(child_id).unwrap();
+        assert_eq!(status.failure, Some(FailureKind::ParentDown));
+        assert_eq!(status.error_message.as_deref(), Some("Exited with code 2 (Default gateway is down)"));
+        // Neither announced nor an incident of its own, also not when both come back
+        assert_eq!(manager.get_incidents(child_id).unwrap().iter().filter(|i| i.is_open()).count(), 0);
+        manager.record_heartbeat("gw", Heartbeat::default()).await.unwrap();
+        manager.record_heartbeat("app", Heartbeat::default()).await.unwrap();
+        let mut changes = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let MonitorEvent::StateChanged { system_id, .. } = event {
+                changes.push(system_id);
+            }
+        }
+        assert_eq!(changes, vec![gateway_id]);
+    }
+}
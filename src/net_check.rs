@@ -0,0 +1,311 @@
+use crate::config::{LocalNetworkConfig, Protocol, SystemConfig};
+use crate::health::{Degradation, Severity};
+use crate::history::FailureKind;
+use crate::probe::{Probe, ProbeError, ProbeFuture, ProbeResult, ProbeTarget};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// Group of the systems `discover` creates
+pub const LOCAL_GROUP: &str = "Local";
+
+const RTF_UP: u32 = 0x1;
+const RTF_GATEWAY: u32 = 0x2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InterfaceSettings {
+    // Interface name, the system's host when unset
+    pub interface: Option<String>,
+    // Receive and transmit errors since the previous check
+    pub warning_errors: Option<u64>,
+    pub critical_errors: Option<u64>,
+    // Dropped packets since the previous check; many drivers count harmless drops, so
+    // these are off by default
+    pub warning_drops: Option<u64>,
+    pub critical_drops: Option<u64>,
+    // An interface that is up but has no address is degraded, e.g. when DHCP failed
+    pub require_address: bool,
+}
+
+impl Default for InterfaceSettings {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            warning_errors: Some(1),
+            critical_errors: Some(100),
+            warning_drops: None,
+            critical_drops: None,
+            require_address: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    pub errors: u64,
+    pub drops: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultRoute {
+    pub interface: String,
+    pub gateway: Ipv4Addr,
+    pub metric: u32,
+}
+
+// Checks a network interface of the machine the monitor runs on, reading /sys/class/net
+pub struct InterfaceProbe {
+    settings: InterfaceSettings,
+    // Counters only ever grow, so errors are counted between two checks
+    previous: Mutex<Option<InterfaceCounters>>,
+}
+
+impl InterfaceProbe {
+    pub fn new(settings: InterfaceSettings) -> Self {
+        Self { settings, previous: Mutex::new(None) }
+    }
+
+    async fn check_interface(&self, name: &str) -> Result<ProbeResult, ProbeError> {
+        let directory = PathBuf::from("/sys/class/net").join(name);
+        if !tokio::fs::try_exists(&directory).await.unwrap_or(false) {
+            return Err(ProbeError::new(FailureKind::LinkDown, format!("No interface {}", name)));
+        }
+        let read = |file: &str| {
+            let path = directory.join(file);
+            async move { tokio::fs::read_to_string(path).await.ok().map(|content| content.trim().to_string()) }
+        };
+
+        let operstate = read("operstate").await.unwrap_or_else(|| "unknown".to_string());
+        // Reading the carrier of an interface that is administratively down fails
+        let carrier = read("carrier").await;
+        if matches!(operstate.as_str(), "down" | "lowerlayerdown" | "notpresent") || carrier.as_deref() != Some("1") {
+            return Err(ProbeError::new(FailureKind::LinkDown, format!("{} link is {}", name, operstate)));
+        }
+
+        let mut statistics = BTreeMap::new();
+        for counter in ["rx_errors", "tx_errors", "rx_dropped", "tx_dropped"] {
+            let value = read(&format!("statistics/{}", counter)).await.and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+            statistics.insert(counter, value);
+        }
+        let counters = InterfaceCounters {
+            errors: statistics["rx_errors"] + statistics["tx_errors"],
+            drops: statistics["rx_dropped"] + statistics["tx_dropped"],
+        };
+        let previous = self.previous.lock().ok().and_then(|mut previous| previous.replace(counters));
+
+        let addresses = interface_addresses(name);
+        let speed = read("speed").await.and_then(|v| v.parse::<i64>().ok()).filter(|speed| *speed > 0);
+
+        let mut metadata: BTreeMap<String, String> = statistics.iter()
+            .map(|(counter, value)| (counter.to_string(), value.to_string()))
+            .collect();
+        metadata.insert("operstate".to_string(), operstate.clone());
+        if let Some(mtu) = read("mtu").await {
+            metadata.insert("mtu".to_string(), mtu);
+        }
+        if let Some(speed) = speed {
+            metadata.insert("speed_mbps".to_string(), speed.to_string());
+        }
+        let address_list: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+        metadata.insert("addresses".to_string(), address_list.join(" "));
+
+        let mut reasons = Vec::new();
+        let mut severity = None;
+        let mut breach = |found: Severity, reason: String| {
+            severity = severity.max(Some(found));
+            reasons.push(reason);
+        };
+        if let Some(previous) = previous {
+            let limits = [
+                ("errors", counters.errors.saturating_sub(previous.errors), self.settings.warning_errors, self.settings.critical_errors),
+                ("drops", counters.drops.saturating_sub(previous.drops), self.settings.warning_drops, self.settings.critical_drops),
+            ];
+            for (label, new, warning, critical) in limits {
+                if critical.is_some_and(|limit| new >= limit) {
+                    breach(Severity::Critical, format!("{} new {} on {}", new, label, name));
+                } else if warning.is_some_and(|limit| new >= limit) {
+                    breach(Severity::Warning, format!("{} new {} on {}", new, label, name));
+                }
+            }
+        }
+        if self.settings.require_address && addresses.is_empty() {
+            breach(Severity::Warning, format!("{} has no address", name));
+        }
+
+        let mut summary = format!("{} {}", name, operstate);
+        if let Some(speed) = speed {
+            summary.push_str(&format!(", {} Mb/s", speed));
+        }
+        if !address_list.is_empty() {
+            summary.push_str(&format!(", {}", address_list.join(", ")));
+        }
+        Ok(ProbeResult {
+            metadata,
+            degradation: severity.map(|severity| Degradation { severity, reasons }),
+            output: Some(summary),
+            ..ProbeResult::default()
+        })
+    }
+}
+
+impl Probe for InterfaceProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let name = self.settings.interface.as_deref().unwrap_or(&target.config.host);
+            self.check_interface(name).await
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(unix)]
+fn interface_addresses(name: &str) -> Vec<IpAddr> {
+    let Ok(addresses) = nix::ifaddrs::getifaddrs() else {
+        return Vec::new();
+    };
+    addresses
+        .filter(|a| a.interface_name == name)
+        .filter_map(|a| {
+            let address = a.address?;
+            if let Some(v4) = address.as_sockaddr_in() {
+                Some(IpAddr::V4(Ipv4Addr::from(v4.ip())))
+            } else {
+                address.as_sockaddr_in6().map(|v6| IpAddr::V6(v6.ip()))
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn interface_addresses(_name: &str) -> Vec<IpAddr> {
+    Vec::new()
+}
+
+// "Iface Destination Gateway Flags RefCnt Use Metric Mask ..." with addresses in hex, in
+// the byte order of the machine. Sorted by metric, so the route in use comes first.
+pub fn parse_default_routes(content: &str) -> Vec<DefaultRoute> {
+    let mut routes: Vec<DefaultRoute> = content.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let hex = |index: usize| fields.get(index).and_then(|f| u32::from_str_radix(f, 16).ok());
+            let (destination, gateway, flags, mask) = (hex(1)?, hex(2)?, hex(3)?, hex(7)?);
+            let is_default = destination == 0 && mask == 0 && gateway != 0;
+            (is_default && flags & (RTF_UP | RTF_GATEWAY) == RTF_UP | RTF_GATEWAY).then(|| DefaultRoute {
+                interface: fields[0].to_string(),
+                gateway: Ipv4Addr::from(gateway.to_ne_bytes()),
+                metric: fields.get(6).and_then(|m| m.parse().ok()).unwrap_or(0),
+            })
+        })
+        .collect();
+    routes.sort_by_key(|route| route.metric);
+    routes
+}
+
+pub fn parse_resolv_conf(content: &str) -> Vec<IpAddr> {
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next()? == "nameserver").then_some(())?;
+            fields.next()?.parse().ok()
+        })
+        .collect()
+}
+
+// Systems for the local interfaces, the default gateways and the DNS resolvers, all in
+// the "Local" group. Gateways name their interface as parent, and resolvers reached
+// through the network the main gateway, so a broken link shows up as just that.
+pub async fn discover(settings: &LocalNetworkConfig) -> Result<Vec<SystemConfig>> {
+    let routes = match tokio::fs::read_to_string("/proc/net/route").await {
+        Ok(content) => parse_default_routes(&content),
+        Err(e) => {
+            log::warn!("Can't read /proc/net/route, no gateways are monitored: {}", e);
+            Vec::new()
+        }
+    };
+
+    let mut interfaces: Vec<String> = settings.interfaces.clone();
+    for route in &routes {
+        if !interfaces.contains(&route.interface) {
+            interfaces.push(route.interface.clone());
+        }
+    }
+
+    let mut systems: Vec<SystemConfig> = interfaces.iter()
+        .map(|interface| local_system(interface_system_name(interface), interface.clone(), Protocol::Interface, None))
+        .collect();
+
+    if settings.gateways {
+        for (index, route) in routes.iter().enumerate() {
+            // Several routes can share a gateway, e.g. with different metrics
+            if routes[..index].iter().any(|r| r.gateway == route.gateway && r.interface == route.interface) {
+                continue;
+            }
+            let name = if index == 0 { "Default gateway".to_string() } else { format!("Default gateway ({})", route.interface) };
+            let parent = Some(interface_system_name(&route.interface));
+            systems.push(local_system(name, route.gateway.to_string(), Protocol::Ping, parent));
+        }
+    }
+
+    if settings.resolvers {
+        let content = match tokio::fs::read_to_string(&settings.resolv_conf).await {
+            Ok(content) => content,
+            Err(e) => {
+                log::warn!("Can't read {}, no DNS resolvers are monitored: {}", settings.resolv_conf, e);
+                String::new()
+            }
+        };
+        let gateway = routes.first().map(|_| "Default gateway".to_string());
+        for resolver in parse_resolv_conf(&content) {
+            // A local stub resolver doesn't depend on the network
+            let parent = if resolver.is_loopback() { None } else { gateway.clone() };
+            systems.push(local_system(format!("DNS resolver {}", resolver), resolver.to_string(), Protocol::Ping, parent));
+        }
+    }
+
+    Ok(systems)
+}
+
+pub fn interface_system_name(interface: &str) -> String {
+    format!("Interface {}", interface)
+}
+
+fn local_system(name: String, host: String, protocol: Protocol, parent: Option<String>) -> SystemConfig {
+    let mut system = SystemConfig::new(name, host, None, protocol);
+    system.group = Some(LOCAL_GROUP.to_string());
+    system.parent = parent;
+    system
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_routes_and_resolvers() {
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+            wlan0\t00000000\t0102A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+            eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+            eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n";
+        let routes = parse_default_routes(route);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].interface, "eth0");
+        if cfg!(target_endian = "little") {
+            assert_eq!(routes[0].gateway, Ipv4Addr::new(192, 168, 1, 1));
+        }
+        assert_eq!(routes[1].metric, 600);
+
+        let resolv = "# Generated\nnameserver 127.0.0.53\nnameserver 2001:db8::53\nsearch example.com\nnameserver bogus\n";
+        assert_eq!(parse_resolv_conf(resolv), vec![
+            "127.0.0.53".parse::<IpAddr>().unwrap(),
+            "2001:db8::53".parse::<IpAddr>().unwrap(),
+        ]);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::config::SystemConfig;
+use crate::exec_check::{ExecProbe, ExecSettings};
+use crate::health::Degradation;
+use crate::heartbeat::{HeartbeatProbe, HeartbeatSettings};
+use crate::local_check::{LocalProbe, LocalSettings};
+use crate::net_check::{InterfaceProbe, InterfaceSettings};
+use crate::process_check::{ProcessProbe, ProcessSettings};
+use crate::history::{CheckTimings, FailureKind};
+use crate::http_check::{self, HttpError, HttpTarget};
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+pub(crate) const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+pub type ProbeFuture<'a> = Pin<Box<dyn Future<Output = Result<ProbeResult, ProbeError>> + Send + 'a>>;
+
+// One kind of check. A probe is built per system from its `[systems.probe]` settings and
+// called for every resolved address of the host.
+pub trait Probe: Send + Sync {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a>;
+
+    // Port used when the system doesn't set one and the protocol has no usual port
+    fn default_port(&self) -> Option<u16> {
+        None
+    }
+
+    // Local probes look at this machine rather than the host, which isn't resolved then
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    // Set by passive probes, which are never run: the system goes down when it hasn't
+    // reported in for this long
+    fn heartbeat_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    // What passive probes are reported to with, see heartbeat.rs
+    fn heartbeat_token(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub struct ProbeTarget<'a> {
+    pub config: &'a SystemConfig,
+    pub address: IpAddr,
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProbeResult {
+    // Phases the probe measured; DNS and the total are filled in by the monitor
+    pub timings: CheckTimings,
+    // Anything else worth showing, e.g. the TTL of a ping reply
+    pub metadata: BTreeMap<String, String>,
+    // Set when the target answered but isn't healthy; combined with the threshold checks
+    pub degradation: Option<Degradation>,
+    // Text the target reported about itself, e.g. a plugin's status line
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeError {
+    pub kind: FailureKind,
+    pub message: String,
+    pub output: Option<String>,
+}
+
+impl ProbeError {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), output: None }
+    }
+
+    pub fn with_output(mut self, output: Option<String>) -> Self {
+        self.output = output;
+        self
+    }
+}
+
+impl std::fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+// Classifies timeouts, HTTP and socket errors; anything else counts as unreachable
+impl From<anyhow::Error> for ProbeError {
+    fn from(error: anyhow::Error) -> Self {
+        let kind = if error.is::<tokio::time::error::Elapsed>() {
+            FailureKind::Timeout
+        } else if let Some(http_error) = error.downcast_ref::<HttpError>() {
+            match http_error {
+                HttpError::Tls(_) => FailureKind::Tls,
+                HttpError::Status(_) | HttpError::InvalidResponse => FailureKind::Response,
+            }
+        } else {
+            match error.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+                Some(std::io::ErrorKind::ConnectionRefused) => FailureKind::Refused,
+                Some(std::io::ErrorKind::TimedOut) => FailureKind::Timeout,
+                _ => FailureKind::Unreachable,
+            }
+        };
+        Self::new(kind, error.to_string())
+    }
+}
+
+type Factory = Arc<dyn Fn(&SystemConfig) -> Result<Arc<dyn Probe>> + Send + Sync>;
+
+// Probes by protocol name, see `Protocol::name`
+#[derive(Clone, Default)]
+pub struct ProbeRegistry {
+    factories: HashMap<String, Factory>,
+}
+
+impl ProbeRegistry {
+    // A registry without any probes; `builtin` has every protocol of `Protocol`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register("ping", |settings: PingSettings| PingProbe { settings });
+        registry.register("tcp", |settings: TimeoutSettings| TcpProbe { settings });
+        registry.register("udp", |settings: UdpSettings| UdpProbe { settings });
+        registry.register("http", |settings: HttpSettings| HttpProbe { settings, tls: false });
+        registry.register("https", |settings: HttpSettings| HttpProbe { settings, tls: true });
+        registry.register("exec", |settings: ExecSettings| ExecProbe { settings });
+        registry.register("heartbeat", |settings: HeartbeatSettings| HeartbeatProbe { settings });
+        registry.register("local", |settings: LocalSettings| LocalProbe::new(settings));
+        registry.register("process", |settings: ProcessSettings| ProcessProbe::new(settings));
+        registry.register("interface", |settings: InterfaceSettings| InterfaceProbe::new(settings));
+        registry
+    }
+
+    // Adds or replaces the probe for `name`. Its settings are read from the system's
+    // `[systems.probe]` table, with `S::default()` when there is none.
+    pub fn register<S, P, F>(&mut self, name: &str, build: F)
+    where
+        S: DeserializeOwned + Default,
+        P: Probe + 'static,
+        F: Fn(S) -> P + Send + Sync + 'static,
+    {
+        let name = name.to_ascii_lowercase();
+        let factory_name = name.clone();
+        let factory: Factory = Arc::new(move |config: &SystemConfig| {
+            let settings: S = match &config.probe {
+                Some(table) => S::deserialize(toml::Value::Table(table.clone()))
+                    .map_err(|e| anyhow::anyhow!("Invalid {} settings for {}: {}", factory_name, config.name, e))?,
+                None => S::default(),
+            };
+            Ok(Arc::new(build(settings)) as Arc<dyn Probe>)
+        });
+        self.factories.insert(name, factory);
+    }
+
+    pub fn create(&self, config: &SystemConfig) -> Result<Arc<dyn Probe>> {
+        let name = config.protocol.name();
+        let factory = self.factories.get(&name)
+            .ok_or_else(|| anyhow::anyhow!("No probe registered for protocol '{}'", config.protocol))?;
+        factory(config)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+pub(crate) fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutSettings {
+    pub timeout_ms: u64,
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        Self { timeout_ms: DEFAULT_TIMEOUT_MS }
+    }
+}
+
+impl TimeoutSettings {
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PingSettings {
+    pub timeout_ms: u64,
+    // Bytes of payload, the ping default when unset
+    pub size: Option<u16>,
+}
+
+impl Default for PingSettings {
+    fn default() -> Self {
+        Self { timeout_ms: DEFAULT_TIMEOUT_MS, size: None }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UdpSettings {
+    pub timeout_ms: u64,
+    pub payload: String,
+}
+
+impl Default for UdpSettings {
+    fn default() -> Self {
+        Self { timeout_ms: DEFAULT_TIMEOUT_MS, payload: "ping".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub timeout_ms: u64,
+    // Overrides the system's `path`
+    pub path: Option<String>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self { timeout_ms: DEFAULT_TIMEOUT_MS, path: None }
+    }
+}
+
+struct PingProbe {
+    settings: PingSettings,
+}
+
+struct TcpProbe {
+    settings: TimeoutSettings,
+}
+
+struct UdpProbe {
+    settings: UdpSettings,
+}
+
+struct HttpProbe {
+    settings: HttpSettings,
+    tls: bool,
+}
+
+// "64 bytes from 192.0.2.1: icmp_seq=1 ttl=57 time=11.4 ms"
+fn parse_ping_field(output: &str, field: &str) -> Option<f64> {
+    let value = output.split(field).nth(1)?;
+    value.split(|c: char| !(c.is_ascii_digit() || c == '.')).next()?.parse().ok()
+}
+
+impl Probe for PingProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let family = if target.address.is_ipv4() { "-4" } else { "-6" };
+            let wait_seconds = self.settings.timeout_ms.div_ceil(1000).max(1).to_string();
+            let mut command = tokio::process::Command::new("ping");
+            command.args([family, "-c", "1", "-W", &wait_seconds]);
+            if let Some(size) = self.settings.size {
+                command.args(["-s", &size.to_string()]);
+            }
+            let start_time = Instant::now();
+            let output = command.arg(target.address.to_string()).output().await
+                .map_err(|e| ProbeError::from(anyhow::Error::from(e)))?;
+
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                // No reply within the wait time is a timeout rather than a broken route
+                let kind = if stderr.trim().is_empty() { FailureKind::Timeout } else { FailureKind::Unreachable };
+                return Err(ProbeError::new(kind, format!("Ping failed: {}", stderr.trim())));
+            }
+
+            // Use the round trip ping measured itself, not how long the process took
+            let rtt = parse_ping_field(&stdout, "time=").unwrap_or_else(|| elapsed_ms(start_time));
+            let mut metadata = BTreeMap::new();
+            if let Some(ttl) = parse_ping_field(&stdout, "ttl=") {
+                metadata.insert("ttl".to_string(), ttl.to_string());
+            }
+            Ok(ProbeResult {
+                timings: CheckTimings { connect_ms: Some(rtt), ..CheckTimings::default() },
+                metadata,
+                ..ProbeResult::default()
+            })
+        })
+    }
+}
+
+impl Probe for TcpProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let port = target.port.unwrap_or(80);
+            let start_time = Instant::now();
+            timeout(self.settings.timeout(), TcpStream::connect(SocketAddr::new(target.address, port))).await
+                .map_err(|e| ProbeError::from(anyhow::Error::from(e)))?
+                .map_err(|e| ProbeError::from(anyhow::Error::from(e)))?;
+            Ok(ProbeResult {
+                timings: CheckTimings { connect_ms: Some(elapsed_ms(start_time)), ..CheckTimings::default() },
+                ..ProbeResult::default()
+            })
+        })
+    }
+}
+
+impl Probe for UdpProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let result: Result<f64> = async {
+                let port = target.port.unwrap_or(53);
+                let bind_address = if target.address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind_address).await?;
+                let start_time = Instant::now();
+                timeout(
+                    Duration::from_millis(self.settings.timeout_ms),
+                    socket.send_to(self.settings.payload.as_bytes(), SocketAddr::new(target.address, port)),
+                ).await??;
+                Ok(elapsed_ms(start_time))
+            }.await;
+            Ok(ProbeResult {
+                timings: CheckTimings { connect_ms: Some(result?), ..CheckTimings::default() },
+                ..ProbeResult::default()
+            })
+        })
+    }
+}
+
+impl Probe for HttpProbe {
+    fn check<'a>(&'a self, target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let path = self.settings.path.as_deref().or(target.config.path.as_deref()).unwrap_or("/");
+            let http_target = HttpTarget {
+                host: &target.config.host,
+                address: target.address,
+                port: target.port.unwrap_or(if self.tls { 443 } else { 80 }),
+                tls: self.tls,
+                path,
+            };
+            let timings = http_check::check(&http_target, Duration::from_millis(self.settings.timeout_ms)).await?;
+            Ok(ProbeResult { timings, ..ProbeResult::default() })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Protocol;
+
+    #[test]
+    fn test_parse_ping_output() {
+        let output = "PING 192.0.2.1 (192.0.2.1) 56(84) bytes of data.\n64 bytes from 192.0.2.1: icmp_seq=1 ttl=57 time=11.4 ms\n";
+        assert_eq!(parse_ping_field(output, "time="), Some(11.4));
+        assert_eq!(parse_ping_field(output, "ttl="), Some(57.0));
+        assert_eq!(parse_ping_field("Request timeout for icmp_seq 0", "time="), None);
+    }
+
+    #[test]
+    fn test_registry_settings() {
+        let registry = ProbeRegistry::builtin();
+        let mut config = SystemConfig::new("dns".to_string(), "192.0.2.53".to_string(), None, Protocol::Udp);
+        config.probe = Some(toml::from_str("payload = \"hello\"\ntimeout_ms = 200").unwrap());
+        assert!(registry.create(&config).is_ok());
+
+        config.probe = Some(toml::from_str("timeout_ms = \"soon\"").unwrap());
+        assert!(registry.create(&config).is_err());
+
+        let custom: SystemConfig = toml::from_str("name = \"x\"\nhost = \"192.0.2.1\"\nprotocol = \"Gopher\"\nenabled = true").unwrap();
+        assert_eq!(custom.protocol, Protocol::Custom("Gopher".to_string()));
+        assert!(registry.create(&custom).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::health::{Degradation, Severity};
+use crate::history::FailureKind;
+use crate::probe::{Probe, ProbeError, ProbeFuture, ProbeResult, ProbeTarget};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+const RESTART_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProcessSettings {
+    // Executable name, as in `ps -e`
+    pub name: Option<String>,
+    // File holding the pid of the process
+    pub pidfile: Option<String>,
+    // Text the command line must contain
+    pub pattern: Option<String>,
+    pub min_instances: usize,
+    // Resident memory of any one instance; above it the system is degraded
+    pub max_memory_mb: Option<u64>,
+    // An instance younger than this means it was restarted, e.g. by a crash loop
+    pub min_uptime_seconds: Option<u64>,
+    // Run when no instance is found, at most once per cooldown; program and arguments
+    pub restart_command: Vec<String>,
+    pub restart_cooldown_seconds: u64,
+}
+
+impl Default for ProcessSettings {
+    fn default() -> Self {
+        Self {
+            name: None,
+            pidfile: None,
+            pattern: None,
+            min_instances: 1,
+            max_memory_mb: None,
+            min_uptime_seconds: None,
+            restart_command: Vec::new(),
+            restart_cooldown_seconds: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    pub command_line: String,
+    pub memory_bytes: u64,
+    pub uptime: Duration,
+}
+
+// Checks that a local process is running, see ProcessSettings
+pub struct ProcessProbe {
+    settings: ProcessSettings,
+    last_restart: Mutex<Option<Instant>>,
+}
+
+impl ProcessProbe {
+    pub fn new(settings: ProcessSettings) -> Self {
+        Self { settings, last_restart: Mutex::new(None) }
+    }
+
+    fn describe(&self) -> String {
+        let settings = &self.settings;
+        [
+            settings.name.as_ref().map(|name| format!("name {}", name)),
+            settings.pidfile.as_ref().map(|pidfile| format!("pidfile {}", pidfile)),
+            settings.pattern.as_ref().map(|pattern| format!("pattern '{}'", pattern)),
+        ].into_iter().flatten().collect::<Vec<_>>().join(", ")
+    }
+
+    fn matches(&self, process: &ProcessInfo) -> bool {
+        let name_matches = self.settings.name.as_ref().is_none_or(|name| {
+            // The kernel truncates names to 15 characters, so also try the executable of the command line
+            let executable = process.command_line.split(' ').next().unwrap_or_default();
+            process.name == *name || executable.rsplit('/').next() == Some(name.as_str())
+        });
+        let pattern_matches = self.settings.pattern.as_ref().is_none_or(|pattern| process.command_line.contains(pattern.as_str()));
+        name_matches && pattern_matches
+    }
+
+    async fn find(&self) -> Result<Vec<ProcessInfo>> {
+        let pids = match &self.settings.pidfile {
+            Some(pidfile) => {
+                let content = tokio::fs::read_to_string(pidfile).await
+                    .with_context(|| format!("Failed to read {}", pidfile))?;
+                vec![content.trim().parse::<u32>().with_context(|| format!("No pid in {}", pidfile))?]
+            }
+            None => all_pids().await?,
+        };
+        let system_uptime = parse_uptime(&tokio::fs::read_to_string("/proc/uptime").await?)
+            .context("Unexpected /proc/uptime format")?;
+        let own_pid = std::process::id();
+
+        let mut found = Vec::new();
+        for pid in pids.into_iter().filter(|pid| *pid != own_pid) {
+            // Processes exit while being looked at
+            if let Some(process) = read_process(pid, system_uptime).await {
+                if self.matches(&process) {
+                    found.push(process);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    // Runs the restart command unless it ran within the cooldown; returns what happened
+    async fn restart(&self) -> Option<String> {
+        let (program, args) = self.settings.restart_command.split_first()?;
+        let cooldown = Duration::from_secs(self.settings.restart_cooldown_seconds);
+        {
+            let mut last_restart = self.last_restart.lock().ok()?;
+            if last_restart.is_some_and(|t| t.elapsed() < cooldown) {
+                return None;
+            }
+            *last_restart = Some(Instant::now());
+        }
+
+        log::warn!("Running restart command {}", self.settings.restart_command.join(" "));
+        // Not captured, a daemon started by the command would keep the pipes open
+        let status = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status();
+        Some(match timeout(RESTART_TIMEOUT, status).await {
+            Ok(Ok(status)) if status.success() => "restart command succeeded".to_string(),
+            Ok(Ok(status)) => format!("restart command failed ({})", status),
+            Ok(Err(e)) => format!("restart command failed: {}", e),
+            Err(_) => format!("restart command timed out after {}s", RESTART_TIMEOUT.as_secs()),
+        })
+    }
+}
+
+async fn all_pids() -> Result<Vec<u32>> {
+    let mut entries = tokio::fs::read_dir("/proc").await.context("Failed to read /proc")?;
+    let mut pids = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+// None for processes that are gone or zombies
+async fn read_process(pid: u32, system_uptime: f64) -> Option<ProcessInfo> {
+    let directory = format!("/proc/{}", pid);
+    let stat = tokio::fs::read_to_string(format!("{}/stat", directory)).await.ok()?;
+    let (name, state, start_ticks) = parse_stat(&stat)?;
+    if state == 'Z' {
+        return None;
+    }
+    let command_line = tokio::fs::read(format!("{}/cmdline", directory)).await.ok()?;
+    let command_line = String::from_utf8_lossy(&command_line).split('\0').filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" ");
+    let status = tokio::fs::read_to_string(format!("{}/status", directory)).await.ok()?;
+    let started = start_ticks as f64 / clock_ticks_per_second();
+    Some(ProcessInfo {
+        pid,
+        name,
+        command_line,
+        memory_bytes: parse_rss_kb(&status).unwrap_or(0) * 1024,
+        uptime: Duration::from_secs_f64((system_uptime - started).max(0.0)),
+    })
+}
+
+fn clock_ticks_per_second() -> f64 {
+    #[cfg(unix)]
+    if let Ok(Some(ticks)) = nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK) {
+        return ticks as f64;
+    }
+    100.0
+}
+
+// "1234 (my daemon) S 1 ..."; the name may itself contain spaces and parentheses.
+// Returns the name, the state and the start time in clock ticks (field 22).
+pub fn parse_stat(content: &str) -> Option<(String, char, u64)> {
+    let open = content.find('(')?;
+    let close = content.rfind(')')?;
+    let name = content.get(open + 1..close)?.to_string();
+    let mut fields = content.get(close + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let start_ticks = fields.nth(18)?.parse().ok()?;
+    Some((name, state, start_ticks))
+}
+
+pub fn parse_rss_kb(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// Seconds since boot, the first field of /proc/uptime
+pub fn parse_uptime(content: &str) -> Option<f64> {
+    content.split_whitespace().next()?.parse().ok()
+}
+
+impl Probe for ProcessProbe {
+    fn check<'a>(&'a self, _target: &'a ProbeTarget<'a>) -> ProbeFuture<'a> {
+        Box::pin(async move {
+            let settings = &self.settings;
+            if settings.name.is_none() && settings.pidfile.is_none() && settings.pattern.is_none() {
+                return Err(ProbeError::new(FailureKind::Unknown, "No name, pidfile or pattern configured"));
+            }
+            // A stale pidfile means the process isn't running, not that the check failed
+            let found = match self.find().await {
+                Ok(found) => found,
+                Err(e) if settings.pidfile.is_some() => {
+                    log::debug!("{:#}", e);
+                    Vec::new()
+                }
+                Err(e) => return Err(ProbeError::new(FailureKind::Unknown, format!("{:#}", e))),
+            };
+
+            if found.len() < settings.min_instances.max(1) {
+                let mut message = match found.len() {
+                    0 => format!("No process with {}", self.describe()),
+                    count => format!("{} of {} processes with {} running", count, settings.min_instances, self.describe()),
+                };
+                if let Some(restart) = self.restart().await {
+                    message = format!("{}; {}", message, restart);
+                }
+                return Err(ProbeError::new(FailureKind::NotRunning, message));
+            }
+
+            let mut severity = None;
+            let mut reasons = Vec::new();
+            if let Some(limit) = settings.max_memory_mb {
+                for process in found.iter().filter(|p| p.memory_bytes > limit * 1024 * 1024) {
+                    severity = Some(Severity::Critical);
+                    reasons.push(format!("pid {} uses {}MB, over {}MB", process.pid, process.memory_bytes / 1024 / 1024, limit));
+                }
+            }
+            let youngest = found.iter().map(|p| p.uptime).min().unwrap_or_default();
+            if let Some(minimum) = settings.min_uptime_seconds.filter(|minimum| youngest.as_secs() < *minimum) {
+                severity = severity.max(Some(Severity::Warning));
+                reasons.push(format!("restarted {}s ago, less than {}s", youngest.as_secs(), minimum));
+            }
+
+            let pids: Vec<String> = found.iter().map(|p| p.pid.to_string()).collect();
+            let memory: u64 = found.iter().map(|p| p.memory_bytes).sum();
+            let metadata = BTreeMap::from([
+                ("instances".to_string(), found.len().to_string()),
+                ("pids".to_string(), pids.join(",")),
+                ("memory_mb".to_string(), (memory / 1024 / 1024).to_string()),
+                ("uptime_seconds".to_string(), youngest.as_secs().to_string()),
+            ]);
+            Ok(ProbeResult {
+                metadata,
+                degradation: severity.map(|severity| Degradation { severity, reasons }),
+                output: Some(format!("{} running (pid {})", found.len(), pids.join(", "))),
+                ..ProbeResult::default()
+            })
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_process() {
+        let stat = "4242 (my (odd) daemon) S 1 4242 4242 0 -1 4194560 1234 0 0 0 12 3 0 0 20 0 4 0 987654 123456789 2048 18446744073709551615";
+        assert_eq!(parse_stat(stat), Some(("my (odd) daemon".to_string(), 'S', 987654)));
+        assert_eq!(parse_rss_kb("Name:\tnginx\nVmPeak:\t  20000 kB\nVmRSS:\t   8192 kB\n"), Some(8192));
+        assert_eq!(parse_uptime("350735.47 234388.90\n"), Some(350735.47));
+
+        let probe = ProcessProbe::new(ProcessSettings {
+            name: Some("postgres-primary".to_string()),
+            pattern: Some("-D /var/lib/pg".to_string()),
+            ..ProcessSettings::default()
+        });
+        let process = ProcessInfo {
+            pid: 10,
+            // Truncated by the kernel
+            name: "postgres-primar".to_string(),
+            command_line: "/usr/bin/postgres-primary -D /var/lib/pg".to_string(),
+            memory_bytes: 0,
+            uptime: Duration::ZERO,
+        };
+        assert!(probe.matches(&process));
+        assert!(!probe.matches(&ProcessInfo { command_line: "/usr/bin/postgres-primary -D /tmp".to_string(), ..process }));
+    }
+}
@@ -0,0 +1,57 @@
+// Formatting shared by the status page and the SLA reports
+
+pub fn format_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        s => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub const STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; max-width: 900px; margin: 2em auto; padding: 0 1em; color: #222; }
+h1 { font-weight: 600; }
+h2 { font-size: 1.1em; border-bottom: 1px solid #ddd; padding-bottom: .3em; }
+.summary { float: right; font-weight: normal; color: #666; }
+.banner { padding: .8em 1em; border-radius: 6px; color: #fff; font-weight: 600; margin-bottom: 1.5em; }
+.banner.ok { background: #2e9e5b; } .banner.partial { background: #e08a1e; } .banner.down { background: #d64545; }
+.system { margin: 1em 0 1.5em; }
+.row { display: flex; justify-content: space-between; }
+.name { font-weight: 600; }
+.state.ok { color: #2e9e5b; } .state.degraded { color: #e08a1e; } .state.down { color: #d64545; } .state.paused { color: #888; }
+.bars { display: flex; gap: 2px; margin: .4em 0; }
+.bar { flex: 1; height: 28px; border-radius: 2px; background: #ccc; }
+.bar.ok { background: #2e9e5b; } .bar.minor { background: #9ccc65; } .bar.partial { background: #e08a1e; } .bar.down { background: #d64545; }
+.legend { font-size: .8em; color: #888; }
+table { width: 100%; border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { text-align: left; padding: .4em; border-bottom: 1px solid #eee; }
+.muted, footer { color: #888; font-size: .9em; }
+footer { margin-top: 2em; }
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(-5), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(125), "2m 5s");
+        assert_eq!(format_duration(7260), "2h 1m");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r#"<a href="x">Tom & Jerry's</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+    }
+}
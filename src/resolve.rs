@@ -0,0 +1,214 @@
+use crate::config::AddressFamily;
+use anyhow::Result;
+use dashmap::DashMap;
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use std::net::{IpAddr, SocketAddr};
+
+const DEFAULT_DNS_PORT: u16 = 53;
+
+pub struct Resolution {
+    pub addresses: Vec<IpAddr>,
+    // Time spent in the resolver; None for IP literals
+    pub elapsed_ms: Option<f64>,
+}
+
+// One resolver per DNS server in use. Each keeps its own cache and honours record TTLs, so
+// repeated checks of the same host only hit the network once the answer expires.
+pub struct DnsResolver {
+    resolvers: DashMap<Option<SocketAddr>, TokioAsyncResolver>,
+}
+
+impl DnsResolver {
+    pub fn new() -> Self {
+        Self {
+            resolvers: DashMap::new(),
+        }
+    }
+
+    // `server` overrides the system resolver, e.g. "10.0.0.53" or "[2001:db8::53]:5353"
+    pub async fn resolve(&self, host: &str, family: AddressFamily, server: Option<&str>) -> Result<Resolution> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(Resolution {
+                addresses: select(host, &[ip], family)?,
+                elapsed_ms: None,
+            });
+        }
+
+        let resolver = self.resolver_for(server.map(parse_server).transpose()?);
+        let start_time = std::time::Instant::now();
+        let lookup = resolver.lookup_ip(host).await.map_err(|e| match e.kind() {
+            ResolveErrorKind::NoRecordsFound { response_code, .. } =>
+                anyhow::anyhow!("Could not resolve {}: no address records ({})", host, response_code),
+            _ => anyhow::anyhow!("Could not resolve {}: {}", host, e),
+        })?;
+        let elapsed_ms = start_time.elapsed().as_secs_f64() * 1000.0;
+
+        let mut addresses: Vec<IpAddr> = Vec::new();
+        for address in lookup.iter() {
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        Ok(Resolution {
+            addresses: select(host, &addresses, family)?,
+            elapsed_ms: Some(elapsed_ms),
+        })
+    }
+
+    // Name of the address from its PTR record, without the trailing dot; None when it has none
+    pub async fn reverse(&self, address: IpAddr) -> Option<String> {
+        let lookup = self.resolver_for(None).reverse_lookup(address).await.ok()?;
+        let name = lookup.iter().next()?.to_utf8();
+        Some(name.trim_end_matches('.').to_string())
+    }
+
+    fn resolver_for(&self, server: Option<SocketAddr>) -> TokioAsyncResolver {
+        self.resolvers.entry(server)
+            .or_insert_with(|| {
+                let (config, mut options) = match server {
+                    Some(server) => {
+                        let servers = NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true);
+                        let mut options = ResolverOpts::default();
+                        // A dedicated server is there to test its answers, not the local hosts file
+                        options.use_hosts_file = false;
+                        (ResolverConfig::from_parts(None, Vec::new(), servers), options)
+                    }
+                    None => read_system_conf().unwrap_or_else(|e| {
+                        log::warn!("Could not read the system DNS configuration, using defaults: {}", e);
+                        (ResolverConfig::default(), ResolverOpts::default())
+                    }),
+                };
+                // Address family selection happens afterwards, so always ask for both
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                TokioAsyncResolver::tokio(config, options)
+            })
+            .clone()
+    }
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_server(server: &str) -> Result<SocketAddr> {
+    server.parse::<SocketAddr>()
+        .or_else(|_| server.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_DNS_PORT)))
+        .map_err(|_| anyhow::anyhow!("Invalid DNS server '{}', expected an IP address with an optional port", server))
+}
+
+fn select(host: &str, addresses: &[IpAddr], family: AddressFamily) -> Result<Vec<IpAddr>> {
+    let first_v4 = addresses.iter().find(|a| a.is_ipv4()).copied();
+    let first_v6 = addresses.iter().find(|a| a.is_ipv6()).copied();
+    let selected = match family {
+        AddressFamily::Auto => addresses.first().copied().into_iter().collect(),
+        AddressFamily::V4 => first_v4.into_iter().collect(),
+        AddressFamily::V6 => first_v6.into_iter().collect(),
+        AddressFamily::PreferV6 => first_v6.or(first_v4).into_iter().collect(),
+        AddressFamily::Both => addresses.to_vec(),
+    };
+    if selected.is_empty() {
+        let wanted = match family {
+            AddressFamily::V4 => "IPv4 address",
+            AddressFamily::V6 => "IPv6 address",
+            _ => "address",
+        };
+        return Err(anyhow::anyhow!("Could not resolve {} to an {}", host, wanted));
+    }
+    Ok(selected)
+}
+
+pub fn family_label(address: &IpAddr) -> &'static str {
+    if address.is_ipv4() { "IPv4" } else { "IPv6" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_by_family() {
+        let addresses: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap(), "192.0.2.2".parse().unwrap()];
+        assert_eq!(select("h", &addresses, AddressFamily::Auto).unwrap(), vec![addresses[0]]);
+        assert_eq!(select("h", &addresses, AddressFamily::V6).unwrap(), vec![addresses[1]]);
+        assert_eq!(select("h", &addresses, AddressFamily::PreferV6).unwrap(), vec![addresses[1]]);
+        assert_eq!(select("h", &addresses, AddressFamily::Both).unwrap().len(), 3);
+        assert_eq!(select("h", &addresses[..1], AddressFamily::PreferV6).unwrap(), vec![addresses[0]]);
+        assert!(select("h", &addresses[..1], AddressFamily::V6).is_err());
+    }
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server("10.0.0.53").unwrap(), "10.0.0.53:53".parse().unwrap());
+        assert_eq!(parse_server("10.0.0.53:5353").unwrap(), "10.0.0.53:5353".parse().unwrap());
+        assert_eq!(parse_server("[2001:db8::53]:5353").unwrap(), "[2001:db8::53]:5353".parse().unwrap());
+        assert_eq!(parse_server("2001:db8::53").unwrap(), "[2001:db8::53]:53".parse().unwrap());
+        assert!(parse_server("dns.example.com").is_err());
+    }
+
+    // Answers A queries with 192.0.2.7 and AAAA queries with 2001:db8::7, and anything about
+    // missing.test with NXDOMAIN
+    async fn serve_fake_dns(socket: tokio::net::UdpSocket) {
+        let mut buffer = [0u8; 512];
+        while let Ok((length, peer)) = socket.recv_from(&mut buffer).await {
+            let query = &buffer[..length];
+            let mut end = 12;
+            let mut labels = Vec::new();
+            while query[end] != 0 {
+                let size = query[end] as usize;
+                labels.push(String::from_utf8_lossy(&query[end + 1..end + 1 + size]).to_string());
+                end += size + 1;
+            }
+            let record_type = u16::from_be_bytes([query[end + 1], query[end + 2]]);
+            let question = &query[12..end + 5];
+            let rdata: Vec<u8> = match record_type {
+                1 => vec![192, 0, 2, 7],
+                28 => "2001:db8::7".parse::<std::net::Ipv6Addr>().unwrap().octets().to_vec(),
+                _ => Vec::new(),
+            };
+            let missing = labels.join(".") == "missing.test";
+            let answers = u16::from(!rdata.is_empty() && !missing);
+
+            let mut response = query[..2].to_vec();
+            response.extend_from_slice(&[0x81, if missing { 0x83 } else { 0x80 }]);
+            response.extend_from_slice(&[0, 1, 0, answers as u8, 0, 0, 0, 0]);
+            response.extend_from_slice(question);
+            if answers > 0 {
+                response.extend_from_slice(&[0xc0, 0x0c]);
+                response.extend_from_slice(&record_type.to_be_bytes());
+                response.extend_from_slice(&[0, 1, 0, 0, 0, 60]);
+                response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                response.extend_from_slice(&rdata);
+            }
+            let _ = socket.send_to(&response, peer).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_custom_server() {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap().to_string();
+        tokio::spawn(serve_fake_dns(socket));
+
+        let resolver = DnsResolver::new();
+        let both = resolver.resolve("app.test", AddressFamily::Both, Some(&server)).await.unwrap();
+        let mut addresses = both.addresses.clone();
+        addresses.sort();
+        assert_eq!(addresses, vec!["192.0.2.7".parse::<IpAddr>().unwrap(), "2001:db8::7".parse().unwrap()]);
+        assert!(both.elapsed_ms.is_some());
+
+        let v6 = resolver.resolve("app.test", AddressFamily::V6, Some(&server)).await.unwrap();
+        assert_eq!(v6.addresses, vec!["2001:db8::7".parse::<IpAddr>().unwrap()]);
+
+        let error = resolver.resolve("missing.test", AddressFamily::Auto, Some(&server)).await.err().unwrap();
+        assert!(error.to_string().starts_with("Could not resolve missing.test"), "{}", error);
+
+        // IP literals never reach a server
+        let literal = resolver.resolve("192.0.2.9", AddressFamily::Auto, Some("invalid")).await.unwrap();
+        assert_eq!(literal.elapsed_ms, None);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::config::SchedulerConfig;
+use crate::latency::{LatencySummary, LatencyWindows};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::AbortHandle;
+use tokio::time::{sleep_until, Instant};
+use uuid::Uuid;
+
+// Runs one check of a system and returns the delay until the next one, or None when the
+// system no longer exists
+pub type CheckRunner = Arc<dyn Fn(Uuid) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send>> + Send + Sync>;
+
+// Checks further behind schedule than this are logged
+const LAG_WARNING: Duration = Duration::from_secs(5);
+
+enum Command {
+    // (Re)schedules a system, cancelling a check of it that is still running
+    Schedule { id: Uuid, protocol: String, due: Instant },
+    Remove(Uuid),
+}
+
+struct Entry {
+    generation: u64,
+    protocol: String,
+    running: Option<AbortHandle>,
+}
+
+// A single task that starts every check when it is due, instead of one sleeping loop per
+// system. Checks wait for a global and a per-protocol permit before they run; how late
+// they start is recorded as scheduling lag.
+pub struct Scheduler {
+    commands: mpsc::UnboundedSender<Command>,
+    // Taken when the first system is scheduled
+    pending: Mutex<Option<(mpsc::UnboundedReceiver<Command>, SchedulerConfig)>>,
+    stats: Arc<Stats>,
+}
+
+#[derive(Default)]
+struct Stats {
+    scheduled: AtomicUsize,
+    waiting: AtomicUsize,
+    running: AtomicUsize,
+    started: AtomicU64,
+    last_lag_ms: Mutex<f64>,
+    lag: Mutex<LatencyWindows>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerStats {
+    pub scheduled: usize,
+    // Due checks waiting for a concurrency permit
+    pub waiting: usize,
+    pub running: usize,
+    pub checks_started: u64,
+    pub last_lag_ms: f64,
+    // Delay between when checks were due and when they started
+    pub lag: Vec<LatencySummary>,
+}
+
+// Decrements a gauge when dropped, also when the check is cancelled
+struct GaugeGuard<'a>(&'a AtomicUsize);
+
+impl<'a> GaugeGuard<'a> {
+    fn new(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Scheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        Self {
+            commands,
+            pending: Mutex::new(Some((receiver, config))),
+            stats: Arc::new(Stats::default()),
+        }
+    }
+
+    // Starts the scheduling task on first use; later calls keep the first runner
+    pub fn start(&self, runner: CheckRunner) {
+        let pending = self.pending.lock().ok().and_then(|mut pending| pending.take());
+        if let Some((receiver, config)) = pending {
+            tokio::spawn(run(receiver, config, runner, Arc::clone(&self.stats)));
+        }
+    }
+
+    pub fn schedule(&self, id: Uuid, protocol: String, due: Instant) {
+        let _ = self.commands.send(Command::Schedule { id, protocol, due });
+    }
+
+    pub fn remove(&self, id: Uuid) {
+        let _ = self.commands.send(Command::Remove(id));
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            scheduled: self.stats.scheduled.load(Ordering::Relaxed),
+            waiting: self.stats.waiting.load(Ordering::Relaxed),
+            running: self.stats.running.load(Ordering::Relaxed),
+            checks_started: self.stats.started.load(Ordering::Relaxed),
+            last_lag_ms: self.stats.last_lag_ms.lock().map_or(0.0, |lag| *lag),
+            lag: self.stats.lag.lock().map_or_else(|_| Vec::new(), |lag| lag.summaries()),
+        }
+    }
+}
+
+// Fixed offset within the interval derived from the system's key, so checks are spread
+// out and each system keeps the same slot across restarts
+pub fn phase(key: &str, interval: Duration) -> Duration {
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let interval_ms = interval.as_millis().max(1) as u64;
+    Duration::from_millis(hash % interval_ms)
+}
+
+async fn run(
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    config: SchedulerConfig,
+    runner: CheckRunner,
+    stats: Arc<Stats>,
+) {
+    let global = Arc::new(Semaphore::new(config.max_concurrent_checks.max(1)));
+    let protocol_limits: HashMap<String, Arc<Semaphore>> = config.protocol_limits.iter()
+        .map(|(protocol, limit)| (protocol.to_ascii_lowercase(), Arc::new(Semaphore::new((*limit).max(1)))))
+        .collect();
+
+    let mut queue: BinaryHeap<Reverse<(Instant, u64, Uuid)>> = BinaryHeap::new();
+    let mut entries: HashMap<Uuid, Entry> = HashMap::new();
+    let mut next_generation = 0u64;
+    let (done_sender, mut done) = mpsc::unbounded_channel::<(Uuid, u64, Option<Instant>)>();
+
+    loop {
+        let next_due = queue.peek().map(|Reverse((due, _, _))| *due);
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Schedule { id, protocol, due }) => {
+                    next_generation += 1;
+                    if let Some(running) = entries.get_mut(&id).and_then(|entry| entry.running.take()) {
+                        running.abort();
+                    }
+                    entries.insert(id, Entry { generation: next_generation, protocol, running: None });
+                    queue.push(Reverse((due, next_generation, id)));
+                }
+                Some(Command::Remove(id)) => {
+                    if let Some(running) = entries.remove(&id).and_then(|entry| entry.running) {
+                        running.abort();
+                    }
+                }
+                // The manager is gone
+                None => break,
+            },
+            Some((id, generation, next)) = done.recv() => {
+                // Ignored when the system was rescheduled or removed while the check ran
+                if let Some(entry) = entries.get_mut(&id).filter(|entry| entry.generation == generation) {
+                    entry.running = None;
+                    match next {
+                        Some(due) => queue.push(Reverse((due, generation, id))),
+                        None => { entries.remove(&id); }
+                    }
+                }
+            },
+            _ = sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                let now = Instant::now();
+                while let Some(Reverse((due, generation, id))) = queue.peek().copied() {
+                    if due > now {
+                        break;
+                    }
+                    queue.pop();
+                    let Some(entry) = entries.get_mut(&id).filter(|entry| entry.generation == generation) else {
+                        continue;
+                    };
+                    let job = Job {
+                        id,
+                        generation,
+                        due,
+                        global: Arc::clone(&global),
+                        protocol: protocol_limits.get(&entry.protocol).cloned(),
+                        runner: Arc::clone(&runner),
+                        stats: Arc::clone(&stats),
+                        done: done_sender.clone(),
+                    };
+                    entry.running = Some(tokio::spawn(job.run()).abort_handle());
+                }
+            },
+        }
+        stats.scheduled.store(entries.len(), Ordering::Relaxed);
+    }
+
+    for running in entries.into_values().filter_map(|entry| entry.running) {
+        running.abort();
+    }
+}
+
+struct Job {
+    id: Uuid,
+    generation: u64,
+    due: Instant,
+    global: Arc<Semaphore>,
+    protocol: Option<Arc<Semaphore>>,
+    runner: CheckRunner,
+    stats: Arc<Stats>,
+    done: mpsc::UnboundedSender<(Uuid, u64, Option<Instant>)>,
+}
+
+impl Job {
+    async fn run(self) {
+        let permits = {
+            let _waiting = GaugeGuard::new(&self.stats.waiting);
+            // Protocol first, so a check queued behind its protocol's limit doesn't hold a
+            // global permit other protocols could use
+            let protocol = match &self.protocol {
+                Some(semaphore) => Some(Arc::clone(semaphore).acquire_owned().await),
+                None => None,
+            };
+            let global = Arc::clone(&self.global).acquire_owned().await;
+            (protocol, global)
+        };
+
+        let lag = Instant::now().saturating_duration_since(self.due);
+        if lag > LAG_WARNING {
+            log::warn!("Check started {:.1}s late, consider raising the concurrency limits", lag.as_secs_f64());
+        }
+        let lag_ms = lag.as_secs_f64() * 1000.0;
+        if let Ok(mut last) = self.stats.last_lag_ms.lock() {
+            *last = lag_ms;
+        }
+        if let Ok(mut windows) = self.stats.lag.lock() {
+            windows.add(Utc::now(), lag_ms);
+        }
+        self.stats.started.fetch_add(1, Ordering::Relaxed);
+
+        let interval = {
+            let _running = GaugeGuard::new(&self.stats.running);
+            (self.runner)(self.id).await
+        };
+        drop(permits);
+
+        // Fixed rate from the due time keeps systems in their slot; a check that overran
+        // its interval is followed by the next one straight away
+        let next = interval.map(|interval| (self.due + interval).max(Instant::now()));
+        let _ = self.done.send((self.id, self.generation, next));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_is_stable_and_within_interval() {
+        let interval = Duration::from_secs(30);
+        let a = phase("google-dns-8.8.8.8-0-ping", interval);
+        assert_eq!(a, phase("google-dns-8.8.8.8-0-ping", interval));
+        assert!(a < interval);
+        assert_ne!(a, phase("cloudflare-dns-1.1.1.1-0-ping", interval));
+        assert_eq!(phase("anything", Duration::ZERO), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrency_limit() {
+        // Peak number of checks running at once, overall and of ping systems
+        #[derive(Default)]
+        struct Peaks {
+            running: usize,
+            ping: usize,
+            most: usize,
+            most_ping: usize,
+            runs: u64,
+        }
+        let peaks = Arc::new(Mutex::new(Peaks::default()));
+        let ids: Vec<Uuid> = (0..6).map(|_| Uuid::new_v4()).collect();
+        let pings: Vec<Uuid> = ids.iter().step_by(2).copied().collect();
+        let runner: CheckRunner = {
+            let peaks = Arc::clone(&peaks);
+            Arc::new(move |id| {
+                let (peaks, is_ping) = (Arc::clone(&peaks), pings.contains(&id));
+                Box::pin(async move {
+                    {
+                        let mut peaks = peaks.lock().unwrap();
+                        peaks.running += 1;
+                        peaks.ping += usize::from(is_ping);
+                        peaks.most = peaks.most.max(peaks.running);
+                        peaks.most_ping = peaks.most_ping.max(peaks.ping);
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    let mut peaks = peaks.lock().unwrap();
+                    peaks.running -= 1;
+                    peaks.ping -= usize::from(is_ping);
+                    peaks.runs += 1;
+                    Some(Duration::from_secs(60))
+                })
+            })
+        };
+
+        let config = SchedulerConfig { max_concurrent_checks: 4, protocol_limits: [("ping".to_string(), 2)].into() };
+        let scheduler = Scheduler::new(config);
+        scheduler.start(runner);
+        for (i, id) in ids.iter().enumerate() {
+            let protocol = if i % 2 == 0 { "ping" } else { "tcp" };
+            scheduler.schedule(*id, protocol.to_string(), Instant::now());
+        }
+        // Well short of the 60s interval, so every system runs exactly once
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let peaks = peaks.lock().unwrap();
+        assert_eq!(peaks.runs, 6);
+        assert_eq!(peaks.most_ping, 2);
+        assert_eq!(peaks.most, 4);
+        let stats = scheduler.stats();
+        assert_eq!((stats.scheduled, stats.running, stats.checks_started), (6, 0, 6));
+    }
+}
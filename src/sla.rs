@@ -0,0 +1,504 @@
+use crate::config::{BusinessHours, Config, SlaConfig, SlaPeriod, SystemConfig};
+use crate::history::{self, CheckRecord, HistoryStore, IncidentKind};
+use crate::report::{escape, format_duration, STYLE};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
+
+// Half-open time range [start, end)
+pub type Span = (DateTime<Utc>, DateTime<Utc>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+// A calendar period in local time, e.g. "2024-05", "2024-Q2" or "2024"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportPeriod {
+    pub label: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaReport {
+    pub sla: String,
+    pub target_percent: f64,
+    pub period: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub business_hours: Option<String>,
+    pub generated_at: DateTime<Utc>,
+    pub breached: bool,
+    pub systems: Vec<SystemSla>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemSla {
+    pub name: String,
+    pub group: Option<String>,
+    pub checks: usize,
+    // Time the SLA applies to within the period, after maintenance is taken out
+    pub counted_seconds: i64,
+    pub maintenance_seconds: i64,
+    pub downtime_seconds: i64,
+    // None when there is no history for the period
+    pub availability_percent: Option<f64>,
+    // Downtime the target allows for the counted time
+    pub budget_seconds: i64,
+    pub budget_consumed_percent: Option<f64>,
+    pub breached: bool,
+    pub incidents: Vec<SlaIncident>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaIncident {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    // Part of the outage that counted against the SLA
+    pub downtime_seconds: i64,
+    pub first_error: Option<String>,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Html => "html",
+            ReportFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            "json" => Ok(ReportFormat::Json),
+            other => Err(anyhow::anyhow!("Unknown report format '{}', expected markdown, html or json", other)),
+        }
+    }
+}
+
+impl ReportPeriod {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let invalid = || anyhow::anyhow!("Invalid period '{}', expected YYYY-MM, YYYY-Qn or YYYY", input);
+        let (year, rest) = input.split_once('-').unwrap_or((input, ""));
+        let year: i32 = year.parse().map_err(|_| invalid())?;
+        let (kind, start) = if rest.is_empty() {
+            (SlaPeriod::Year, NaiveDate::from_ymd_opt(year, 1, 1))
+        } else if let Some(quarter) = rest.strip_prefix(['Q', 'q']) {
+            let quarter: u32 = quarter.parse().ok().filter(|q| (1..=4).contains(q)).ok_or_else(invalid)?;
+            (SlaPeriod::Quarter, NaiveDate::from_ymd_opt(year, (quarter - 1) * 3 + 1, 1))
+        } else {
+            let month: u32 = rest.parse().map_err(|_| invalid())?;
+            (SlaPeriod::Month, NaiveDate::from_ymd_opt(year, month, 1))
+        };
+        Self::starting(kind, start.ok_or_else(invalid)?)
+    }
+
+    // The last complete period of this kind before `today`
+    pub fn previous(kind: SlaPeriod, today: NaiveDate) -> Result<Self> {
+        let current = period_start(kind, today);
+        Self::starting(kind, period_start(kind, current - Duration::days(1)))
+    }
+
+    fn starting(kind: SlaPeriod, start: NaiveDate) -> Result<Self> {
+        let end = match kind {
+            SlaPeriod::Month => start.checked_add_months(chrono::Months::new(1)),
+            SlaPeriod::Quarter => start.checked_add_months(chrono::Months::new(3)),
+            SlaPeriod::Year => start.checked_add_months(chrono::Months::new(12)),
+        }.ok_or_else(|| anyhow::anyhow!("Period starting {} is out of range", start))?;
+        let label = match kind {
+            SlaPeriod::Month => start.format("%Y-%m").to_string(),
+            SlaPeriod::Quarter => format!("{}-Q{}", start.year(), start.month0() / 3 + 1),
+            SlaPeriod::Year => start.year().to_string(),
+        };
+        Ok(Self { label, from: local_midnight(start)?, to: local_midnight(end)? })
+    }
+}
+
+fn period_start(kind: SlaPeriod, date: NaiveDate) -> NaiveDate {
+    let month = match kind {
+        SlaPeriod::Month => date.month(),
+        SlaPeriod::Quarter => date.month0() / 3 * 3 + 1,
+        SlaPeriod::Year => 1,
+    };
+    NaiveDate::from_ymd_opt(date.year(), month, 1).unwrap_or(date)
+}
+
+fn local_midnight(date: NaiveDate) -> Result<DateTime<Utc>> {
+    Local.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or_else(|| anyhow::anyhow!("Midnight of {} does not exist in the local time zone", date))
+}
+
+// Reports for the named SLA, or every SLA when `name` is None. Each covers `period`, or
+// the SLA's last complete period when that isn't given.
+pub async fn generate(config: &Config, name: Option<&str>, period: Option<&str>) -> Result<Vec<SlaReport>> {
+    if !config.history.enabled {
+        anyhow::bail!("History is disabled in the configuration, there is nothing to report on");
+    }
+    let slas: Vec<&SlaConfig> = config.slas.iter()
+        .filter(|sla| name.is_none_or(|name| sla.name.eq_ignore_ascii_case(name)))
+        .collect();
+    if slas.is_empty() {
+        match name {
+            Some(name) => anyhow::bail!("No SLA named '{}' in the configuration", name),
+            None => anyhow::bail!("No SLAs are defined in the configuration"),
+        }
+    }
+
+    let store = HistoryStore::new(&config.history.directory, config.history.retention_days);
+    let period = period.map(ReportPeriod::parse).transpose()?;
+    let mut reports = Vec::new();
+    for sla in slas {
+        let period = match &period {
+            Some(period) => period.clone(),
+            None => ReportPeriod::previous(sla.period, Local::now().date_naive())?,
+        };
+        // Stop at the current time for a period that hasn't ended yet
+        let span = (period.from, period.to.min(Utc::now()));
+        let business = business_spans(sla.business_hours.as_ref(), span, &Local);
+
+        let mut systems = Vec::new();
+        for system in config.systems.iter().filter(|s| sla.covers(s)) {
+            let maintenance: Vec<Span> = config.maintenance.iter()
+                .filter(|window| window.applies_to(system))
+                .map(|window| (window.start, window.end))
+                .collect();
+            // A day of lead-in so an outage already running at the start of the period is seen
+            let checks = store.load(&system.storage_key(), Some(span.0 - Duration::days(1)), Some(span.1)).await?;
+            systems.push(system_report(system, &checks, sla.target_percent, span, &business, &maintenance));
+        }
+
+        reports.push(SlaReport {
+            sla: sla.name.clone(),
+            target_percent: sla.target_percent,
+            period: period.label,
+            from: period.from,
+            to: period.to,
+            business_hours: sla.business_hours.as_ref().map(|hours| hours.to_string()),
+            generated_at: Utc::now(),
+            breached: systems.iter().any(|s| s.breached),
+            systems,
+        });
+    }
+    Ok(reports)
+}
+
+fn system_report(
+    system: &SystemConfig,
+    checks: &[CheckRecord],
+    target_percent: f64,
+    span: Span,
+    business: &[Span],
+    maintenance: &[Span],
+) -> SystemSla {
+    let counted = subtract(business, maintenance);
+    let counted_seconds = total_seconds(&counted);
+    let maintenance_seconds = total_seconds(business) - counted_seconds;
+
+    let incidents: Vec<SlaIncident> = history::incidents_from_checks(checks).into_iter()
+        .filter(|incident| incident.kind == IncidentKind::Outage)
+        .filter(|incident| incident.started_at < span.1 && incident.ended_at.is_none_or(|end| end > span.0))
+        .map(|incident| {
+            let outage = (incident.started_at.max(span.0), incident.ended_at.unwrap_or(span.1).min(span.1));
+            SlaIncident {
+                started_at: incident.started_at,
+                ended_at: incident.ended_at,
+                downtime_seconds: overlap_seconds(outage, &counted),
+                first_error: incident.first_error,
+            }
+        })
+        .collect();
+
+    let checks_in_period = checks.iter().filter(|c| c.timestamp >= span.0 && c.timestamp < span.1).count();
+    let downtime_seconds: i64 = incidents.iter().map(|i| i.downtime_seconds).sum();
+    let budget_seconds = (counted_seconds as f64 * (100.0 - target_percent) / 100.0).round() as i64;
+    let availability_percent = (checks_in_period > 0 && counted_seconds > 0)
+        .then(|| 100.0 * (counted_seconds - downtime_seconds) as f64 / counted_seconds as f64);
+    let budget_consumed_percent = (budget_seconds > 0)
+        .then(|| 100.0 * downtime_seconds as f64 / budget_seconds as f64);
+
+    SystemSla {
+        name: system.name.clone(),
+        group: system.group.clone(),
+        checks: checks_in_period,
+        counted_seconds,
+        maintenance_seconds,
+        downtime_seconds,
+        availability_percent,
+        budget_seconds,
+        budget_consumed_percent,
+        breached: availability_percent.is_some_and(|a| a < target_percent),
+        incidents,
+    }
+}
+
+// The parts of `span` within business hours, evaluated in `tz`
+pub fn business_spans<Tz: TimeZone>(hours: Option<&BusinessHours>, span: Span, tz: &Tz) -> Vec<Span> {
+    let Some(hours) = hours else {
+        return vec![span];
+    };
+    let mut spans = Vec::new();
+    let mut date = span.0.with_timezone(tz).date_naive();
+    let last = span.1.with_timezone(tz).date_naive();
+    while date <= last {
+        if hours.days.contains(&date.weekday()) {
+            let start = tz.from_local_datetime(&date.and_time(hours.start)).earliest();
+            let end = tz.from_local_datetime(&date.and_time(hours.end)).earliest();
+            if let (Some(start), Some(end)) = (start, end) {
+                let start = start.with_timezone(&Utc).max(span.0);
+                let end = end.with_timezone(&Utc).min(span.1);
+                if start < end {
+                    spans.push((start, end));
+                }
+            }
+        }
+        date = match date.succ_opt() {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    spans
+}
+
+// `spans` with every part covered by `cuts` removed
+pub fn subtract(spans: &[Span], cuts: &[Span]) -> Vec<Span> {
+    let mut remaining = spans.to_vec();
+    for &(cut_start, cut_end) in cuts {
+        remaining = remaining.into_iter()
+            .flat_map(|(start, end)| {
+                if cut_end <= start || cut_start >= end {
+                    return vec![(start, end)];
+                }
+                let mut pieces = Vec::new();
+                if start < cut_start {
+                    pieces.push((start, cut_start));
+                }
+                if cut_end < end {
+                    pieces.push((cut_end, end));
+                }
+                pieces
+            })
+            .collect();
+    }
+    remaining
+}
+
+fn overlap_seconds(span: Span, spans: &[Span]) -> i64 {
+    spans.iter()
+        .map(|&(start, end)| (end.min(span.1) - start.max(span.0)).num_seconds().max(0))
+        .sum()
+}
+
+fn total_seconds(spans: &[Span]) -> i64 {
+    spans.iter().map(|(start, end)| (*end - *start).num_seconds()).sum()
+}
+
+pub fn render(reports: &[SlaReport], format: ReportFormat) -> Result<String> {
+    match format {
+        ReportFormat::Markdown => Ok(render_markdown(reports)),
+        ReportFormat::Html => Ok(render_html(reports)),
+        ReportFormat::Json => Ok(serde_json::to_string_pretty(reports)? + "\n"),
+    }
+}
+
+fn local_time(timestamp: DateTime<Utc>) -> String {
+    timestamp.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn percent(value: Option<f64>) -> String {
+    value.map_or("no data".to_string(), |v| format!("{:.3}%", v))
+}
+
+fn status(system: &SystemSla) -> &'static str {
+    match (system.availability_percent, system.breached) {
+        (None, _) => "No data",
+        (Some(_), true) => "Breached",
+        (Some(_), false) => "Met",
+    }
+}
+
+// Table cells end at an unescaped pipe or a line break
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+fn render_markdown(reports: &[SlaReport]) -> String {
+    let mut out = String::new();
+    for report in reports {
+        let _ = writeln!(out, "# SLA report: {} ({})\n", report.sla, report.period);
+        let _ = writeln!(out, "- Period: {} to {}", local_time(report.from), local_time(report.to));
+        let _ = writeln!(out, "- Target: {}%", report.target_percent);
+        if let Some(hours) = &report.business_hours {
+            let _ = writeln!(out, "- Business hours: {}", hours);
+        }
+        let _ = writeln!(out, "- Status: {}\n", if report.breached { "**Breached**" } else { "Met" });
+
+        out.push_str("| System | Availability | Downtime | Budget used | Maintenance | Status |\n");
+        out.push_str("|---|---|---|---|---|---|\n");
+        for system in &report.systems {
+            let _ = writeln!(out, "| {} | {} | {} | {} | {} | {} |",
+                markdown_cell(&system.name),
+                percent(system.availability_percent),
+                format_duration(system.downtime_seconds),
+                system.budget_consumed_percent.map_or("-".to_string(), |p| format!("{:.0}%", p)),
+                format_duration(system.maintenance_seconds),
+                status(system));
+        }
+
+        for system in report.systems.iter().filter(|s| !s.incidents.is_empty()) {
+            let _ = writeln!(out, "\n## Incidents: {}\n", system.name);
+            out.push_str("| Started | Ended | Counted downtime | First error |\n|---|---|---|---|\n");
+            for incident in &system.incidents {
+                let _ = writeln!(out, "| {} | {} | {} | {} |",
+                    local_time(incident.started_at),
+                    incident.ended_at.map_or("ongoing".to_string(), local_time),
+                    format_duration(incident.downtime_seconds),
+                    markdown_cell(incident.first_error.as_deref().unwrap_or("")));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn render_html(reports: &[SlaReport]) -> String {
+    let mut html = String::new();
+    let _ = write!(html, r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>SLA report</title>
+<style>{STYLE}</style>
+</head>
+<body>
+"#);
+    for report in reports {
+        let _ = write!(html, r#"<h1>SLA report: {} ({})</h1>
+<p class="muted">{} to {} &middot; target {}%{}</p>
+<div class="banner {}">{}</div>
+<table>
+<tr><th>System</th><th>Availability</th><th>Downtime</th><th>Budget used</th><th>Maintenance</th><th>Status</th></tr>
+"#,
+            escape(&report.sla), escape(&report.period),
+            local_time(report.from), local_time(report.to), report.target_percent,
+            report.business_hours.as_ref().map_or(String::new(), |h| format!(" &middot; business hours {}", escape(h))),
+            if report.breached { "down" } else { "ok" },
+            if report.breached { "SLA breached" } else { "SLA met" });
+        for system in &report.systems {
+            let _ = writeln!(html, r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class="state {}">{}</td></tr>"#,
+                escape(&system.name),
+                percent(system.availability_percent),
+                format_duration(system.downtime_seconds),
+                system.budget_consumed_percent.map_or("-".to_string(), |p| format!("{:.0}%", p)),
+                format_duration(system.maintenance_seconds),
+                if system.breached { "down" } else { "ok" },
+                status(system));
+        }
+        html.push_str("</table>\n");
+
+        for system in report.systems.iter().filter(|s| !s.incidents.is_empty()) {
+            let _ = writeln!(html, "<h2>Incidents: {}</h2>\n<table>\n<tr><th>Started</th><th>Ended</th><th>Counted downtime</th><th>First error</th></tr>",
+                escape(&system.name));
+            for incident in &system.incidents {
+                let _ = writeln!(html, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    local_time(incident.started_at),
+                    incident.ended_at.map_or("ongoing".to_string(), local_time),
+                    format_duration(incident.downtime_seconds),
+                    escape(incident.first_error.as_deref().unwrap_or("")));
+            }
+            html.push_str("</table>\n");
+        }
+    }
+    let _ = write!(html, "<footer>Generated {}</footer>\n</body>\n</html>\n", local_time(Utc::now()));
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Protocol;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 6, hour, minute, 0).unwrap()
+    }
+
+    fn check(timestamp: DateTime<Utc>, is_online: bool) -> CheckRecord {
+        CheckRecord {
+            timestamp,
+            is_online,
+            response_time_ms: is_online.then_some(10),
+            error: (!is_online).then(|| "timeout".to_string()),
+            failure: None,
+            degraded: None,
+            timings: Default::default(),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_business_hours_and_maintenance() {
+        // Monday 2024-05-06, 09:00-17:00 counted, 12:00-13:00 maintenance
+        let span = (at(0, 0), at(0, 0) + Duration::days(1));
+        let business = business_spans(Some(&BusinessHours::default()), span, &Utc);
+        assert_eq!(business, vec![(at(9, 0), at(17, 0))]);
+
+        // Down 08:00-10:00 (one counted hour) and 12:00-12:30 (inside maintenance)
+        let checks = vec![
+            check(at(7, 0), true), check(at(8, 0), false), check(at(10, 0), true),
+            check(at(12, 0), false), check(at(12, 30), true), check(at(16, 0), true),
+        ];
+        let system = SystemConfig::new("web".to_string(), "10.0.0.1".to_string(), None, Protocol::Ping);
+        let report = system_report(&system, &checks, 99.0, span, &business, &[(at(12, 0), at(13, 0))]);
+
+        assert_eq!(report.counted_seconds, 7 * 3600);
+        assert_eq!(report.maintenance_seconds, 3600);
+        assert_eq!(report.downtime_seconds, 3600);
+        assert_eq!(report.incidents.len(), 2);
+        assert!(report.breached);
+        let availability = report.availability_percent.unwrap();
+        assert!((availability - 600.0 / 7.0).abs() < 1e-9);
+
+        let period = ReportPeriod::parse("2024-Q2").unwrap();
+        assert_eq!(period.label, "2024-Q2");
+        assert_eq!(ReportPeriod::previous(SlaPeriod::Quarter, NaiveDate::from_ymd_opt(2024, 8, 15).unwrap()).unwrap(), period);
+        assert!(ReportPeriod::parse("2024-13").is_err());
+    }
+
+    #[test]
+    fn test_markdown_escapes_cells() {
+        let system = SystemConfig::new("web|api".to_string(), "10.0.0.1".to_string(), None, Protocol::Ping);
+        let span = (at(0, 0), at(0, 0) + Duration::days(1));
+        let mut checks = vec![check(at(8, 0), false), check(at(9, 0), true)];
+        checks[0].error = Some("bad | reply\nfrom peer".to_string());
+        let report = SlaReport {
+            sla: "Web".to_string(),
+            period: "2024-05".to_string(),
+            from: span.0,
+            to: span.1,
+            target_percent: 99.0,
+            business_hours: None,
+            generated_at: span.1,
+            breached: true,
+            systems: vec![system_report(&system, &checks, 99.0, span, &[span], &[])],
+        };
+
+        let markdown = render_markdown(&[report]);
+        assert!(markdown.contains("| web\\|api | "));
+        assert!(markdown.contains("| bad \\| reply from peer |"));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::config::StateConfig;
+use crate::health::{Degradation, SystemState};
+use crate::history::{FailureKind, Incident};
+use crate::monitor::MonitorManager;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+// What survives a restart, for every system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub saved_at: DateTime<Utc>,
+    pub systems: Vec<SavedStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedStatus {
+    // `SystemConfig::storage_key`, which stays the same as long as the name and target do
+    pub key: String,
+    // `SystemConfig::target_key`, to find a renamed system again
+    #[serde(default)]
+    pub target: String,
+    pub id: Uuid,
+    pub is_online: bool,
+    pub state: SystemState,
+    #[serde(default)]
+    pub degradation: Option<Degradation>,
+    pub last_check: DateTime<Utc>,
+    pub last_online: Option<DateTime<Utc>>,
+    pub last_offline: Option<DateTime<Utc>>,
+    pub response_time_ms: Option<u64>,
+    pub total_checks: u64,
+    pub successful_checks: u64,
+    pub error_message: Option<String>,
+    #[serde(default)]
+    pub failure: Option<FailureKind>,
+    #[serde(default)]
+    pub last_heartbeat: Option<DateTime<Utc>>,
+    // Outage or degraded period still going on when the snapshot was taken
+    #[serde(default)]
+    pub open_incidents: Vec<Incident>,
+}
+
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Saved statuses by key; empty when nothing was saved yet
+    pub async fn load(&self) -> Result<HashMap<String, SavedStatus>> {
+        let content = match fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: StateSnapshot = serde_json::from_str(&content)?;
+        log::info!("Restoring state of {} systems saved at {}", snapshot.systems.len(), snapshot.saved_at);
+        Ok(snapshot.systems.into_iter().map(|saved| (saved.key.clone(), saved)).collect())
+    }
+
+    // Written to a temporary file first so a crash mid-write never leaves a truncated snapshot
+    pub async fn save(&self, snapshot: &StateSnapshot) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).await?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(snapshot)?).await?;
+        fs::rename(&temporary, &self.path).await?;
+        Ok(())
+    }
+}
+
+pub async fn run_writer(config: StateConfig, path: PathBuf, manager: Arc<RwLock<MonitorManager>>) {
+    let store = StateStore::new(&path);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.save_interval_seconds.max(1)));
+    // The first tick completes immediately, before any check has run
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let snapshot = manager.read().await.snapshot();
+        if let Err(e) = store.save(&snapshot).await {
+            log::error!("Failed to save monitor state to {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::config::StatusPageConfig;
+use crate::health::SystemState;
+use crate::history::{DailyUptime, Incident, IncidentKind, DAILY_RETENTION_DAYS};
+use crate::monitor::{MonitorManager, SystemStatus};
+use crate::report::{escape, format_duration, STYLE};
+use anyhow::Result;
+use chrono::{Duration, NaiveDate, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const MAX_RECENT_INCIDENTS: usize = 10;
+const UNGROUPED: &str = "Systems";
+
+struct PageEntry {
+    display_name: String,
+    group: Option<String>,
+    status: SystemStatus,
+    daily: Vec<DailyUptime>,
+    incidents: Vec<Incident>,
+}
+
+// Everything the page shows, copied out of the manager so the lock isn't held while rendering
+// or writing
+pub struct PageSnapshot {
+    entries: Vec<PageEntry>,
+}
+
+pub fn snapshot(config: &StatusPageConfig, manager: &MonitorManager) -> PageSnapshot {
+    let systems = manager.get_systems();
+    let selected: Vec<(String, Option<String>, SystemStatus)> = if config.systems.is_empty() {
+        systems.into_iter().map(|s| (s.config.name.clone(), None, s)).collect()
+    } else {
+        config.systems.iter()
+            .filter_map(|entry| {
+                let status = systems.iter().find(|s| s.config.name == entry.name)?.clone();
+                let display_name = entry.display_name.clone().unwrap_or_else(|| entry.name.clone());
+                Some((display_name, entry.group.clone(), status))
+            })
+            .collect()
+    };
+
+    let entries = selected.into_iter()
+        .map(|(display_name, group, status)| PageEntry {
+            daily: manager.get_daily_uptime(status.id).unwrap_or_default(),
+            incidents: manager.get_incidents(status.id).unwrap_or_default(),
+            display_name,
+            group,
+            status,
+        })
+        .collect();
+    PageSnapshot { entries }
+}
+
+pub fn render(config: &StatusPageConfig, snapshot: &PageSnapshot) -> String {
+    let entries = &snapshot.entries;
+    let now = Utc::now();
+
+    // Only the public groups from the page's configuration are shown, internal ones stay private
+    let mut groups: BTreeMap<&str, Vec<&PageEntry>> = BTreeMap::new();
+    for entry in entries {
+        groups.entry(entry.group.as_deref().unwrap_or(UNGROUPED)).or_default().push(entry);
+    }
+
+    let count = |state: SystemState| entries.iter()
+        .filter(|e| e.status.config.enabled && e.status.state == state)
+        .count();
+    let (down, degraded) = (count(SystemState::Down), count(SystemState::Degraded));
+    let (banner_class, banner_text) = match down {
+        0 if degraded > 0 => ("partial", "Degraded performance".to_string()),
+        0 => ("ok", "All systems operational".to_string()),
+        n if n == entries.len() => ("down", "Major outage".to_string()),
+        n => ("partial", format!("{} of {} systems are down", n, entries.len())),
+    };
+
+    let mut html = String::new();
+    let _ = write!(html, r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta http-equiv="refresh" content="{refresh}">
+<title>{title}</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<h1>{title}</h1>
+<div class="banner {banner_class}">{banner_text}</div>
+"#,
+        refresh = config.refresh_seconds.max(10),
+        title = escape(&config.title),
+    );
+
+    for (group, members) in &groups {
+        let up = members.iter().filter(|e| e.status.is_online).count();
+        let _ = write!(html, r#"<section><h2>{} <span class="summary">{} / {} operational</span></h2>"#,
+            escape(group), up, members.len());
+
+        for entry in members {
+            let (class, label) = match (entry.status.config.enabled, entry.status.state) {
+                (false, _) => ("paused", "Paused"),
+                (true, SystemState::Up) => ("ok", "Operational"),
+                (true, SystemState::Degraded) => ("degraded", "Degraded performance"),
+                (true, SystemState::Down) => ("down", "Down"),
+            };
+            let uptime = overall_uptime(&entry.daily);
+            let _ = write!(html, r#"<div class="system"><div class="row"><span class="name">{}</span><span class="state {}">{}</span></div>"#,
+                escape(&entry.display_name), class, label);
+            html.push_str(&uptime_bars(&entry.daily, now.date_naive()));
+            let _ = write!(html, r#"<div class="row legend"><span>{} days ago</span><span>{}</span><span>Today</span></div></div>"#,
+                DAILY_RETENTION_DAYS,
+                uptime.map_or("No data".to_string(), |u| format!("{:.2}% uptime", u)));
+        }
+        html.push_str("</section>");
+    }
+
+    let mut incidents: Vec<(&str, &Incident)> = entries.iter()
+        .flat_map(|e| e.incidents.iter().map(move |i| (e.display_name.as_str(), i)))
+        .collect();
+    incidents.sort_by_key(|(_, incident)| std::cmp::Reverse(incident.started_at));
+
+    html.push_str("<section><h2>Recent incidents</h2>");
+    if incidents.is_empty() {
+        html.push_str(r#"<p class="muted">No incidents recorded.</p>"#);
+    } else {
+        html.push_str("<table><tr><th>System</th><th>Type</th><th>Started</th><th>Duration</th><th>Status</th></tr>");
+        for (name, incident) in incidents.into_iter().take(MAX_RECENT_INCIDENTS) {
+            let _ = write!(html, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(name),
+                match incident.kind {
+                    IncidentKind::Outage => "Outage",
+                    IncidentKind::Degraded => "Degraded performance",
+                },
+                incident.started_at.format("%Y-%m-%d %H:%M UTC"),
+                format_duration(incident.duration_seconds()),
+                if incident.is_open() { "Ongoing" } else { "Resolved" });
+        }
+        html.push_str("</table>");
+    }
+    html.push_str("</section>");
+
+    let _ = write!(html, r#"<footer>Updated {}</footer>
+</body>
+</html>
+"#, now.format("%Y-%m-%d %H:%M:%S UTC"));
+    html
+}
+
+pub async fn write_to_directory(config: &StatusPageConfig, snapshot: &PageSnapshot, directory: &str) -> Result<()> {
+    let html = render(config, snapshot);
+    tokio::fs::create_dir_all(directory).await?;
+    // Write then rename so a web server never serves a half-written page
+    let target = Path::new(directory).join("index.html");
+    let temp = Path::new(directory).join(".index.html.tmp");
+    tokio::fs::write(&temp, html).await?;
+    tokio::fs::rename(&temp, &target).await?;
+    Ok(())
+}
+
+pub async fn run_writer(config: StatusPageConfig, manager: Arc<RwLock<MonitorManager>>) {
+    let Some(directory) = config.output_directory.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(config.refresh_seconds.max(1)));
+    loop {
+        interval.tick().await;
+        let snapshot = snapshot(&config, &*manager.read().await);
+        if let Err(e) = write_to_directory(&config, &snapshot, &directory).await {
+            log::error!("Failed to write status page to {}: {}", directory, e);
+        }
+    }
+}
+
+fn overall_uptime(daily: &[DailyUptime]) -> Option<f64> {
+    let total: u64 = daily.iter().map(|d| d.total_checks).sum();
+    let successful: u64 = daily.iter().map(|d| d.successful_checks).sum();
+    (total > 0).then(|| successful as f64 / total as f64 * 100.0)
+}
+
+fn uptime_bars(daily: &[DailyUptime], today: NaiveDate) -> String {
+    let mut bars = String::from(r#"<div class="bars">"#);
+    for offset in (0..DAILY_RETENTION_DAYS as i64).rev() {
+        let date = today - Duration::days(offset);
+        let (class, tooltip) = match daily.iter().find(|d| d.date == date) {
+            Some(day) => {
+                let uptime = day.uptime_percentage();
+                let class = match uptime {
+                    u if u >= 100.0 => "ok",
+                    u if u >= 99.0 => "minor",
+                    u if u >= 95.0 => "partial",
+                    _ => "down",
+                };
+                (class, format!("{}: {:.2}%", date, uptime))
+            }
+            None => ("none", format!("{}: no data", date)),
+        };
+        let _ = write!(bars, r#"<span class="bar {}" title="{}"></span>"#, class, tooltip);
+    }
+    bars.push_str("</div>");
+    bars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Protocol, StatusPageSystem, SystemConfig};
+
+    #[test]
+    fn test_render_shows_public_names_only() {
+        let mut config = SystemConfig::new("db-primary".to_string(), "10.0.0.5".to_string(), Some(5432), Protocol::Tcp);
+        config.group = Some("Internal Databases".to_string());
+        let mut status = SystemStatus::new(config);
+        status.is_online = true;
+        status.state = SystemState::Up;
+        let page = StatusPageConfig {
+            title: "Acme <Status>".to_string(),
+            systems: vec![StatusPageSystem {
+                name: "db-primary".to_string(),
+                display_name: Some("Checkout".to_string()),
+                group: Some("Shop".to_string()),
+            }],
+            ..StatusPageConfig::default()
+        };
+        let snapshot = PageSnapshot {
+            entries: vec![PageEntry {
+                display_name: "Checkout".to_string(),
+                group: Some("Shop".to_string()),
+                status,
+                daily: Vec::new(),
+                incidents: Vec::new(),
+            }],
+        };
+
+        let html = render(&page, &snapshot);
+        assert!(html.contains("Checkout"));
+        assert!(html.contains(r#"<h2>Shop <span class="summary">1 / 1 operational</span></h2>"#));
+        assert!(html.contains("All systems operational"));
+        assert!(html.contains("Acme &lt;Status&gt;"));
+        assert!(!html.contains("Internal Databases"));
+        assert!(!html.contains("db-primary"));
+    }
+}
@@ -0,0 +1,591 @@
+use crate::config::{TraceMethod, TraceSettings};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::Duration;
+
+const UDP_BASE_PORT: u16 = 33434;
+const MAX_KNOWN_PATHS: usize = 20;
+const MAX_ROUTE_CHANGES: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceHop {
+    pub ttl: u8,
+    // Every distinct address that answered at this TTL, in the order first seen
+    pub addresses: Vec<IpAddr>,
+    pub sent: u32,
+    pub received: u32,
+    pub rtts_ms: Vec<f64>,
+}
+
+impl TraceHop {
+    pub fn loss_percent(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (self.sent - self.received) as f64 / self.sent as f64 * 100.0
+    }
+
+    pub fn avg_rtt_ms(&self) -> Option<f64> {
+        (!self.rtts_ms.is_empty()).then(|| self.rtts_ms.iter().sum::<f64>() / self.rtts_ms.len() as f64)
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.addresses.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceResult {
+    pub target: IpAddr,
+    pub method: TraceMethod,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub hops: Vec<TraceHop>,
+    pub reached: bool,
+    pub error: Option<String>,
+}
+
+impl TraceResult {
+    // TTL of the first hop where this trace no longer follows `baseline`: the responders are
+    // different, or this trace goes silent for good where the baseline still had answers.
+    // A single silent hop in the middle is not treated as a divergence.
+    pub fn divergence_from(&self, baseline: &TraceResult) -> Option<u8> {
+        for base in baseline.hops.iter().filter(|h| !h.is_silent()) {
+            let responders = self.hops.iter().find(|h| h.ttl == base.ttl).map(|h| &h.addresses);
+            let diverged = match responders {
+                Some(addresses) if !addresses.is_empty() => !addresses.iter().any(|a| base.addresses.contains(a)),
+                // Silent or never probed: only a divergence if nothing answers from here on
+                _ => !self.reached && self.hops.iter().filter(|h| h.ttl >= base.ttl).all(|h| h.is_silent()),
+            };
+            if diverged {
+                return Some(base.ttl);
+            }
+        }
+        None
+    }
+}
+
+// Responders per TTL, index 0 being the first hop
+pub type PathHops = Vec<Vec<IpAddr>>;
+
+// A distinct route seen toward a system; with ECMP tolerance the responders of every
+// matching trace accumulate at each TTL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownPath {
+    pub hops: PathHops,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub times_seen: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteChange {
+    pub timestamp: DateTime<Utc>,
+    pub old_hops: PathHops,
+    pub new_hops: PathHops,
+}
+
+// Latest trace for a system, the most recent one that made it to the target and the
+// distinct paths seen so far
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TraceState {
+    pub latest: Option<TraceResult>,
+    pub last_good: Option<TraceResult>,
+    pub running: bool,
+    pub paths: Vec<KnownPath>,
+    pub current_path: Option<usize>,
+    pub route_changes: VecDeque<RouteChange>,
+}
+
+impl TraceResult {
+    pub fn path_hops(&self) -> PathHops {
+        self.hops.iter().map(|h| h.addresses.clone()).collect()
+    }
+}
+
+// Number of TTLs at which `new` doesn't match `known`. Silent hops on either side are
+// ignored, but a longer or shorter path counts every extra hop as changed.
+pub fn changed_hops(known: &[Vec<IpAddr>], new: &[Vec<IpAddr>], ecmp_tolerant: bool) -> usize {
+    let overlaps = |i: usize| match (known.get(i), new.get(i)) {
+        (Some(k), Some(n)) => k.is_empty() || n.is_empty() || n.iter().any(|a| k.contains(a)),
+        _ => false,
+    };
+
+    (0..known.len().max(new.len()))
+        .filter(|&i| {
+            let (Some(k), Some(n)) = (known.get(i), new.get(i)) else {
+                return true;
+            };
+            if k.is_empty() || n.is_empty() {
+                false
+            } else if ecmp_tolerant {
+                // Paths that split at one hop and rejoin at the next are load balancing, not a new route
+                let rejoins = i > 0 && overlaps(i - 1) && overlaps(i + 1);
+                !n.iter().any(|a| k.contains(a)) && !rejoins
+            } else {
+                !n.iter().all(|a| k.contains(a))
+            }
+        })
+        .count()
+}
+
+impl TraceState {
+    // Stores a finished trace; returns the route change it revealed, if any. Only traces that
+    // reached the target take part in path tracking, a broken path is an outage, not a route.
+    pub fn record(&mut self, result: TraceResult, settings: &TraceSettings) -> Option<RouteChange> {
+        self.running = false;
+        let change = if result.reached {
+            self.last_good = Some(result.clone());
+            self.track_path(&result, settings)
+        } else {
+            None
+        };
+        self.latest = Some(result);
+        change
+    }
+
+    fn track_path(&mut self, result: &TraceResult, settings: &TraceSettings) -> Option<RouteChange> {
+        let hops = result.path_hops();
+        let tolerance = settings.max_changed_hops as usize;
+        let matches = |path: &KnownPath| changed_hops(&path.hops, &hops, settings.ecmp_tolerant) <= tolerance;
+
+        // Prefer the current path, then any path we've seen before (a flap back), then a new one
+        let index = self.current_path
+            .filter(|&i| self.paths.get(i).is_some_and(matches))
+            .or_else(|| self.paths.iter().position(matches));
+        let index = match index {
+            Some(index) => {
+                let path = &mut self.paths[index];
+                for (known, seen) in path.hops.iter_mut().zip(&hops) {
+                    for address in seen {
+                        if !known.contains(address) {
+                            known.push(*address);
+                        }
+                    }
+                }
+                if hops.len() > path.hops.len() {
+                    path.hops.extend_from_slice(&hops[path.hops.len()..]);
+                }
+                path.last_seen = result.started_at;
+                path.times_seen += 1;
+                index
+            }
+            None => {
+                self.paths.push(KnownPath {
+                    hops: hops.clone(),
+                    first_seen: result.started_at,
+                    last_seen: result.started_at,
+                    times_seen: 1,
+                });
+                self.paths.len() - 1
+            }
+        };
+
+        let previous = self.current_path.replace(index);
+        let change = match previous {
+            Some(previous) if previous != index => {
+                let change = RouteChange {
+                    timestamp: result.started_at,
+                    old_hops: self.paths[previous].hops.clone(),
+                    new_hops: hops,
+                };
+                self.route_changes.push_back(change.clone());
+                if self.route_changes.len() > MAX_ROUTE_CHANGES {
+                    self.route_changes.pop_front();
+                }
+                Some(change)
+            }
+            _ => None,
+        };
+
+        self.trim_paths();
+        change
+    }
+
+    // Forgets the least recently seen paths, never the current one
+    fn trim_paths(&mut self) {
+        while self.paths.len() > MAX_KNOWN_PATHS {
+            let current = self.current_path;
+            let Some(oldest) = self.paths.iter().enumerate()
+                .filter(|(i, _)| Some(*i) != current)
+                .min_by_key(|(_, p)| p.last_seen)
+                .map(|(i, _)| i)
+            else {
+                return;
+            };
+            self.paths.remove(oldest);
+            if let Some(current) = self.current_path.as_mut() {
+                if *current > oldest {
+                    *current -= 1;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProbeReply {
+    // An intermediate router reported the TTL expired
+    Hop { address: IpAddr, rtt: Duration },
+    // The target itself answered (or the path ended in an unreachable error)
+    Final { address: IpAddr, rtt: Duration, reached: bool },
+    Timeout,
+}
+
+pub async fn trace(target: IpAddr, method: TraceMethod, port: Option<u16>, settings: &TraceSettings) -> TraceResult {
+    let settings = settings.clone();
+    let started_at = Utc::now();
+    let outcome = tokio::task::spawn_blocking(move || {
+        match run_trace(target, method, port, &settings) {
+            // ICMP sockets need net.ipv4.ping_group_range; UDP probes work for everyone
+            Err(e) if method == TraceMethod::Icmp && is_permission_denied(&e) => {
+                log::debug!("ICMP trace to {} not permitted, falling back to UDP", target);
+                run_trace(target, TraceMethod::Udp, None, &settings).map(|r| (r, TraceMethod::Udp))
+            }
+            other => other.map(|r| (r, method)),
+        }
+    }).await;
+
+    let (hops, reached, error, method) = match outcome {
+        Ok(Ok(((hops, reached), method))) => (hops, reached, None, method),
+        Ok(Err(e)) => (Vec::new(), false, Some(e.to_string()), method),
+        Err(e) => (Vec::new(), false, Some(e.to_string()), method),
+    };
+    TraceResult {
+        target,
+        method,
+        started_at,
+        finished_at: Utc::now(),
+        hops,
+        reached,
+        error,
+    }
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+fn run_trace(target: IpAddr, method: TraceMethod, port: Option<u16>, settings: &TraceSettings) -> Result<(Vec<TraceHop>, bool)> {
+    let timeout = Duration::from_millis(settings.probe_timeout_ms.max(1));
+    let probes = settings.probes_per_hop.max(1);
+    let mut hops = Vec::new();
+    let mut sequence: u16 = 0;
+
+    for ttl in 1..=settings.max_hops.max(1) {
+        let mut hop = TraceHop {
+            ttl,
+            addresses: Vec::new(),
+            sent: 0,
+            received: 0,
+            rtts_ms: Vec::new(),
+        };
+        let mut finished = None;
+
+        for _ in 0..probes {
+            sequence = sequence.wrapping_add(1);
+            let probe_port = match method {
+                TraceMethod::Udp => port.unwrap_or(UDP_BASE_PORT.wrapping_add(sequence)),
+                TraceMethod::Tcp => port.unwrap_or(80),
+                TraceMethod::Icmp => 0,
+            };
+            hop.sent += 1;
+            let (address, rtt) = match probe(target, ttl, method, probe_port, sequence, timeout)? {
+                ProbeReply::Timeout => continue,
+                ProbeReply::Hop { address, rtt } => (address, rtt),
+                ProbeReply::Final { address, rtt, reached } => {
+                    finished = Some(finished.unwrap_or(false) || reached);
+                    (address, rtt)
+                }
+            };
+            hop.received += 1;
+            hop.rtts_ms.push(rtt.as_secs_f64() * 1000.0);
+            if !hop.addresses.contains(&address) {
+                hop.addresses.push(address);
+            }
+        }
+
+        hops.push(hop);
+        if let Some(reached) = finished {
+            return Ok((hops, reached));
+        }
+    }
+    Ok((hops, false))
+}
+
+#[cfg(target_os = "linux")]
+fn probe(target: IpAddr, ttl: u8, method: TraceMethod, port: u16, sequence: u16, timeout: Duration) -> Result<ProbeReply> {
+    use nix::errno::Errno;
+    use nix::poll::{poll, PollFd, PollFlags};
+    use nix::sys::socket::{setsockopt, sockopt};
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+    use std::net::SocketAddr;
+    use std::time::Instant;
+
+    let is_v4 = target.is_ipv4();
+    let domain = if is_v4 { Domain::IPV4 } else { Domain::IPV6 };
+    let socket = match method {
+        TraceMethod::Udp => Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?,
+        TraceMethod::Tcp => Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?,
+        // Unprivileged "ping" sockets; needs net.ipv4.ping_group_range to include our group
+        TraceMethod::Icmp => {
+            let protocol = if is_v4 { Protocol::ICMPV4 } else { Protocol::ICMPV6 };
+            Socket::new(domain, Type::DGRAM, Some(protocol))?
+        }
+    };
+    if is_v4 {
+        socket.set_ttl(ttl as u32)?;
+        setsockopt(&socket, sockopt::Ipv4RecvErr, &true)?;
+    } else {
+        socket.set_unicast_hops_v6(ttl as u32)?;
+        setsockopt(&socket, sockopt::Ipv6RecvErr, &true)?;
+    }
+    socket.set_nonblocking(true)?;
+
+    let destination = SockAddr::from(SocketAddr::new(target, port));
+    let start = Instant::now();
+    match method {
+        TraceMethod::Udp => {
+            socket.send_to(b"system-monitor trace", &destination)?;
+        }
+        TraceMethod::Icmp => {
+            socket.send_to(&echo_request(is_v4, sequence), &destination)?;
+        }
+        TraceMethod::Tcp => match socket.connect(&destination) {
+            Ok(()) => return Ok(ProbeReply::Final { address: target, rtt: start.elapsed(), reached: true }),
+            Err(e) if e.raw_os_error() == Some(Errno::EINPROGRESS as i32) => {}
+            Err(e) => return Err(e.into()),
+        },
+    }
+
+    let mut events = PollFlags::POLLIN | PollFlags::POLLERR;
+    if method == TraceMethod::Tcp {
+        events |= PollFlags::POLLOUT;
+    }
+
+    loop {
+        let Some(remaining) = timeout.checked_sub(start.elapsed()) else {
+            return Ok(ProbeReply::Timeout);
+        };
+        let mut fds = [PollFd::new(&socket, events)];
+        match poll(&mut fds, remaining.as_millis().max(1) as i32) {
+            Ok(0) => return Ok(ProbeReply::Timeout),
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+        let rtt = start.elapsed();
+
+        if let Some((address, origin_type, code)) = read_error_queue(&socket, is_v4)? {
+            let time_exceeded = if is_v4 { origin_type == 11 } else { origin_type == 3 };
+            if time_exceeded {
+                return Ok(ProbeReply::Hop { address, rtt });
+            }
+            // Port unreachable from the target means our UDP probe arrived
+            let port_unreachable = if is_v4 { origin_type == 3 && code == 3 } else { origin_type == 1 && code == 4 };
+            let reached = address == target || port_unreachable;
+            return Ok(ProbeReply::Final { address, rtt, reached });
+        }
+
+        match method {
+            TraceMethod::Tcp => return Ok(tcp_reply(socket.take_error()?, target, rtt)),
+            TraceMethod::Icmp | TraceMethod::Udp => {
+                let mut buffer = [std::mem::MaybeUninit::<u8>::uninit(); 512];
+                match socket.recv(&mut buffer) {
+                    Ok(_) => return Ok(ProbeReply::Final { address: target, rtt, reached: true }),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                    // Same as for TCP, a failed socket would keep waking the poll
+                    Err(_) => return Ok(ProbeReply::Final { address: target, rtt, reached: false }),
+                }
+            }
+        }
+    }
+}
+
+// Outcome of a TCP probe once the socket is writable or failed. Both a completed handshake and
+// a reset prove the target answered. Any other error ends the probe too: the socket keeps
+// polling as failed, so waiting on would spin until the timeout.
+#[cfg(target_os = "linux")]
+fn tcp_reply(error: Option<std::io::Error>, target: IpAddr, rtt: Duration) -> ProbeReply {
+    let reached = match error {
+        None => true,
+        Some(e) => e.raw_os_error() == Some(nix::errno::Errno::ECONNREFUSED as i32),
+    };
+    ProbeReply::Final { address: target, rtt, reached }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn probe(_target: IpAddr, _ttl: u8, _method: TraceMethod, _port: u16, _sequence: u16, _timeout: Duration) -> Result<ProbeReply> {
+    anyhow::bail!("Path analysis is only supported on Linux")
+}
+
+// Reads one ICMP error reported for this socket; returns the offending address plus ICMP type and code
+#[cfg(target_os = "linux")]
+fn read_error_queue(socket: &socket2::Socket, is_v4: bool) -> Result<Option<(IpAddr, u8, u8)>> {
+    use nix::errno::Errno;
+    use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags, SockaddrStorage};
+    use std::io::IoSliceMut;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::os::fd::AsRawFd;
+
+    let mut data = [0u8; 512];
+    let mut iov = [IoSliceMut::new(&mut data)];
+    let mut control = nix::cmsg_space!(nix::libc::sock_extended_err, nix::libc::sockaddr_in6);
+    let message = match recvmsg::<SockaddrStorage>(socket.as_raw_fd(), &mut iov, Some(&mut control), MsgFlags::MSG_ERRQUEUE) {
+        Ok(message) => message,
+        Err(Errno::EAGAIN) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    for cmsg in message.cmsgs() {
+        match cmsg {
+            ControlMessageOwned::Ipv4RecvErr(err, Some(offender)) if is_v4 && err.ee_origin == nix::libc::SO_EE_ORIGIN_ICMP => {
+                let address = Ipv4Addr::from(u32::from_be(offender.sin_addr.s_addr));
+                return Ok(Some((IpAddr::V4(address), err.ee_type, err.ee_code)));
+            }
+            ControlMessageOwned::Ipv6RecvErr(err, Some(offender)) if !is_v4 && err.ee_origin == nix::libc::SO_EE_ORIGIN_ICMP6 => {
+                let address = Ipv6Addr::from(offender.sin6_addr.s6_addr);
+                return Ok(Some((IpAddr::V6(address), err.ee_type, err.ee_code)));
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+// ICMP echo request; the kernel fills in the identifier for ping sockets
+fn echo_request(is_v4: bool, sequence: u16) -> Vec<u8> {
+    let icmp_type = if is_v4 { 8 } else { 128 };
+    let mut packet = vec![icmp_type, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"system-monitor trace");
+    if is_v4 {
+        // ICMPv6 checksums cover a pseudo-header and are always computed by the kernel
+        let checksum = internet_checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data.chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(ttl: u8, address: Option<&str>) -> TraceHop {
+        let addresses: Vec<IpAddr> = address.map(|a| a.parse().unwrap()).into_iter().collect();
+        TraceHop {
+            ttl,
+            sent: 3,
+            received: if addresses.is_empty() { 0 } else { 3 },
+            rtts_ms: if addresses.is_empty() { vec![] } else { vec![1.0; 3] },
+            addresses,
+        }
+    }
+
+    fn trace_of(hops: Vec<TraceHop>, reached: bool) -> TraceResult {
+        TraceResult {
+            target: "192.0.2.1".parse().unwrap(),
+            method: TraceMethod::Icmp,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            hops,
+            reached,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_divergence_detection() {
+        let good = trace_of(vec![
+            hop(1, Some("10.0.0.1")),
+            hop(2, None),
+            hop(3, Some("198.51.100.1")),
+            hop(4, Some("192.0.2.1")),
+        ], true);
+
+        // A silent hop in the middle doesn't count
+        let same = trace_of(vec![
+            hop(1, Some("10.0.0.1")),
+            hop(2, None),
+            hop(3, Some("198.51.100.1")),
+            hop(4, Some("192.0.2.1")),
+        ], true);
+        assert_eq!(same.divergence_from(&good), None);
+
+        let rerouted = trace_of(vec![
+            hop(1, Some("10.0.0.1")),
+            hop(2, None),
+            hop(3, Some("203.0.113.9")),
+        ], false);
+        assert_eq!(rerouted.divergence_from(&good), Some(3));
+
+        let dead_end = trace_of(vec![
+            hop(1, Some("10.0.0.1")),
+            hop(2, None),
+            hop(3, None),
+            hop(4, None),
+        ], false);
+        assert_eq!(dead_end.divergence_from(&good), Some(3));
+    }
+
+    #[test]
+    fn test_route_change_with_ecmp() {
+        let path_a = trace_of(vec![hop(1, Some("10.0.0.1")), hop(2, Some("198.51.100.1")), hop(3, Some("192.0.2.1"))], true);
+        let ecmp = trace_of(vec![hop(1, Some("10.0.0.1")), hop(2, Some("198.51.100.2")), hop(3, Some("192.0.2.1"))], true);
+        let path_b = trace_of(vec![hop(1, Some("10.0.0.1")), hop(2, Some("203.0.113.5")), hop(3, Some("203.0.113.6")), hop(4, Some("192.0.2.1"))], true);
+
+        let strict = TraceSettings { ecmp_tolerant: false, ..TraceSettings::default() };
+        let mut state = TraceState::default();
+        assert!(state.record(path_a.clone(), &strict).is_none());
+        assert!(state.record(ecmp.clone(), &strict).is_some());
+        assert!(state.record(path_a.clone(), &strict).is_some());
+        assert_eq!(state.paths.len(), 2);
+
+        let tolerant = TraceSettings::default();
+        let mut state = TraceState::default();
+        assert!(state.record(path_a, &tolerant).is_none());
+        assert!(state.record(ecmp, &tolerant).is_none());
+        assert_eq!(state.paths.len(), 1);
+        assert_eq!(state.paths[0].hops[1].len(), 2);
+
+        let change = state.record(path_b, &tolerant).unwrap();
+        assert_eq!(change.old_hops[1].len(), 2);
+        assert_eq!(change.new_hops.len(), 4);
+        assert_eq!(state.route_changes.len(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_tcp_reply() {
+        let target: IpAddr = "192.0.2.1".parse().unwrap();
+        let rtt = Duration::from_millis(5);
+        let reached = |error: Option<std::io::Error>| match tcp_reply(error, target, rtt) {
+            ProbeReply::Final { reached, .. } => reached,
+            _ => panic!("TCP probes always end with a final reply"),
+        };
+        assert!(reached(None));
+        assert!(reached(Some(std::io::Error::from_raw_os_error(nix::libc::ECONNREFUSED))));
+        assert!(!reached(Some(std::io::Error::from_raw_os_error(nix::libc::EHOSTUNREACH))));
+        assert!(!reached(Some(std::io::Error::from_raw_os_error(nix::libc::ENETUNREACH))));
+    }
+
+    #[test]
+    fn test_echo_request_checksum() {
+        let packet = echo_request(true, 1);
+        assert_eq!(internet_checksum(&packet), 0);
+    }
+}
@@ -1,28 +1,244 @@
-use crate::config::{Config, Protocol, SystemConfig};
-use crate::monitor::{MonitorManager, SystemStatus};
+use crate::config::{AddressFamily, Protocol, SharedConfig, SystemConfig};
+use crate::config_files::SystemOrigin;
+use crate::discovery::{self, DiscoveredHost, SweepOptions};
+use crate::events::MonitorEvent;
+use crate::export::{self, ExportFormat, ExportKind, TimestampZone};
+use crate::health::{Severity, SystemState};
+use crate::history::{self, CheckTimings, Incident, IncidentKind};
+use crate::inventory::{self, FieldMapping, ImportFormat, ImportOptions, ImportPreview, RowStatus};
+use crate::monitor::{self, LatencyReport, MonitorManager, SystemStatus};
+use crate::resolve;
+use crate::scheduler::SchedulerStats;
+use crate::sla::{self, ReportFormat};
+use crate::state::StateStore;
+use crate::trace::{PathHops, TraceState};
+use chrono::Utc;
 use eframe::egui;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::TryRecvError};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
 pub struct MonitorApp {
-    config: Config,
+    // Shared with the API, which saves the same file
+    config: SharedConfig,
+    config_path: String,
     monitor_manager: Arc<RwLock<MonitorManager>>,
     systems: Vec<SystemStatus>,
+    traces: HashMap<Uuid, TraceState>,
+    incidents: HashMap<Uuid, Vec<Incident>>,
+    latency: HashMap<Uuid, LatencyReport>,
+    scheduler: Option<SchedulerStats>,
     show_add_dialog: bool,
     new_system: SystemConfig,
     selected_protocol: usize,
-    refresh_counter: u64,
+    events: broadcast::Receiver<MonitorEvent>,
+    needs_refresh: bool,
     system_to_remove: Option<Uuid>,
+    export_dialog: Option<ExportDialog>,
+    sla_dialog: Option<SlaDialog>,
+    discovery_dialog: Option<DiscoveryDialog>,
+    import_dialog: Option<ImportDialog>,
     runtime: tokio::runtime::Runtime,
 }
 
+struct ExportDialog {
+    system_id: Uuid,
+    system_name: String,
+    kind: ExportKind,
+    format: ExportFormat,
+    zone: TimestampZone,
+    // 0 exports everything that is stored
+    range_days: i64,
+    path: String,
+    result: Option<Result<String, String>>,
+}
+
+impl ExportDialog {
+    fn new(system: &SystemStatus) -> Self {
+        let mut dialog = Self {
+            system_id: system.id,
+            system_name: system.config.name.clone(),
+            kind: ExportKind::Checks,
+            format: ExportFormat::Csv,
+            zone: TimestampZone::Utc,
+            range_days: 7,
+            path: String::new(),
+            result: None,
+        };
+        dialog.path = dialog.default_path();
+        dialog
+    }
+
+    fn default_path(&self) -> String {
+        let kind = match self.kind {
+            ExportKind::Checks => "checks",
+            ExportKind::Incidents => "incidents",
+            ExportKind::Latency => "latency",
+        };
+        let name: String = self.system_name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        format!("exports/{}-{}-{}.{}", name, kind, Utc::now().format("%Y%m%d-%H%M%S"), self.format.extension())
+    }
+}
+
+struct SlaDialog {
+    // None reports on every SLA
+    sla: Option<String>,
+    // Empty for the last complete period
+    period: String,
+    format: ReportFormat,
+    path: String,
+    result: Option<Result<String, String>>,
+}
+
+impl SlaDialog {
+    fn new() -> Self {
+        let mut dialog = Self {
+            sla: None,
+            period: String::new(),
+            format: ReportFormat::Markdown,
+            path: String::new(),
+            result: None,
+        };
+        dialog.path = dialog.default_path();
+        dialog
+    }
+
+    fn default_path(&self) -> String {
+        let name: String = self.sla.as_deref().unwrap_or("all").chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+            .collect();
+        let period = if self.period.trim().is_empty() { "last" } else { self.period.trim() };
+        format!("reports/sla-{}-{}.{}", name, period, self.format.extension())
+    }
+}
+
+struct DiscoveryDialog {
+    network: String,
+    // Comma separated
+    ports: String,
+    icmp: bool,
+    concurrency: usize,
+    group: String,
+    protocol: Protocol,
+    sweep: Option<RunningSweep>,
+    // Found hosts and whether they are selected for adding
+    hosts: Vec<(DiscoveredHost, bool)>,
+    result: Option<Result<String, String>>,
+}
+
+struct RunningSweep {
+    done: Arc<AtomicUsize>,
+    total: usize,
+    result: tokio::sync::oneshot::Receiver<anyhow::Result<Vec<DiscoveredHost>>>,
+    task: tokio::task::AbortHandle,
+}
+
+// Closing the dialog stops the sweep; dropping the sweep's JoinSet aborts its probes too
+impl Drop for RunningSweep {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl DiscoveryDialog {
+    fn new() -> Self {
+        let options = SweepOptions::default();
+        Self {
+            network: String::new(),
+            ports: options.ports.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(","),
+            icmp: options.icmp,
+            concurrency: options.concurrency,
+            group: String::new(),
+            protocol: Protocol::Ping,
+            sweep: None,
+            hosts: Vec::new(),
+            result: None,
+        }
+    }
+
+    fn options(&self) -> anyhow::Result<SweepOptions> {
+        let ports = self.ports.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| p.parse::<u16>().map_err(|_| anyhow::anyhow!("Invalid port '{}'", p)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(SweepOptions { icmp: self.icmp, ports, concurrency: self.concurrency, ..SweepOptions::default() })
+    }
+}
+
+struct ImportDialog {
+    path: String,
+    format: ImportFormat,
+    mapping: FieldMapping,
+    protocol: Protocol,
+    group: String,
+    preview: Option<ImportPreview>,
+    // Where the current systems are exported to
+    export_path: String,
+    export_format: ExportFormat,
+    result: Option<Result<String, String>>,
+}
+
+impl ImportDialog {
+    fn new() -> Self {
+        Self {
+            path: String::new(),
+            format: ImportFormat::Csv,
+            mapping: FieldMapping::default(),
+            protocol: Protocol::Ping,
+            group: String::new(),
+            preview: None,
+            export_path: "exports/systems.csv".to_string(),
+            export_format: ExportFormat::Csv,
+            result: None,
+        }
+    }
+
+    fn options(&self) -> ImportOptions {
+        ImportOptions {
+            format: self.format,
+            mapping: self.mapping.clone(),
+            protocol: self.protocol.clone(),
+            group: Some(self.group.trim().to_string()).filter(|g| !g.is_empty()),
+        }
+    }
+}
+
 impl MonitorApp {
-    pub fn new(config: Config, monitor_manager: Arc<RwLock<MonitorManager>>, runtime: tokio::runtime::Runtime) -> Self {
+    pub fn new(
+        config: SharedConfig,
+        config_path: String,
+        monitor_manager: Arc<RwLock<MonitorManager>>,
+        runtime: tokio::runtime::Runtime,
+        ctx: egui::Context,
+    ) -> Self {
+        let (events, mut repaint_events) = runtime.block_on(async {
+            let manager = monitor_manager.read().await;
+            (manager.subscribe(), manager.subscribe())
+        });
+
+        // Wake the UI whenever the monitor publishes something instead of repainting every frame
+        runtime.spawn(async move {
+            while let Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) = repaint_events.recv().await {
+                ctx.request_repaint();
+            }
+        });
+
         Self {
             config,
+            config_path,
             monitor_manager,
             systems: Vec::new(),
+            traces: HashMap::new(),
+            incidents: HashMap::new(),
+            latency: HashMap::new(),
+            scheduler: None,
             show_add_dialog: false,
             new_system: SystemConfig {
                 name: String::new(),
@@ -30,21 +246,61 @@ impl MonitorApp {
                 port: None,
                 protocol: Protocol::Ping,
                 enabled: true,
+                group: None,
+                trace: None,
+                address_family: AddressFamily::Auto,
+                dns_server: None,
+                path: None,
+                thresholds: None,
+                schedule: None,
+                probe: None,
+                parent: None,
+                extends: None,
+                origin: None,
             },
             selected_protocol: 0,
-            refresh_counter: 0,
+            events,
+            needs_refresh: true,
             system_to_remove: None,
+            export_dialog: None,
+            sla_dialog: None,
+            discovery_dialog: None,
+            import_dialog: None,
             runtime,
         }
     }
 
-    fn refresh_systems(&mut self) {
+    fn refresh_systems(&mut self) -> bool {
         if let Ok(manager) = self.monitor_manager.try_read() {
             let new_systems = manager.get_systems();
             if new_systems.len() != self.systems.len() {
                 log::info!("Systems count changed: {} -> {}", self.systems.len(), new_systems.len());
             }
+            self.traces = new_systems.iter()
+                .filter_map(|s| manager.get_trace(s.id).map(|t| (s.id, t)))
+                .collect();
+            self.incidents = new_systems.iter()
+                .filter_map(|s| manager.get_incidents(s.id).map(|i| (s.id, i)))
+                .collect();
+            self.latency = new_systems.iter()
+                .filter_map(|s| manager.get_latency(s.id).map(|l| (s.id, l)))
+                .collect();
+            self.scheduler = Some(manager.scheduler_stats());
             self.systems = new_systems;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns true if anything was published since the last frame
+    fn drain_events(&mut self) -> bool {
+        let mut received = false;
+        loop {
+            match self.events.try_recv() {
+                Ok(_) | Err(TryRecvError::Lagged(_)) => received = true,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return received,
+            }
         }
     }
 
@@ -53,17 +309,15 @@ impl MonitorApp {
             0 => Protocol::Ping,
             1 => Protocol::Tcp,
             2 => Protocol::Udp,
+            3 => Protocol::Http,
+            4 => Protocol::Https,
             _ => Protocol::Ping,
         };
 
         if let Ok(mut manager) = self.monitor_manager.try_write() {
             if let Ok(_id) = self.runtime.block_on(manager.add_system(self.new_system.clone())) {
-                self.config.add_system(self.new_system.clone());
                 // Save config in background
-                let config = self.config.clone();
-                self.runtime.spawn(async move {
-                    let _ = config.save_to_file("monitor_config.toml").await;
-                });
+                self.save_config_in_background();
             }
         }
 
@@ -74,6 +328,17 @@ impl MonitorApp {
             port: None,
             protocol: Protocol::Ping,
             enabled: true,
+            group: None,
+            trace: None,
+            address_family: AddressFamily::Auto,
+            dns_server: None,
+            path: None,
+            thresholds: None,
+            schedule: None,
+            probe: None,
+            parent: None,
+            extends: None,
+            origin: None,
         };
         self.selected_protocol = 0;
         self.show_add_dialog = false;
@@ -82,49 +347,626 @@ impl MonitorApp {
     fn remove_system(&mut self, id: Uuid) {
         if let Ok(mut manager) = self.monitor_manager.try_write() {
             manager.remove_system(id);
-            
-            // Remove from config
-            self.config.systems.retain(|_s| {
-                // This is a bit hacky since we don't store UUIDs in config
-                // In a real app, you'd want to match by name+host or add UUIDs to config
-                true
+            self.save_config_in_background();
+        }
+    }
+
+    // The manager is the source of truth, systems may also have been changed through the API
+    fn save_config_in_background(&self) {
+        let (config, manager, path) = (Arc::clone(&self.config), Arc::clone(&self.monitor_manager), self.config_path.clone());
+        self.runtime.spawn(async move {
+            if let Err(e) = monitor::save_config(&config, &manager, &path).await {
+                log::error!("Failed to save the configuration: {}", e);
+            }
+        });
+    }
+
+    fn save_config(&self) -> anyhow::Result<()> {
+        self.runtime.block_on(monitor::save_config(&self.config, &self.monitor_manager, &self.config_path))
+    }
+
+    fn configured_systems(&self) -> Vec<SystemConfig> {
+        self.config.blocking_lock().systems.clone()
+    }
+
+    fn start_trace(&mut self, id: Uuid) {
+        let _guard = self.runtime.enter();
+        if let Ok(manager) = self.monitor_manager.try_read() {
+            match manager.start_trace(id) {
+                Ok(_) => {
+                    self.traces.entry(id).or_default().running = true;
+                }
+                Err(e) => log::error!("Failed to start path analysis: {}", e),
+            }
+        }
+    }
+
+    fn run_export(&self, dialog: &ExportDialog) -> anyhow::Result<String> {
+        let from = (dialog.range_days > 0).then(|| Utc::now() - chrono::Duration::days(dialog.range_days));
+        let checks = self.runtime.block_on(async {
+            let manager = self.monitor_manager.read().await;
+            manager.load_checks(dialog.system_id, from, None).await
+        })?.ok_or_else(|| anyhow::anyhow!("{} no longer exists", dialog.system_name))?;
+
+        let output = match dialog.kind {
+            ExportKind::Checks => export::export_checks(&checks, dialog.format, dialog.zone)?,
+            ExportKind::Incidents => {
+                let incidents = history::incidents_from_checks(&checks);
+                export::export_incidents(&incidents, dialog.format, dialog.zone)?
+            }
+            ExportKind::Latency => export::export_latency(&checks, dialog.format, dialog.zone)?,
+        };
+
+        let path = std::path::Path::new(&dialog.path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, output)?;
+        Ok(format!("Exported {} checks to {}", checks.len(), path.display()))
+    }
+
+    fn draw_export_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut dialog) = self.export_dialog.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut export_clicked = false;
+
+        egui::Window::new(format!("Export {}", dialog.system_name))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let before = (dialog.kind, dialog.format);
+                egui::Grid::new("export_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Data:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut dialog.kind, ExportKind::Checks, "Check history");
+                        ui.radio_value(&mut dialog.kind, ExportKind::Incidents, "Incidents");
+                        ui.radio_value(&mut dialog.kind, ExportKind::Latency, "Latency");
+                    });
+                    ui.end_row();
+
+                    ui.label("Format:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut dialog.format, ExportFormat::Csv, "CSV");
+                        ui.radio_value(&mut dialog.format, ExportFormat::Json, "JSON");
+                        ui.radio_value(&mut dialog.format, ExportFormat::Jsonl, "JSON Lines");
+                    });
+                    ui.end_row();
+
+                    ui.label("Range:");
+                    egui::ComboBox::from_id_source("export_range")
+                        .selected_text(match dialog.range_days {
+                            0 => "Everything stored".to_string(),
+                            1 => "Last 24 hours".to_string(),
+                            days => format!("Last {} days", days),
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut dialog.range_days, 1, "Last 24 hours");
+                            ui.selectable_value(&mut dialog.range_days, 7, "Last 7 days");
+                            ui.selectable_value(&mut dialog.range_days, 30, "Last 30 days");
+                            ui.selectable_value(&mut dialog.range_days, 90, "Last 90 days");
+                            ui.selectable_value(&mut dialog.range_days, 0, "Everything stored");
+                        });
+                    ui.end_row();
+
+                    ui.label("Timestamps:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut dialog.zone, TimestampZone::Utc, "UTC");
+                        ui.radio_value(&mut dialog.zone, TimestampZone::Local, "Local time");
+                    });
+                    ui.end_row();
+
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut dialog.path);
+                    ui.end_row();
+                });
+                if before != (dialog.kind, dialog.format) {
+                    dialog.path = dialog.default_path();
+                }
+
+                ui.horizontal(|ui| {
+                    export_clicked = ui.button("Export").clicked();
+                });
+
+                match &dialog.result {
+                    Some(Ok(message)) => { ui.label(message); }
+                    Some(Err(error)) => { ui.colored_label(egui::Color32::RED, error); }
+                    None => {}
+                }
             });
-            
-            // Save config
-            let config = self.config.clone();
-            self.runtime.spawn(async move {
-                let _ = config.save_to_file("monitor_config.toml").await;
+
+        if export_clicked {
+            dialog.result = Some(self.run_export(&dialog).map_err(|e| e.to_string()));
+        }
+        if open {
+            self.export_dialog = Some(dialog);
+        }
+    }
+
+    fn run_sla_report(&self, dialog: &SlaDialog) -> anyhow::Result<String> {
+        let period = Some(dialog.period.trim()).filter(|p| !p.is_empty());
+        let config = self.config.blocking_lock().clone();
+        let reports = self.runtime.block_on(sla::generate(&config, dialog.sla.as_deref(), period))?;
+        let output = sla::render(&reports, dialog.format)?;
+
+        let path = std::path::Path::new(&dialog.path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, output)?;
+        let breached = reports.iter().filter(|r| r.breached).count();
+        Ok(format!("Wrote {} report(s) to {}, {} breached", reports.len(), path.display(), breached))
+    }
+
+    fn draw_sla_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut dialog) = self.sla_dialog.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut generate_clicked = false;
+
+        let slas: Vec<String> = self.config.blocking_lock().slas.iter().map(|sla| sla.name.clone()).collect();
+        egui::Window::new("SLA Report")
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let before = (dialog.sla.clone(), dialog.period.clone(), dialog.format);
+                egui::Grid::new("sla_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("SLA:");
+                    egui::ComboBox::from_id_source("sla_name")
+                        .selected_text(dialog.sla.clone().unwrap_or_else(|| "All SLAs".to_string()))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut dialog.sla, None, "All SLAs");
+                            for sla in &slas {
+                                ui.selectable_value(&mut dialog.sla, Some(sla.clone()), sla);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Period:");
+                    ui.add(egui::TextEdit::singleline(&mut dialog.period).hint_text("YYYY-MM, YYYY-Qn or YYYY"));
+                    ui.end_row();
+
+                    ui.label("Format:");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut dialog.format, ReportFormat::Markdown, "Markdown");
+                        ui.radio_value(&mut dialog.format, ReportFormat::Html, "HTML");
+                        ui.radio_value(&mut dialog.format, ReportFormat::Json, "JSON");
+                    });
+                    ui.end_row();
+
+                    ui.label("File:");
+                    ui.text_edit_singleline(&mut dialog.path);
+                    ui.end_row();
+                });
+                if before != (dialog.sla.clone(), dialog.period.clone(), dialog.format) {
+                    dialog.path = dialog.default_path();
+                }
+                ui.label("Leave the period empty for the last complete period of each SLA.");
+
+                ui.horizontal(|ui| {
+                    generate_clicked = ui.button("Generate").clicked();
+                });
+
+                match &dialog.result {
+                    Some(Ok(message)) => { ui.label(message); }
+                    Some(Err(error)) => { ui.colored_label(egui::Color32::RED, error); }
+                    None => {}
+                }
             });
+
+        if generate_clicked {
+            dialog.result = Some(self.run_sla_report(&dialog).map_err(|e| e.to_string()));
+        }
+        if open {
+            self.sla_dialog = Some(dialog);
         }
     }
 
-    fn draw_status_icon(&self, ui: &mut egui::Ui, is_online: bool, response_time: Option<u64>) {
-        let (color, text) = if is_online {
-            let color = match response_time {
-                Some(ms) if ms < 100 => egui::Color32::GREEN,
-                Some(ms) if ms < 500 => egui::Color32::YELLOW,
-                Some(_) => egui::Color32::from_rgb(255, 165, 0), // Orange
-                None => egui::Color32::GREEN,
-            };
-            (color, "●")
-        } else {
-            (egui::Color32::RED, "●")
+    fn start_sweep(&self, dialog: &mut DiscoveryDialog) -> anyhow::Result<()> {
+        let options = dialog.options()?;
+        let total = discovery::parse_cidr(&dialog.network)?.len();
+        let done = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let network = dialog.network.clone();
+        let progress = Arc::clone(&done);
+        let task = self.runtime.spawn(async move {
+            let _ = sender.send(discovery::sweep(&network, &options, Some(progress)).await);
+        }).abort_handle();
+        dialog.sweep = Some(RunningSweep { done, total, result: receiver, task });
+        dialog.hosts.clear();
+        dialog.result = None;
+        Ok(())
+    }
+
+    fn add_discovered(&mut self, dialog: &DiscoveryDialog) -> anyhow::Result<String> {
+        let group = Some(dialog.group.trim().to_string()).filter(|g| !g.is_empty());
+        let systems: Vec<SystemConfig> = dialog.hosts.iter()
+            .filter(|(_, selected)| *selected)
+            .map(|(host, _)| host.to_system(dialog.protocol.clone(), group.clone()))
+            .collect();
+        let added = self.runtime.block_on(async {
+            let mut manager = self.monitor_manager.write().await;
+            for system in &systems {
+                manager.add_system(system.clone()).await?;
+            }
+            anyhow::Ok(systems.len())
+        })?;
+        self.save_config()?;
+        self.needs_refresh = true;
+        Ok(format!("Added {} system(s)", added))
+    }
+
+    fn draw_discovery_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut dialog) = self.discovery_dialog.take() else {
+            return;
         };
+        let mut open = true;
+        let mut scan_clicked = false;
+        let mut add_clicked = false;
+
+        if let Some(sweep) = &mut dialog.sweep {
+            match sweep.result.try_recv() {
+                Ok(Ok(hosts)) => {
+                    let configured = &self.configured_systems();
+                    dialog.result = Some(Ok(format!("Found {} host(s)", hosts.len())));
+                    dialog.hosts = hosts.into_iter().map(|host| {
+                        let selected = !host.is_monitored(configured);
+                        (host, selected)
+                    }).collect();
+                    dialog.sweep = None;
+                }
+                Ok(Err(e)) => {
+                    dialog.result = Some(Err(e.to_string()));
+                    dialog.sweep = None;
+                }
+                Err(tokio::sync::oneshot::error::TryRecvError::Empty) => {
+                    ctx.request_repaint_after(Duration::from_millis(200));
+                }
+                Err(tokio::sync::oneshot::error::TryRecvError::Closed) => {
+                    dialog.result = Some(Err("The sweep stopped unexpectedly".to_string()));
+                    dialog.sweep = None;
+                }
+            }
+        }
+
+        egui::Window::new("Discover Network")
+            .collapsible(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let running = dialog.sweep.is_some();
+                egui::Grid::new("discovery_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Network:");
+                    ui.add(egui::TextEdit::singleline(&mut dialog.network).hint_text("192.168.1.0/24"));
+                    ui.end_row();
+
+                    ui.label("TCP ports:");
+                    ui.text_edit_singleline(&mut dialog.ports);
+                    ui.end_row();
+
+                    ui.label("Ping:");
+                    ui.checkbox(&mut dialog.icmp, "Send an ICMP echo to every address");
+                    ui.end_row();
+
+                    ui.label("Concurrency:");
+                    ui.add(egui::DragValue::new(&mut dialog.concurrency).clamp_range(1..=1024));
+                    ui.end_row();
+                });
+
+                ui.horizontal(|ui| {
+                    scan_clicked = ui.add_enabled(!running, egui::Button::new("Scan")).clicked();
+                    if let Some(sweep) = &dialog.sweep {
+                        let done = sweep.done.load(Ordering::Relaxed);
+                        ui.add(egui::ProgressBar::new(done as f32 / sweep.total.max(1) as f32)
+                            .text(format!("{} of {}", done, sweep.total)));
+                    }
+                });
+
+                if !dialog.hosts.is_empty() {
+                    ui.separator();
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        egui::Grid::new("discovered_grid").num_columns(5).striped(true).show(ui, |ui| {
+                            for heading in ["", "Address", "Hostname", "Open Ports", "RTT"] {
+                                ui.strong(heading);
+                            }
+                            ui.end_row();
+                            let configured = self.configured_systems();
+                            for (host, selected) in &mut dialog.hosts {
+                                let monitored = host.is_monitored(&configured);
+                                ui.add_enabled(!monitored, egui::Checkbox::without_text(selected))
+                                    .on_disabled_hover_text("Already monitored");
+                                ui.label(host.address.to_string());
+                                ui.label(host.hostname.as_deref().unwrap_or("-"));
+                                let ports: Vec<String> = host.open_ports.iter().map(|p| p.to_string()).collect();
+                                ui.label(if ports.is_empty() { "-".to_string() } else { ports.join(", ") });
+                                ui.label(host.response_time_ms.map_or("-".to_string(), |ms| format!("{:.1}ms", ms)));
+                                ui.end_row();
+                            }
+                        });
+                    });
 
-        ui.colored_label(color, text);
+                    ui.horizontal(|ui| {
+                        ui.label("Group:");
+                        ui.text_edit_singleline(&mut dialog.group);
+                        ui.label("Protocol:");
+                        egui::ComboBox::from_id_source("discovery_protocol")
+                            .selected_text(dialog.protocol.to_string())
+                            .show_ui(ui, |ui| {
+                                for protocol in [Protocol::Ping, Protocol::Tcp, Protocol::Http, Protocol::Https] {
+                                    let label = protocol.to_string();
+                                    ui.selectable_value(&mut dialog.protocol, protocol, label);
+                                }
+                            });
+                    });
+                    let selected = dialog.hosts.iter().filter(|(_, selected)| *selected).count();
+                    add_clicked = ui.add_enabled(selected > 0, egui::Button::new(format!("Add {} Selected", selected))).clicked();
+                }
+
+                match &dialog.result {
+                    Some(Ok(message)) => { ui.label(message); }
+                    Some(Err(error)) => { ui.colored_label(egui::Color32::RED, error); }
+                    None => {}
+                }
+            });
+
+        if scan_clicked {
+            if let Err(e) = self.start_sweep(&mut dialog) {
+                dialog.result = Some(Err(e.to_string()));
+            }
+        }
+        if add_clicked {
+            dialog.result = Some(self.add_discovered(&dialog).map_err(|e| e.to_string()));
+            let configured = self.configured_systems();
+            for (host, selected) in &mut dialog.hosts {
+                *selected = *selected && !host.is_monitored(&configured);
+            }
+        }
+        if open {
+            self.discovery_dialog = Some(dialog);
+        }
+    }
+
+    fn preview_import(&self, dialog: &ImportDialog) -> anyhow::Result<ImportPreview> {
+        let content = std::fs::read_to_string(&dialog.path)?;
+        let manager = self.runtime.block_on(self.monitor_manager.read());
+        inventory::preview(&content, &dialog.options(), &manager.system_configs(), &|system| manager.validate_probe(system, None))
+    }
+
+    // Every row is checked before any is added, so a bad row doesn't leave half an import
+    fn validate_import(&self, preview: &ImportPreview) -> anyhow::Result<()> {
+        let manager = self.runtime.block_on(self.monitor_manager.read());
+        for system in preview.new_systems() {
+            manager.validate_probe(&system, None)
+                .map_err(|e| anyhow::anyhow!("Nothing imported, {} is invalid: {}", system.name, e))?;
+        }
+        Ok(())
+    }
+
+    fn run_import(&mut self, preview: &ImportPreview) -> anyhow::Result<String> {
+        let systems = preview.new_systems();
+        self.runtime.block_on(async {
+            let mut manager = self.monitor_manager.write().await;
+            for system in &systems {
+                manager.add_system(system.clone()).await?;
+            }
+            anyhow::Ok(())
+        })?;
+        self.save_config()?;
+        self.needs_refresh = true;
+        Ok(format!("Imported {} system(s)", systems.len()))
+    }
+
+    fn run_export_systems(&self, dialog: &ImportDialog) -> anyhow::Result<String> {
+        let systems = self.runtime.block_on(async { self.monitor_manager.read().await.system_configs() });
+        let output = inventory::export_systems(&systems, dialog.export_format)?;
+        let path = std::path::Path::new(&dialog.export_path);
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, output)?;
+        Ok(format!("Exported {} systems to {}", systems.len(), path.display()))
+    }
+
+    fn draw_import_dialog(&mut self, ctx: &egui::Context) {
+        let Some(mut dialog) = self.import_dialog.take() else {
+            return;
+        };
+        let mut open = true;
+        let mut preview_clicked = false;
+        let mut import_clicked = false;
+        let mut export_clicked = false;
+
+        egui::Window::new("Import / Export Systems")
+            .collapsible(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let before = dialog.path.clone();
+                egui::Grid::new("import_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("File:");
+                    ui.add(egui::TextEdit::singleline(&mut dialog.path).hint_text("inventory.csv, /etc/hosts, scan.xml"));
+                    ui.end_row();
+
+                    ui.label("Format:");
+                    ui.horizontal(|ui| {
+                        for format in [ImportFormat::Csv, ImportFormat::Json, ImportFormat::Hosts, ImportFormat::Nmap] {
+                            ui.radio_value(&mut dialog.format, format, format.to_string());
+                        }
+                    });
+                    ui.end_row();
+
+                    if matches!(dialog.format, ImportFormat::Csv | ImportFormat::Json) {
+                        let mapping = &mut dialog.mapping;
+                        for (label, column) in [
+                            ("Name column:", &mut mapping.name),
+                            ("Host column:", &mut mapping.host),
+                            ("Port column:", &mut mapping.port),
+                            ("Protocol column:", &mut mapping.protocol),
+                            ("Group column:", &mut mapping.group),
+                            ("Enabled column:", &mut mapping.enabled),
+                        ] {
+                            ui.label(label);
+                            ui.text_edit_singleline(column);
+                            ui.end_row();
+                        }
+                    }
+
+                    ui.label("Default protocol:");
+                    egui::ComboBox::from_id_source("import_protocol")
+                        .selected_text(dialog.protocol.to_string())
+                        .show_ui(ui, |ui| {
+                            for protocol in [Protocol::Ping, Protocol::Tcp, Protocol::Udp, Protocol::Http, Protocol::Https] {
+                                let label = protocol.to_string();
+                                ui.selectable_value(&mut dialog.protocol, protocol, label);
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Default group:");
+                    ui.text_edit_singleline(&mut dialog.group);
+                    ui.end_row();
+                });
+                if before != dialog.path {
+                    dialog.format = ImportFormat::detect(std::path::Path::new(&dialog.path));
+                    dialog.preview = None;
+                }
+
+                ui.horizontal(|ui| {
+                    preview_clicked = ui.button("Preview").clicked();
+                    if let Some(preview) = &dialog.preview {
+                        let (new, duplicates, invalid) = preview.counts();
+                        import_clicked = ui.add_enabled(new > 0, egui::Button::new(format!("Import {} New", new))).clicked();
+                        ui.label(format!("{} duplicate, {} invalid", duplicates, invalid));
+                    }
+                });
+
+                if let Some(preview) = &dialog.preview {
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        egui::Grid::new("import_preview_grid").num_columns(5).striped(true).show(ui, |ui| {
+                            for heading in ["Row", "Status", "Name", "Host", "Protocol"] {
+                                ui.strong(heading);
+                            }
+                            ui.end_row();
+                            for row in &preview.rows {
+                                ui.label(row.row.to_string());
+                                match &row.status {
+                                    RowStatus::New => { ui.colored_label(egui::Color32::GREEN, "new"); }
+                                    RowStatus::Duplicate { of } => {
+                                        ui.colored_label(egui::Color32::YELLOW, "duplicate").on_hover_text(format!("Same as {}", of));
+                                    }
+                                    RowStatus::Invalid { reason } => {
+                                        ui.colored_label(egui::Color32::RED, "invalid").on_hover_text(reason);
+                                    }
+                                }
+                                let system = row.system.as_ref();
+                                ui.label(system.map_or("-", |s| s.name.as_str()));
+                                ui.label(system.map_or("-".to_string(), |s| match s.port {
+                                    Some(port) => format!("{}:{}", s.host, port),
+                                    None => s.host.clone(),
+                                }));
+                                ui.label(system.map_or("-".to_string(), |s| s.protocol.to_string()));
+                                ui.end_row();
+                            }
+                        });
+                    });
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Export to:");
+                    ui.text_edit_singleline(&mut dialog.export_path);
+                    let before = dialog.export_format;
+                    ui.radio_value(&mut dialog.export_format, ExportFormat::Csv, "CSV");
+                    ui.radio_value(&mut dialog.export_format, ExportFormat::Json, "JSON");
+                    if before != dialog.export_format {
+                        dialog.export_path = format!("exports/systems.{}", dialog.export_format.extension());
+                    }
+                    export_clicked = ui.button("Export Systems").clicked();
+                });
+
+                match &dialog.result {
+                    Some(Ok(message)) => { ui.label(message); }
+                    Some(Err(error)) => { ui.colored_label(egui::Color32::RED, error); }
+                    None => {}
+                }
+            });
+
+        if preview_clicked {
+            match self.preview_import(&dialog) {
+                Ok(preview) => {
+                    dialog.preview = Some(preview);
+                    dialog.result = None;
+                }
+                Err(e) => dialog.result = Some(Err(e.to_string())),
+            }
+        }
+        if import_clicked {
+            if let Some(preview) = &dialog.preview {
+                match self.validate_import(preview) {
+                    Ok(()) => {
+                        dialog.result = Some(self.run_import(preview).map_err(|e| e.to_string()));
+                        dialog.preview = None;
+                    }
+                    Err(e) => dialog.result = Some(Err(e.to_string())),
+                }
+            }
+        }
+        if export_clicked {
+            dialog.result = Some(self.run_export_systems(&dialog).map_err(|e| e.to_string()));
+        }
+        if open {
+            self.import_dialog = Some(dialog);
+        }
+    }
+
+    fn draw_status_icon(&self, ui: &mut egui::Ui, system: &SystemStatus) {
+        // Colours follow the system's thresholds, see ThresholdSettings
+        let color = match (system.state, system.degradation.as_ref().map(|d| d.severity)) {
+            (SystemState::Up, _) => egui::Color32::GREEN,
+            (SystemState::Degraded, Some(Severity::Critical)) => egui::Color32::from_rgb(255, 165, 0), // Orange
+            (SystemState::Degraded, _) => egui::Color32::YELLOW,
+            (SystemState::Down, _) => egui::Color32::RED,
+        };
+
+        let response = ui.colored_label(color, "●");
+        if let Some(degradation) = &system.degradation {
+            response.on_hover_text(degradation.reasons.join("\n"));
+        }
     }
 }
 
 impl eframe::App for MonitorApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Auto-refresh every few seconds
-        self.refresh_counter += 1;
-        if self.refresh_counter % 60 == 0 { // Refresh every ~1 second at 60 FPS
-            self.refresh_systems();
+    // Last snapshot before the window closes, the periodic one may be up to a minute old
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let settings = self.config.blocking_lock().state.clone();
+        if !settings.enabled {
+            return;
         }
+        let store = StateStore::new(settings.resolve_path(&self.config_path));
+        let result = self.runtime.block_on(async {
+            let snapshot = self.monitor_manager.read().await.snapshot();
+            store.save(&snapshot).await
+        });
+        match result {
+            Ok(()) => log::info!("Saved monitor state to {}", store.path().display()),
+            Err(e) => log::error!("Failed to save monitor state to {}: {}", store.path().display(), e),
+        }
+    }
 
-        // Request repaint for smooth updates
-        ctx.request_repaint();
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.drain_events() {
+            self.needs_refresh = true;
+        }
+        if self.needs_refresh {
+            if self.refresh_systems() {
+                self.needs_refresh = false;
+            } else {
+                // The manager is busy being modified, try again shortly
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+        }
 
         // Handle system removal outside of the iteration
         if let Some(id_to_remove) = self.system_to_remove.take() {
@@ -142,14 +984,30 @@ impl eframe::App for MonitorApp {
                 }
                 
                 if ui.button("Refresh").clicked() {
-                    self.refresh_systems();
+                    self.needs_refresh = true;
+                }
+
+                let has_slas = !self.config.blocking_lock().slas.is_empty();
+                if ui.add_enabled(has_slas, egui::Button::new("SLA Report…"))
+                    .on_disabled_hover_text("No SLAs are defined in the configuration")
+                    .clicked()
+                {
+                    self.sla_dialog = Some(SlaDialog::new());
+                }
+
+                if ui.button("Discover…").clicked() {
+                    self.discovery_dialog = Some(DiscoveryDialog::new());
+                }
+
+                if ui.button("Import/Export…").clicked() {
+                    self.import_dialog = Some(ImportDialog::new());
                 }
                 
                 if ui.button("Test Ping").clicked() {
                     // Quick test
                     let rt = tokio::runtime::Runtime::new().unwrap();
                     match rt.block_on(tokio::process::Command::new("ping")
-                        .args(&["-c", "1", "8.8.8.8"])
+                        .args(["-c", "1", "8.8.8.8"])
                         .output()) {
                         Ok(output) => {
                             log::info!("Ping test result: success={}", output.status.success());
@@ -163,6 +1021,11 @@ impl eframe::App for MonitorApp {
 
                 ui.separator();
                 ui.label(format!("Monitoring {} systems", self.systems.len()));
+                if let Some(stats) = &self.scheduler {
+                    let p95 = stats.lag.first().and_then(|w| w.p95_ms).unwrap_or(0.0);
+                    ui.label(format!("Scheduling lag p95: {:.0}ms", p95))
+                        .on_hover_text(format!("{} running, {} waiting for a slot", stats.running, stats.waiting));
+                }
             });
 
             ui.separator();
@@ -187,7 +1050,7 @@ impl eframe::App for MonitorApp {
                         // System rows
                         let systems_to_show = self.systems.clone();
                         for system in &systems_to_show {
-                            self.draw_status_icon(ui, system.is_online, system.response_time_ms);
+                            self.draw_status_icon(ui, system);
                             
                             ui.label(&system.config.name);
                             
@@ -200,7 +1063,10 @@ impl eframe::App for MonitorApp {
                             
                             ui.label(format!("{}", system.config.protocol));
                             
-                            if let Some(ms) = system.response_time_ms {
+                            // Local resources, processes and interfaces have no response time worth showing, only their reading
+                            if matches!(system.config.protocol, Protocol::Local | Protocol::Process | Protocol::Interface) {
+                                ui.label(system.output.as_deref().unwrap_or("-"));
+                            } else if let Some(ms) = system.response_time_ms {
                                 ui.label(format!("{}ms", ms));
                             } else {
                                 ui.label("-");
@@ -209,8 +1075,12 @@ impl eframe::App for MonitorApp {
                             ui.label(format!("{:.1}%", system.uptime_percentage));
                             
                             let system_id = system.id;
+                            let included = system.config.origin == Some(SystemOrigin::Included);
                             ui.horizontal(|ui| {
-                                if ui.button("Remove").clicked() {
+                                if ui.add_enabled(!included, egui::Button::new("Remove"))
+                                    .on_disabled_hover_text("Defined in an included file")
+                                    .clicked()
+                                {
                                     self.system_to_remove = Some(system_id);
                                 }
                             });
@@ -225,6 +1095,8 @@ impl eframe::App for MonitorApp {
                 ui.separator();
                 ui.heading("System Details");
                 
+                let mut export_requested = None;
+                let mut trace_requested = None;
                 for system in &self.systems {
                     ui.collapsing(&system.config.name, |ui| {
                         ui.horizontal(|ui| {
@@ -238,6 +1110,13 @@ impl eframe::App for MonitorApp {
                                 ui.label(last_online.format("%Y-%m-%d %H:%M:%S UTC").to_string());
                             });
                         }
+
+                        if system.config.protocol == Protocol::Heartbeat {
+                            ui.horizontal(|ui| {
+                                ui.label("Last Heartbeat:");
+                                ui.label(system.last_heartbeat.map_or("never".to_string(), |t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()));
+                            });
+                        }
                         
                         if let Some(last_offline) = system.last_offline {
                             ui.horizontal(|ui| {
@@ -256,17 +1135,83 @@ impl eframe::App for MonitorApp {
                             ui.label(system.successful_checks.to_string());
                         });
                         
+                        draw_waterfall(ui, &system.timings);
+                        if let Some(latency) = self.latency.get(&system.id) {
+                            draw_latency(ui, system.id, latency);
+                        }
+                        
+                        if let Some(degradation) = &system.degradation {
+                            ui.horizontal(|ui| {
+                                ui.label("Degraded:");
+                                ui.colored_label(egui::Color32::from_rgb(255, 165, 0), degradation.reasons.join(", "));
+                            });
+                        }
+                        
                         if let Some(error) = &system.error_message {
                             ui.horizontal(|ui| {
                                 ui.label("Last Error:");
+                                if let Some(failure) = system.failure {
+                                    ui.colored_label(egui::Color32::RED, format!("[{}]", failure));
+                                }
                                 ui.colored_label(egui::Color32::RED, error);
                             });
                         }
+
+                        if let Some(output) = &system.output {
+                            ui.label("Output:");
+                            ui.monospace(output);
+                        }
+
+                        if system.addresses.len() > 1 || !system.config.address_family.is_auto() {
+                            for address in &system.addresses {
+                                ui.horizontal(|ui| {
+                                    ui.label(format!("{} {}:", resolve::family_label(&address.address), address.address));
+                                    match (&address.error, address.response_time_ms) {
+                                        (None, Some(ms)) => ui.colored_label(egui::Color32::GREEN, format!("online ({}ms)", ms)),
+                                        (None, None) => ui.colored_label(egui::Color32::GREEN, "online"),
+                                        (Some(error), _) => ui.colored_label(egui::Color32::RED, error),
+                                    };
+                                    draw_metadata(ui, &address.metadata);
+                                });
+                            }
+                        } else if let Some(address) = system.addresses.first().filter(|a| !a.metadata.is_empty()) {
+                            ui.horizontal(|ui| draw_metadata(ui, &address.metadata));
+                        }
+
+                        let trace = self.traces.get(&system.id);
+                        ui.horizontal(|ui| {
+                            if ui.button("Export…").clicked() {
+                                export_requested = Some(ExportDialog::new(system));
+                            }
+                            let tracing = trace.is_some_and(|t| t.running);
+                            let label = if tracing { "Tracing…" } else { "Trace Route" };
+                            if ui.add_enabled(!tracing, egui::Button::new(label)).clicked() {
+                                trace_requested = Some(system.id);
+                            }
+                        });
+
+                        if let Some(trace) = trace {
+                            draw_trace(ui, system.id, trace);
+                        }
+
+                        let incidents = self.incidents.get(&system.id).map_or(&[][..], |i| i.as_slice());
+                        draw_timeline(ui, system.id, incidents, trace);
                     });
                 }
+                if export_requested.is_some() {
+                    self.export_dialog = export_requested;
+                }
+                if let Some(id) = trace_requested {
+                    self.start_trace(id);
+                }
             }
         });
 
+        self.draw_export_dialog(ctx);
+        self.draw_sla_dialog(ctx);
+        self.draw_discovery_dialog(ctx);
+        self.draw_import_dialog(ctx);
+
         // Add system dialog
         if self.show_add_dialog {
             egui::Window::new("Add New System")
@@ -290,12 +1235,27 @@ impl eframe::App for MonitorApp {
                                 0 => "PING",
                                 1 => "TCP",
                                 2 => "UDP",
+                                3 => "HTTP",
+                                4 => "HTTPS",
                                 _ => "PING",
                             })
                             .show_ui(ui, |ui| {
                                 ui.selectable_value(&mut self.selected_protocol, 0, "PING");
                                 ui.selectable_value(&mut self.selected_protocol, 1, "TCP");
                                 ui.selectable_value(&mut self.selected_protocol, 2, "UDP");
+                                ui.selectable_value(&mut self.selected_protocol, 3, "HTTP");
+                                ui.selectable_value(&mut self.selected_protocol, 4, "HTTPS");
+                            });
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Address Family:");
+                        egui::ComboBox::from_id_source("address_family")
+                            .selected_text(self.new_system.address_family.to_string())
+                            .show_ui(ui, |ui| {
+                                for family in [AddressFamily::Auto, AddressFamily::V4, AddressFamily::V6, AddressFamily::Both, AddressFamily::PreferV6] {
+                                    ui.selectable_value(&mut self.new_system.address_family, family, family.to_string());
+                                }
                             });
                     });
 
@@ -309,11 +1269,22 @@ impl eframe::App for MonitorApp {
                         });
                     }
 
-                    ui.horizontal(|ui| {
-                        if ui.button("Add").clicked() {
-                            if !self.new_system.name.is_empty() && !self.new_system.host.is_empty() {
-                                self.add_system();
+                    if self.selected_protocol >= 3 {
+                        ui.horizontal(|ui| {
+                            ui.label("Path:");
+                            let mut path = self.new_system.path.clone().unwrap_or_default();
+                            if ui.text_edit_singleline(&mut path).changed() {
+                                self.new_system.path = (!path.is_empty()).then_some(path);
                             }
+                        });
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Add").clicked()
+                            && !self.new_system.name.is_empty()
+                            && !self.new_system.host.is_empty()
+                        {
+                            self.add_system();
                         }
                         
                         if ui.button("Cancel").clicked() {
@@ -323,4 +1294,251 @@ impl eframe::App for MonitorApp {
                 });
         }
     }
-}
\ No newline at end of file
+}
+
+fn draw_trace(ui: &mut egui::Ui, id: Uuid, state: &TraceState) {
+    let Some(latest) = &state.latest else {
+        return;
+    };
+    // Only compare against an earlier trace, a good latest trace is its own baseline
+    let baseline = state.last_good.as_ref().filter(|good| good.started_at != latest.started_at);
+    let divergence = baseline.and_then(|good| latest.divergence_from(good));
+
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.strong(format!("Path to {} ({})", latest.target, latest.method));
+        ui.label(latest.started_at.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+        if latest.reached {
+            ui.colored_label(egui::Color32::GREEN, "reached");
+        } else {
+            ui.colored_label(egui::Color32::RED, "not reached");
+        }
+    });
+    if let Some(error) = &latest.error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+    if let (Some(ttl), Some(good)) = (divergence, baseline) {
+        ui.colored_label(
+            egui::Color32::from_rgb(255, 165, 0),
+            format!("Path diverges at hop {} from the last known-good trace ({})",
+                ttl, good.started_at.format("%Y-%m-%d %H:%M:%S UTC")),
+        );
+    }
+
+    let format_addresses = |addresses: &[std::net::IpAddr]| {
+        if addresses.is_empty() {
+            "*".to_string()
+        } else {
+            addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
+        }
+    };
+
+    egui::Grid::new(("trace_grid", id))
+        .num_columns(if baseline.is_some() { 5 } else { 4 })
+        .spacing([10.0, 4.0])
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Hop");
+            ui.strong("Address");
+            ui.strong("Loss");
+            ui.strong("Avg RTT");
+            if baseline.is_some() {
+                ui.strong("Last Known-Good");
+            }
+            ui.end_row();
+
+            for hop in &latest.hops {
+                let diverged = divergence.is_some_and(|ttl| hop.ttl >= ttl);
+                let color = if diverged {
+                    egui::Color32::from_rgb(255, 165, 0)
+                } else {
+                    ui.visuals().text_color()
+                };
+                ui.colored_label(color, hop.ttl.to_string());
+                ui.colored_label(color, format_addresses(&hop.addresses));
+                ui.colored_label(color, format!("{:.0}%", hop.loss_percent()));
+                ui.colored_label(color, hop.avg_rtt_ms().map_or("-".to_string(), |ms| format!("{:.1}ms", ms)));
+                if let Some(good) = baseline {
+                    let previous = good.hops.iter().find(|h| h.ttl == hop.ttl);
+                    ui.label(previous.map_or("-".to_string(), |h| format_addresses(&h.addresses)));
+                }
+                ui.end_row();
+            }
+        });
+}
+
+enum TimelineEntry<'a> {
+    OutageStarted(&'a Incident),
+    OutageEnded(&'a Incident),
+    RouteChanged { old_hops: &'a PathHops, new_hops: &'a PathHops },
+}
+
+const MAX_TIMELINE_ENTRIES: usize = 20;
+
+// Outages and route changes merged into one list, newest first
+fn draw_timeline(ui: &mut egui::Ui, id: Uuid, incidents: &[Incident], trace: Option<&TraceState>) {
+    let mut entries: Vec<(chrono::DateTime<Utc>, TimelineEntry)> = Vec::new();
+    for incident in incidents {
+        entries.push((incident.started_at, TimelineEntry::OutageStarted(incident)));
+        if let Some(ended_at) = incident.ended_at {
+            entries.push((ended_at, TimelineEntry::OutageEnded(incident)));
+        }
+    }
+    for change in trace.iter().flat_map(|t| t.route_changes.iter()) {
+        entries.push((change.timestamp, TimelineEntry::RouteChanged {
+            old_hops: &change.old_hops,
+            new_hops: &change.new_hops,
+        }));
+    }
+    if entries.is_empty() {
+        return;
+    }
+    entries.sort_by_key(|(timestamp, _)| std::cmp::Reverse(*timestamp));
+
+    let format_path = |hops: &PathHops| {
+        hops.iter()
+            .map(|addresses| match addresses.as_slice() {
+                [] => "*".to_string(),
+                [single] => single.to_string(),
+                many => format!("{{{}}}", many.iter().map(|a| a.to_string()).collect::<Vec<_>>().join("|")),
+            })
+            .collect::<Vec<_>>()
+            .join(" → ")
+    };
+
+    ui.separator();
+    ui.strong("Timeline");
+    egui::Grid::new(("timeline_grid", id))
+        .num_columns(2)
+        .spacing([10.0, 4.0])
+        .show(ui, |ui| {
+            for (timestamp, entry) in entries.iter().take(MAX_TIMELINE_ENTRIES) {
+                ui.label(timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+                match entry {
+                    TimelineEntry::OutageStarted(incident) if incident.kind == IncidentKind::Degraded => {
+                        ui.colored_label(egui::Color32::YELLOW, "Degraded");
+                    }
+                    TimelineEntry::OutageStarted(incident) => {
+                        let error = incident.first_error.as_deref().unwrap_or("unknown error");
+                        ui.colored_label(egui::Color32::RED, format!("Outage started: {}", error));
+                    }
+                    TimelineEntry::OutageEnded(incident) => {
+                        let what = match incident.kind {
+                            IncidentKind::Outage => "Outage ended",
+                            IncidentKind::Degraded => "Recovered from degradation",
+                        };
+                        ui.colored_label(egui::Color32::GREEN,
+                            format!("{} after {}s", what, incident.duration_seconds()));
+                    }
+                    TimelineEntry::RouteChanged { old_hops, new_hops } => {
+                        ui.colored_label(egui::Color32::from_rgb(255, 165, 0), "Route changed")
+                            .on_hover_text(format!("Old: {}\nNew: {}", format_path(old_hops), format_path(new_hops)));
+                    }
+                }
+                ui.end_row();
+            }
+        });
+}
+
+// Extra details a probe reported, e.g. the TTL of a ping reply
+fn draw_metadata(ui: &mut egui::Ui, metadata: &BTreeMap<String, String>) {
+    for (key, value) in metadata {
+        ui.weak(format!("{}: {}", key, value));
+    }
+}
+
+// Stacked bar of the check phases, in the order they happen
+fn draw_waterfall(ui: &mut egui::Ui, timings: &CheckTimings) {
+    let phases = timings.phases();
+    if phases.is_empty() {
+        return;
+    }
+    let total: f64 = phases.iter().map(|(_, ms)| ms).sum();
+    let color = |label: &str| match label {
+        "DNS" => egui::Color32::from_rgb(156, 110, 217),
+        "Connect" => egui::Color32::from_rgb(66, 150, 230),
+        "TLS" => egui::Color32::from_rgb(230, 160, 40),
+        _ => egui::Color32::from_rgb(70, 180, 110),
+    };
+
+    ui.horizontal(|ui| {
+        ui.label("Timings:");
+        let width = ui.available_width().min(400.0);
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(width, 14.0), egui::Sense::hover());
+        let mut x = rect.left();
+        for (label, ms) in &phases {
+            let share = if total > 0.0 { (ms / total) as f32 } else { 1.0 / phases.len() as f32 };
+            let segment = egui::Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2((share * width).max(1.0), rect.height()));
+            ui.painter().rect_filled(segment, 0.0, color(label));
+            x += share * width;
+        }
+        if let Some(total_ms) = timings.total_ms {
+            response.on_hover_text(format!("Total {:.1}ms", total_ms));
+        }
+    });
+    ui.horizontal(|ui| {
+        for (label, ms) in &phases {
+            ui.colored_label(color(label), format!("■ {} {:.1}ms", label, ms));
+        }
+    });
+}
+
+// Percentiles per window and a histogram of the last 24 hours
+fn draw_latency(ui: &mut egui::Ui, id: Uuid, report: &LatencyReport) {
+    if report.windows.iter().all(|w| w.count == 0) {
+        return;
+    }
+    let format_ms = |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{:.1}ms", ms));
+
+    egui::Grid::new(("latency_grid", id))
+        .num_columns(6)
+        .spacing([10.0, 4.0])
+        .striped(true)
+        .show(ui, |ui| {
+            for heading in ["Window", "Samples", "p50", "p90", "p95", "p99"] {
+                ui.strong(heading);
+            }
+            ui.end_row();
+            for window in &report.windows {
+                ui.label(&window.window);
+                ui.label(window.count.to_string());
+                ui.label(format_ms(window.p50_ms));
+                ui.label(format_ms(window.p90_ms));
+                ui.label(format_ms(window.p95_ms));
+                ui.label(format_ms(window.p99_ms));
+                ui.end_row();
+            }
+        });
+
+    // Buckets are cumulative, the bars show what falls between neighbouring bounds
+    let mut previous = 0;
+    let counts: Vec<(f64, u64)> = report.histogram.iter()
+        .map(|bucket| {
+            let count = bucket.count - previous;
+            previous = bucket.count;
+            (bucket.le_ms, count)
+        })
+        .collect();
+    let highest = counts.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+
+    ui.horizontal(|ui| {
+        ui.label("Histogram (24h):");
+        let bar_width = 18.0;
+        let height = 40.0;
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(bar_width * counts.len() as f32, height), egui::Sense::hover());
+        for (i, (le_ms, count)) in counts.iter().enumerate() {
+            let bar_height = height * (*count as f32 / highest as f32);
+            let bar = egui::Rect::from_min_max(
+                egui::pos2(rect.left() + i as f32 * bar_width + 1.0, rect.bottom() - bar_height),
+                egui::pos2(rect.left() + (i + 1) as f32 * bar_width - 1.0, rect.bottom()),
+            );
+            ui.painter().rect_filled(bar, 0.0, egui::Color32::from_rgb(66, 150, 230));
+            let cell = egui::Rect::from_min_max(
+                egui::pos2(rect.left() + i as f32 * bar_width, rect.top()),
+                egui::pos2(rect.left() + (i + 1) as f32 * bar_width, rect.bottom()),
+            );
+            ui.interact(cell, ui.id().with(("latency_bucket", id, i)), egui::Sense::hover())
+                .on_hover_text(format!("≤ {}ms: {}", le_ms, count));
+        }
+    });
+}